@@ -0,0 +1,17 @@
+\ Counts the primes below 1000 by trial division.
+variable count
+
+: prime? ( n -- flag )
+    dup 2 < if drop 0 exit then
+    2 begin
+        over over dup * < if drop drop -1 exit then
+        over over mod 0= if drop drop 0 exit then
+        1+
+    again ;
+
+0 count !
+2 begin
+    dup prime? if 1 count +! then
+    1+ dup 1000 =
+until
+drop count @
@@ -0,0 +1,442 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    error::VmError,
+    instruction::Instruction,
+    opcode::Opcode,
+    value::Value,
+    virtual_machine::{MAX_CALL_DEPTH, MEMORY_LIMIT},
+};
+
+/// Functions that every generated program uses. The error messages and the memory are defined before them.
+const RUNTIME: &str = r#"
+static int64_t *stack;
+static size_t depth, capacity;
+int64_t registers[256];
+static size_t *calls;
+static size_t call_depth, call_capacity;
+struct handler { size_t target, depth, call_depth; };
+static struct handler *handlers;
+static size_t handler_depth, handler_capacity;
+static jmp_buf catch_point;
+static int64_t thrown;
+int64_t a, b;
+
+static inline void fail(const char *message) {
+    fprintf(stderr, "%s\n", message);
+    exit(1);
+}
+
+static inline void push_handler(size_t target) {
+    if (handler_depth == handler_capacity) {
+        handler_capacity = handler_capacity ? handler_capacity * 2 : 16;
+        handlers = realloc(handlers, handler_capacity * sizeof *handlers);
+        if (!handlers) abort();
+    }
+    handlers[handler_depth++] = (struct handler){target, depth, call_depth};
+}
+
+static inline void pop_handler(void) {
+    if (handler_depth == 0) fail(NO_HANDLER_IN_HANDLER_STACK);
+    handler_depth--;
+}
+
+static inline void throw_error(int64_t code, const char *message) {
+    if (handler_depth == 0) fail(message);
+    thrown = code;
+    longjmp(catch_point, 1);
+}
+
+static inline void throw_value(int64_t value, size_t address) {
+    if (handler_depth == 0) {
+        fprintf(stderr, "%s%" PRId64 "%s%zu%s\n", UNCAUGHT_THROW_BEFORE, value, UNCAUGHT_THROW_BETWEEN, address, UNCAUGHT_THROW_AFTER);
+        exit(1);
+    }
+    thrown = value;
+    longjmp(catch_point, 1);
+}
+
+static inline void push(int64_t value) {
+    if (depth == capacity) {
+        capacity = capacity ? capacity * 2 : 256;
+        stack = realloc(stack, capacity * sizeof *stack);
+        if (!stack) abort();
+    }
+    stack[depth++] = value;
+}
+
+static inline size_t unwind(void) {
+    struct handler handler = handlers[--handler_depth];
+    if (depth > handler.depth) depth = handler.depth;
+    if (call_depth > handler.call_depth) call_depth = handler.call_depth;
+    push(thrown);
+    return handler.target;
+}
+
+static inline int64_t pop(void) {
+    if (depth == 0) throw_error(NO_VALUE_IN_STACK_CODE, NO_VALUE_IN_STACK);
+    return stack[--depth];
+}
+
+static inline void require(size_t required, const char *message) {
+    if (depth < required) {
+        fprintf(stderr, "%s%zu\n", message, depth);
+        exit(1);
+    }
+}
+
+static inline void push_call(size_t index) {
+    if (call_depth == MAX_CALL_DEPTH) fail(CALL_STACK_OVERFLOW);
+    if (call_depth == call_capacity) {
+        call_capacity = call_capacity ? call_capacity * 2 : 256;
+        calls = realloc(calls, call_capacity * sizeof *calls);
+        if (!calls) abort();
+    }
+    calls[call_depth++] = index;
+}
+
+static inline size_t pop_call(void) {
+    if (call_depth == 0) fail(NO_ADDRESS_IN_CALL_STACK);
+    return calls[--call_depth];
+}
+
+static inline int64_t divide(int64_t left, int64_t right) {
+    if (right == 0) throw_error(DIVISION_BY_ZERO_CODE, DIVISION_BY_ZERO);
+    if (right == -1) return (int64_t)(0 - (uint64_t)left);
+    return left / right;
+}
+
+static inline int64_t remainder_of(int64_t left, int64_t right) {
+    if (right == 0) throw_error(DIVISION_BY_ZERO_CODE, DIVISION_BY_ZERO);
+    if (right == -1) return 0;
+    return left % right;
+}
+
+static inline unsigned shift_amount(int64_t amount) {
+    if (amount < 0 || amount > 63) {
+        fprintf(stderr, "%s%" PRId64 "%s\n", INVALID_SHIFT_AMOUNT_BEFORE, amount, INVALID_SHIFT_AMOUNT_AFTER);
+        exit(1);
+    }
+    return (unsigned)amount;
+}
+
+static inline uint8_t *address(int64_t addr, size_t length) {
+    if (addr < 0 || (uint64_t)addr > memory_size || length > memory_size - (size_t)addr) {
+        fprintf(stderr, "%s%zu%s%" PRId64 "%s\n", MEMORY_OUT_OF_BOUNDS_BEFORE, length, MEMORY_OUT_OF_BOUNDS_BETWEEN, addr, MEMORY_OUT_OF_BOUNDS_AFTER);
+        exit(1);
+    }
+    return memory + addr;
+}
+
+static inline int64_t load64(int64_t addr) {
+    uint8_t *bytes = address(addr, 8);
+    uint64_t value = 0;
+    for (int i = 7; i >= 0; i--) value = value << 8 | bytes[i];
+    return (int64_t)value;
+}
+
+static inline void store64(int64_t addr, int64_t value) {
+    uint8_t *bytes = address(addr, 8);
+    for (int i = 0; i < 8; i++) bytes[i] = (uint8_t)((uint64_t)value >> (8 * i));
+}
+
+static inline int64_t grow_memory(int64_t amount) {
+    if (amount < 0 || (uint64_t)amount > MEMORY_LIMIT - memory_size) return -1;
+    size_t size = memory_size;
+    memory_size += (size_t)amount;
+    return (int64_t)size;
+}
+
+static inline int64_t rotate_left(int64_t value, unsigned amount) {
+    uint64_t bits = (uint64_t)value;
+    return (int64_t)((bits << amount) | (bits >> ((64 - amount) & 63)));
+}
+
+static inline int64_t rotate_right(int64_t value, unsigned amount) {
+    uint64_t bits = (uint64_t)value;
+    return (int64_t)((bits >> amount) | (bits << ((64 - amount) & 63)));
+}
+
+static inline int64_t popcount(int64_t value) {
+    uint64_t bits = (uint64_t)value;
+    int64_t count = 0;
+    for (; bits; bits &= bits - 1) count++;
+    return count;
+}
+
+static inline void print_result(void) {
+    if (depth == 0) {
+        printf("PROGRAM RESULT: []\n");
+        return;
+    }
+    printf("PROGRAM RESULT: [\n");
+    for (size_t i = 0; i < depth; i++) printf("    %" PRId64 ",\n", stack[i]);
+    printf("]\n");
+}
+"#;
+
+/// Generates a self-contained C program from instructions decoded from current-format bytecode without fibers or channels.
+/// Jumps are `goto`s, and `RETURN` jumps through a `switch` over the instructions after each `CALL`.
+/// Thrown values `longjmp` back to `main`, which jumps to the handler through a `switch` over the `TRY` targets.
+/// The memory is a static array that starts with `data`.
+/// The program prints its result like the `run` command, or prints a runtime error and exits with status 1.
+pub fn generate(instructions: &[Instruction], data: &[u8]) -> String {
+    let mut targets: BTreeSet<usize> = instructions
+        .iter()
+        .filter_map(|instruction| instruction.target())
+        .collect();
+    let return_sites: Vec<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| matches!(instruction, Instruction::CALL(_)))
+        .map(|(index, _)| index + 1)
+        .collect();
+    targets.extend(&return_sites);
+    let handler_targets: BTreeSet<usize> = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::TRY(target) => Some(*target),
+            _ => None,
+        })
+        .collect();
+
+    let mut text = String::from(
+        "#include <inttypes.h>\n#include <setjmp.h>\n#include <stdint.h>\n#include <stdio.h>\n#include <stdlib.h>\n\n",
+    );
+
+    // The amount is printed at runtime between the two halves of the message.
+    let invalid_shift_amount = VmError::InvalidShiftAmount(0).to_string();
+    let (before, after) = invalid_shift_amount
+        .split_once('0')
+        .unwrap_or((&invalid_shift_amount, ""));
+
+    // The length and the address are printed at runtime between the three parts of the message.
+    let memory_out_of_bounds = VmError::MemoryOutOfBounds { addr: 0, len: 0 }.to_string();
+    let mut parts = memory_out_of_bounds.splitn(3, '0');
+    let (first, second, third) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+
+    // The value and the address are printed at runtime between the three parts of the message.
+    let uncaught_throw = VmError::UncaughtThrow {
+        value: 0,
+        address: 0,
+    }
+    .to_string();
+    let mut parts = uncaught_throw.splitn(3, '0');
+    let (throw_first, throw_second, throw_third) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+
+    let messages = [
+        ("NO_VALUE_IN_STACK", VmError::NoValueInStack.to_string()),
+        ("DIVISION_BY_ZERO", VmError::DivisionByZero.to_string()),
+        (
+            "NO_ADDRESS_IN_CALL_STACK",
+            VmError::NoAddressInCallStack.to_string(),
+        ),
+        (
+            "CALL_STACK_OVERFLOW",
+            VmError::CallStackOverflow.to_string(),
+        ),
+        (
+            "RET_OPCODE_NOT_FOUND",
+            VmError::RetOpcodeNotFound.to_string(),
+        ),
+        ("INVALID_SHIFT_AMOUNT_BEFORE", before.to_string()),
+        ("INVALID_SHIFT_AMOUNT_AFTER", after.to_string()),
+        ("MEMORY_OUT_OF_BOUNDS_BEFORE", first.to_string()),
+        ("MEMORY_OUT_OF_BOUNDS_BETWEEN", second.to_string()),
+        ("MEMORY_OUT_OF_BOUNDS_AFTER", third.to_string()),
+        (
+            "NO_HANDLER_IN_HANDLER_STACK",
+            VmError::NoHandlerInHandlerStack.to_string(),
+        ),
+        ("UNCAUGHT_THROW_BEFORE", throw_first.to_string()),
+        ("UNCAUGHT_THROW_BETWEEN", throw_second.to_string()),
+        ("UNCAUGHT_THROW_AFTER", throw_third.to_string()),
+    ];
+
+    for (name, message) in messages {
+        text.push_str(&format!(
+            "static const char {name}[] = {};\n",
+            string_literal(&message)
+        ));
+    }
+
+    // Handlers receive these values for the runtime errors they catch.
+    for (name, error) in [
+        ("NO_VALUE_IN_STACK_CODE", VmError::NoValueInStack),
+        ("DIVISION_BY_ZERO_CODE", VmError::DivisionByZero),
+    ] {
+        let code = error.code().unwrap_or_default();
+        text.push_str(&format!("#define {name} INT64_C({code})\n"));
+    }
+
+    text.push_str(&format!("\n#define MEMORY_LIMIT {MEMORY_LIMIT}\n"));
+    text.push_str(&format!("#define MAX_CALL_DEPTH {MAX_CALL_DEPTH}\n"));
+    text.push_str("static uint8_t memory[MEMORY_LIMIT]");
+    if !data.is_empty() {
+        let bytes: Vec<String> = data.iter().map(|byte| byte.to_string()).collect();
+        text.push_str(&format!(" = {{{}}}", bytes.join(", ")));
+    }
+    text.push_str(&format!(";\nstatic size_t memory_size = {};\n", data.len()));
+
+    text.push_str(RUNTIME);
+    text.push_str("\nint main(void) {\n");
+
+    if !handler_targets.is_empty() {
+        text.push_str("    if (setjmp(catch_point)) goto catch;\n");
+    }
+
+    for (index, &instruction) in instructions.iter().enumerate() {
+        if targets.contains(&index) {
+            text.push_str(&format!("L{index}:\n"));
+        }
+
+        text.push_str(&format!("    {}\n", statement(index, instruction)));
+    }
+
+    if targets.contains(&instructions.len()) {
+        text.push_str(&format!("L{}:\n", instructions.len()));
+    }
+
+    text.push_str("    fail(RET_OPCODE_NOT_FOUND);\n");
+
+    if instructions.contains(&Instruction::RETURN) {
+        text.push_str("dispatch:\n    switch (pop_call()) {\n");
+
+        for index in return_sites {
+            text.push_str(&format!("    case {index}: goto L{index};\n"));
+        }
+
+        text.push_str("    }\n");
+    }
+
+    if !handler_targets.is_empty() {
+        text.push_str("catch:\n    switch (unwind()) {\n");
+
+        for index in handler_targets {
+            text.push_str(&format!("    case {index}: goto L{index};\n"));
+        }
+
+        text.push_str("    }\n");
+    }
+
+    text.push_str("    return 0;\n}\n");
+    text
+}
+
+/// Returns the C statement that executes an instruction.
+fn statement(index: usize, instruction: Instruction) -> String {
+    match instruction {
+        Instruction::PUSH(value) => format!("push({});", integer_literal(value)),
+        Instruction::POP => "pop();".to_string(),
+        Instruction::STORE(register) => format!("registers[{register}] = pop();"),
+        Instruction::LOAD(register) => format!("push(registers[{register}]);"),
+        Instruction::ADD => binary("(int64_t)((uint64_t)a + (uint64_t)b)"),
+        Instruction::SUB => binary("(int64_t)((uint64_t)a - (uint64_t)b)"),
+        Instruction::MUL => binary("(int64_t)((uint64_t)a * (uint64_t)b)"),
+        Instruction::DIV => binary("divide(a, b)"),
+        Instruction::MOD => binary("remainder_of(a, b)"),
+        Instruction::RET => "print_result(); return 0;".to_string(),
+        Instruction::DUP => format!("{} push(stack[depth - 1]);", require(Opcode::DUP, 1)),
+        Instruction::SWAP => format!(
+            "{} a = stack[depth - 2]; stack[depth - 2] = stack[depth - 1]; stack[depth - 1] = a;",
+            require(Opcode::SWAP, 2)
+        ),
+        Instruction::OVER => format!("{} push(stack[depth - 2]);", require(Opcode::OVER, 2)),
+        Instruction::ROT => format!(
+            "{} a = stack[depth - 3]; stack[depth - 3] = stack[depth - 2]; stack[depth - 2] = stack[depth - 1]; stack[depth - 1] = a;",
+            require(Opcode::ROT, 3)
+        ),
+        Instruction::NIP => format!("{} b = pop(); stack[depth - 1] = b;", require(Opcode::NIP, 2)),
+        Instruction::TUCK => format!(
+            "{} b = stack[depth - 1]; stack[depth - 1] = stack[depth - 2]; stack[depth - 2] = b; push(b);",
+            require(Opcode::TUCK, 2)
+        ),
+        Instruction::PICK(depth) => format!(
+            "{} push(stack[depth - {}]);",
+            require(Opcode::PICK, depth as usize + 1),
+            depth as usize + 1
+        ),
+        Instruction::DEPTH => "push((int64_t)depth);".to_string(),
+        Instruction::BAND => binary("a & b"),
+        Instruction::BOR => binary("a | b"),
+        Instruction::BXOR => binary("a ^ b"),
+        Instruction::BNOT => "push(~pop());".to_string(),
+        Instruction::SHL => binary("(int64_t)((uint64_t)a << shift_amount(b))"),
+        Instruction::SHR => binary("a >> shift_amount(b)"),
+        Instruction::USHR => binary("(int64_t)((uint64_t)a >> shift_amount(b))"),
+        Instruction::ROTL => binary("rotate_left(a, shift_amount(b))"),
+        Instruction::ROTR => binary("rotate_right(a, shift_amount(b))"),
+        Instruction::POPCNT => "push(popcount(pop()));".to_string(),
+        Instruction::EQ => binary("a == b"),
+        Instruction::NE => binary("a != b"),
+        Instruction::LT => binary("a < b"),
+        Instruction::LE => binary("a <= b"),
+        Instruction::GT => binary("a > b"),
+        Instruction::GE => binary("a >= b"),
+        Instruction::PUT(depth) => format!(
+            "{} b = pop(); stack[depth - {}] = b;",
+            require(Opcode::PUT, depth as usize + 2),
+            depth as usize + 1
+        ),
+        Instruction::LOAD8 => "push(*address(pop(), 1));".to_string(),
+        Instruction::LOAD64 => "push(load64(pop()));".to_string(),
+        Instruction::STORE8 => "b = pop(); a = pop(); *address(a, 1) = (uint8_t)b;".to_string(),
+        Instruction::STORE64 => "b = pop(); a = pop(); store64(a, b);".to_string(),
+        Instruction::MEMSIZE => "push((int64_t)memory_size);".to_string(),
+        Instruction::MEMGROW => "push(grow_memory(pop()));".to_string(),
+        Instruction::JMP(target) => format!("goto L{target};"),
+        Instruction::JZ(target) => format!("if (pop() == 0) goto L{target};"),
+        Instruction::JNZ(target) => format!("if (pop() != 0) goto L{target};"),
+        Instruction::CALL(target) => format!("push_call({}); goto L{target};", index + 1),
+        Instruction::RETURN => "goto dispatch;".to_string(),
+        Instruction::TRY(target) => format!("push_handler({target});"),
+        Instruction::THROW(address) => format!("throw_value(pop(), {address});"),
+        Instruction::ENDTRY => "pop_handler();".to_string(),
+        Instruction::SPAWN(_)
+        | Instruction::YIELD
+        | Instruction::RESUME
+        | Instruction::FIBERSTATUS
+        | Instruction::SEND
+        | Instruction::RECV => unreachable!("fibers and channels are rejected by `backend::generate`"),
+    }
+}
+
+/// Pops the right operand into `b` and the left operand into `a`, then pushes `result`.
+/// The shift amount is checked after both operands are popped, like the virtual machine does.
+fn binary(result: &str) -> String {
+    format!("b = pop(); a = pop(); push({result});")
+}
+
+/// Reports `VmError::StackUnderflow` if the stack holds fewer than `required` values for `opcode`.
+fn require(opcode: Opcode, required: usize) -> String {
+    let error = VmError::StackUnderflow {
+        opcode,
+        required,
+        found: 0,
+    };
+    // The number of values found is printed at runtime.
+    let message = error.to_string();
+    let message = message.strip_suffix('0').unwrap_or(&message);
+
+    format!("require({required}, {});", string_literal(message))
+}
+
+/// `INT64_C(-9223372036854775808)` negates a literal that is too big, so the minimum has its own macro.
+fn integer_literal(value: Value) -> String {
+    match value {
+        Value::MIN => "INT64_MIN".to_string(),
+        _ => format!("INT64_C({value})"),
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
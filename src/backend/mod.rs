@@ -0,0 +1,127 @@
+pub mod c;
+pub mod wasm;
+pub mod x86_64;
+
+use crate::{
+    error::BackendError, instruction::Instruction, opcode::Opcode, virtual_machine::VirtualMachine,
+};
+
+/// The number of handlers a program can hold for nested `TRY`s in backends with a fixed handler stack.
+pub const HANDLER_STACK_SIZE: usize = 4096;
+
+/// A kind of file that the `compile` command can create.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Bytecode,
+    X86_64Asm,
+    C,
+    Wasm,
+    Wat,
+}
+
+impl Target {
+    /// Finds a target by the name given to `--target`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bytecode" => Some(Self::Bytecode),
+            "x86_64-asm" => Some(Self::X86_64Asm),
+            "c" => Some(Self::C),
+            "wasm" => Some(Self::Wasm),
+            "wat" => Some(Self::Wat),
+            _ => None,
+        }
+    }
+
+    /// Returns the name that `--target` takes for the target.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bytecode => "bytecode",
+            Self::X86_64Asm => "x86_64-asm",
+            Self::C => "c",
+            Self::Wasm => "wasm",
+            Self::Wat => "wat",
+        }
+    }
+
+    /// Returns the extension of the files created for the target.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Bytecode => "bin",
+            Self::X86_64Asm => "s",
+            Self::C => "c",
+            Self::Wasm => "wasm",
+            Self::Wat => "wat",
+        }
+    }
+}
+
+/// Returns the opcode of an instruction that only the virtual machine can run.
+fn unsupported_opcode(instruction: Instruction) -> Option<Opcode> {
+    match instruction {
+        Instruction::SPAWN(_) => Some(Opcode::SPAWN),
+        Instruction::YIELD => Some(Opcode::YIELD),
+        Instruction::RESUME => Some(Opcode::RESUME),
+        Instruction::FIBERSTATUS => Some(Opcode::FIBERSTATUS),
+        Instruction::SEND => Some(Opcode::SEND),
+        Instruction::RECV => Some(Opcode::RECV),
+        _ => None,
+    }
+}
+
+/// Creates the file contents for a target from compiled bytecode.
+/// Programs with fibers or channels can only be compiled to bytecode.
+pub fn generate(target: Target, bytecode: Vec<u8>) -> Result<Vec<u8>, BackendError> {
+    let generate: fn(&[Instruction], &[u8]) -> Vec<u8> = match target {
+        Target::Bytecode => return Ok(bytecode),
+        Target::X86_64Asm => |instructions, data| x86_64::generate(instructions, data).into_bytes(),
+        Target::C => |instructions, data| c::generate(instructions, data).into_bytes(),
+        Target::Wasm => |instructions, data| wasm::generate(instructions, data).encode(),
+        Target::Wat => {
+            |instructions, data| wasm::generate(instructions, data).to_string().into_bytes()
+        }
+    };
+
+    // Decoding fills the memory with the data of the bytecode.
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    let instructions = virtual_machine
+        .decode()
+        .map_err(BackendError::InvalidBytecode)?
+        .to_vec();
+
+    if let Some(opcode) = instructions.iter().find_map(|&i| unsupported_opcode(i)) {
+        return Err(BackendError::UnsupportedOpcode {
+            opcode,
+            target: target.name(),
+        });
+    }
+
+    Ok(generate(&instructions, virtual_machine.memory()))
+}
+
+#[test]
+fn test_generating_fibers() {
+    let bytecode =
+        crate::virtual_machine::compile_assembly("SPAWN f PUSH 0 SWAP RESUME RET f: RET");
+
+    assert_eq!(
+        generate(Target::Bytecode, bytecode.clone()).unwrap(),
+        bytecode
+    );
+
+    for target in [Target::X86_64Asm, Target::C, Target::Wasm, Target::Wat] {
+        assert!(matches!(
+            generate(target, bytecode.clone()),
+            Err(BackendError::UnsupportedOpcode { opcode: Opcode::SPAWN, target: name }) if name == target.name()
+        ));
+    }
+
+    let bytecode = crate::virtual_machine::compile_assembly("RECV RET");
+
+    assert!(matches!(
+        generate(Target::C, bytecode),
+        Err(BackendError::UnsupportedOpcode {
+            opcode: Opcode::RECV,
+            ..
+        })
+    ));
+}
@@ -0,0 +1,1440 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::{
+    error::VmError,
+    instruction::Instruction,
+    value::Value,
+    virtual_machine::{MAX_CALL_DEPTH, MEMORY_LIMIT},
+};
+
+use super::HANDLER_STACK_SIZE;
+
+/// Status codes returned by the exported `run` function.
+pub const SUCCESS: i32 = 0;
+pub const RET_OPCODE_NOT_FOUND: i32 = 1;
+pub const NO_VALUE_IN_STACK: i32 = 2;
+pub const STACK_UNDERFLOW: i32 = 3;
+pub const INVALID_SHIFT_AMOUNT: i32 = 4;
+pub const DIVISION_BY_ZERO: i32 = 5;
+pub const NO_ADDRESS_IN_CALL_STACK: i32 = 6;
+pub const CALL_STACK_FULL: i32 = 7;
+pub const MEMORY_OUT_OF_BOUNDS: i32 = 8;
+pub const UNCAUGHT_THROW: i32 = 9;
+pub const NO_HANDLER_IN_HANDLER_STACK: i32 = 10;
+pub const HANDLER_STACK_FULL: i32 = 11;
+
+/// The registers are at the start of the linear memory, followed by the call stack, the handler stack,
+/// the memory of the program and the stack.
+pub const CALL_STACK_START: i32 = 256 * 8;
+pub const HANDLER_STACK_START: i32 = CALL_STACK_START + MAX_CALL_DEPTH as i32 * 4;
+pub const MEMORY_START: i32 = HANDLER_STACK_START + HANDLER_STACK_SIZE as i32 * HANDLER_SIZE;
+pub const STACK_START: i32 = MEMORY_START + MEMORY_LIMIT as i32;
+
+/// The size in bytes of a handler: the block of its code, `sp` and `csp`.
+const HANDLER_SIZE: i32 = 12;
+
+/// The size of a linear memory page in bytes.
+const PAGE_SIZE: i32 = 65536;
+
+/// The number of pages the memory starts with. The stack grows it a page at a time.
+const INITIAL_PAGES: u32 = (STACK_START / PAGE_SIZE) as u32 + 1;
+
+const SP: u32 = 0;
+const CSP: u32 = 1;
+/// The size of the memory of the program, which `MEMGROW` changes.
+const MEMORY_SIZE: u32 = 2;
+const HSP: u32 = 3;
+
+const PUSH: u32 = 0;
+const POP: u32 = 1;
+
+/// Locals of the `run` function.
+const PC: u32 = 0;
+const A: u32 = 1;
+const B: u32 = 2;
+const C: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    I32,
+    I64,
+}
+
+impl ValueType {
+    fn byte(self) -> u8 {
+        match self {
+            Self::I32 => 0x7F,
+            Self::I64 => 0x7E,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+        }
+    }
+}
+
+/// The function types of the module as `(params, results)`.
+const TYPES: [(&[ValueType], &[ValueType]); 4] = [
+    (&[], &[ValueType::I32]),
+    (&[ValueType::I32], &[ValueType::I64]),
+    (&[ValueType::I64], &[]),
+    (&[], &[ValueType::I64]),
+];
+
+/// The WebAssembly instructions that the backend emits.
+/// Memory instructions use natural alignment and no offset.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WasmInstruction {
+    Unreachable,
+    Block,
+    Loop,
+    If(Option<ValueType>),
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    Drop,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load,
+    I64Load,
+    I64Load8U,
+    I32Store,
+    I64Store,
+    I64Store8,
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eq,
+    I32LtU,
+    I32GtU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64GeS,
+    I32Add,
+    I32Sub,
+    I32Or,
+    I32Shl,
+    I32ShrU,
+    I64Popcnt,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+    I64Rotl,
+    I64Rotr,
+    I32WrapI64,
+    I64ExtendI32U,
+}
+
+impl WasmInstruction {
+    /// Returns the opcode and the text format name of the instruction.
+    fn opcode(&self) -> (u8, &'static str) {
+        match self {
+            Self::Unreachable => (0x00, "unreachable"),
+            Self::Block => (0x02, "block"),
+            Self::Loop => (0x03, "loop"),
+            Self::If(_) => (0x04, "if"),
+            Self::Else => (0x05, "else"),
+            Self::End => (0x0B, "end"),
+            Self::Br(_) => (0x0C, "br"),
+            Self::BrTable(_, _) => (0x0E, "br_table"),
+            Self::Return => (0x0F, "return"),
+            Self::Call(_) => (0x10, "call"),
+            Self::Drop => (0x1A, "drop"),
+            Self::LocalGet(_) => (0x20, "local.get"),
+            Self::LocalSet(_) => (0x21, "local.set"),
+            Self::GlobalGet(_) => (0x23, "global.get"),
+            Self::GlobalSet(_) => (0x24, "global.set"),
+            Self::I32Load => (0x28, "i32.load"),
+            Self::I64Load => (0x29, "i64.load"),
+            Self::I64Load8U => (0x31, "i64.load8_u"),
+            Self::I32Store => (0x36, "i32.store"),
+            Self::I64Store => (0x37, "i64.store"),
+            Self::I64Store8 => (0x3C, "i64.store8"),
+            Self::MemorySize => (0x3F, "memory.size"),
+            Self::MemoryGrow => (0x40, "memory.grow"),
+            Self::I32Const(_) => (0x41, "i32.const"),
+            Self::I64Const(_) => (0x42, "i64.const"),
+            Self::I32Eq => (0x46, "i32.eq"),
+            Self::I32LtU => (0x49, "i32.lt_u"),
+            Self::I32GtU => (0x4B, "i32.gt_u"),
+            Self::I64Eqz => (0x50, "i64.eqz"),
+            Self::I64Eq => (0x51, "i64.eq"),
+            Self::I64Ne => (0x52, "i64.ne"),
+            Self::I64LtS => (0x53, "i64.lt_s"),
+            Self::I64GtS => (0x55, "i64.gt_s"),
+            Self::I64GtU => (0x56, "i64.gt_u"),
+            Self::I64LeS => (0x57, "i64.le_s"),
+            Self::I64GeS => (0x59, "i64.ge_s"),
+            Self::I32Add => (0x6A, "i32.add"),
+            Self::I32Sub => (0x6B, "i32.sub"),
+            Self::I32Or => (0x72, "i32.or"),
+            Self::I32Shl => (0x74, "i32.shl"),
+            Self::I32ShrU => (0x76, "i32.shr_u"),
+            Self::I64Popcnt => (0x7B, "i64.popcnt"),
+            Self::I64Add => (0x7C, "i64.add"),
+            Self::I64Sub => (0x7D, "i64.sub"),
+            Self::I64Mul => (0x7E, "i64.mul"),
+            Self::I64DivS => (0x7F, "i64.div_s"),
+            Self::I64RemS => (0x81, "i64.rem_s"),
+            Self::I64And => (0x83, "i64.and"),
+            Self::I64Or => (0x84, "i64.or"),
+            Self::I64Xor => (0x85, "i64.xor"),
+            Self::I64Shl => (0x86, "i64.shl"),
+            Self::I64ShrS => (0x87, "i64.shr_s"),
+            Self::I64ShrU => (0x88, "i64.shr_u"),
+            Self::I64Rotl => (0x89, "i64.rotl"),
+            Self::I64Rotr => (0x8A, "i64.rotr"),
+            Self::I32WrapI64 => (0xA7, "i32.wrap_i64"),
+            Self::I64ExtendI32U => (0xAD, "i64.extend_i32_u"),
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.opcode().0);
+
+        match self {
+            Self::Block | Self::Loop | Self::If(None) => bytes.push(0x40),
+            Self::If(Some(value_type)) => bytes.push(value_type.byte()),
+            Self::Br(depth)
+            | Self::Call(depth)
+            | Self::LocalGet(depth)
+            | Self::LocalSet(depth)
+            | Self::GlobalGet(depth)
+            | Self::GlobalSet(depth) => write_unsigned(bytes, *depth as u64),
+            Self::BrTable(depths, default) => {
+                write_unsigned(bytes, depths.len() as u64);
+                for depth in depths {
+                    write_unsigned(bytes, *depth as u64);
+                }
+                write_unsigned(bytes, *default as u64);
+            }
+            Self::I32Load | Self::I32Store => bytes.extend_from_slice(&[2, 0]),
+            Self::I64Load | Self::I64Store => bytes.extend_from_slice(&[3, 0]),
+            Self::I64Load8U | Self::I64Store8 => bytes.extend_from_slice(&[0, 0]),
+            Self::MemorySize | Self::MemoryGrow => bytes.push(0x00),
+            Self::I32Const(value) => write_signed(bytes, *value as i64),
+            Self::I64Const(value) => write_signed(bytes, *value),
+            _ => {}
+        }
+    }
+}
+
+impl Display for WasmInstruction {
+    /// Formats the instruction in the text format, like `br_table 0 1 1`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.opcode().1;
+
+        match self {
+            Self::If(Some(value_type)) => write!(f, "{name} (result {})", value_type.name()),
+            Self::Br(index)
+            | Self::Call(index)
+            | Self::LocalGet(index)
+            | Self::LocalSet(index)
+            | Self::GlobalGet(index)
+            | Self::GlobalSet(index) => write!(f, "{name} {index}"),
+            Self::BrTable(depths, default) => {
+                write!(f, "{name}")?;
+                for depth in depths {
+                    write!(f, " {depth}")?;
+                }
+                write!(f, " {default}")
+            }
+            Self::I32Const(value) => write!(f, "{name} {value}"),
+            Self::I64Const(value) => write!(f, "{name} {value}"),
+            _ => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A function of the module. Its body doesn't include the final `end`.
+#[derive(Debug)]
+pub struct Function {
+    pub name: &'static str,
+    pub type_index: u32,
+    pub locals: Vec<ValueType>,
+    pub body: Vec<WasmInstruction>,
+    pub exported: bool,
+}
+
+/// A WebAssembly module that runs a program.
+/// It exports its memory, `run` which returns a status code, `depth` and `value` which read the result stack.
+#[derive(Debug)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    /// The data that the memory of the program starts with, at `MEMORY_START`.
+    pub data: Vec<u8>,
+}
+
+/// Generates a module from instructions decoded from current-format bytecode without fibers or channels.
+/// The registers, the memory of the program and the stack are in the linear memory, and the values are `i64`s.
+pub fn generate(instructions: &[Instruction], data: &[u8]) -> Module {
+    use WasmInstruction::*;
+
+    let push = Function {
+        name: "push",
+        type_index: 2,
+        locals: vec![],
+        body: vec![
+            GlobalGet(SP),
+            I32Const(8),
+            I32Add,
+            MemorySize,
+            I32Const(16),
+            I32Shl,
+            I32GtU,
+            If(None),
+            I32Const(1),
+            MemoryGrow,
+            Drop,
+            End,
+            GlobalGet(SP),
+            LocalGet(0),
+            I64Store,
+            GlobalGet(SP),
+            I32Const(8),
+            I32Add,
+            GlobalSet(SP),
+        ],
+        exported: false,
+    };
+
+    let pop = Function {
+        name: "pop",
+        type_index: 3,
+        locals: vec![],
+        body: vec![
+            GlobalGet(SP),
+            I32Const(8),
+            I32Sub,
+            GlobalSet(SP),
+            GlobalGet(SP),
+            I64Load,
+        ],
+        exported: false,
+    };
+
+    let depth = Function {
+        name: "depth",
+        type_index: 0,
+        locals: vec![],
+        body: vec![
+            GlobalGet(SP),
+            I32Const(STACK_START),
+            I32Sub,
+            I32Const(3),
+            I32ShrU,
+        ],
+        exported: true,
+    };
+
+    let value = Function {
+        name: "value",
+        type_index: 1,
+        locals: vec![],
+        body: vec![
+            LocalGet(0),
+            I32Const(3),
+            I32Shl,
+            I32Const(STACK_START),
+            I32Add,
+            I64Load,
+        ],
+        exported: true,
+    };
+
+    let run = Function {
+        name: "run",
+        type_index: 0,
+        locals: vec![
+            ValueType::I32,
+            ValueType::I64,
+            ValueType::I64,
+            ValueType::I64,
+        ],
+        body: Lowering::new(instructions).lower(instructions),
+        exported: true,
+    };
+
+    Module {
+        functions: vec![push, pop, run, depth, value],
+        data: data.to_vec(),
+    }
+}
+
+/// Lowers instructions into the body of `run`.
+/// Basic blocks are the arms of a `br_table` inside a loop, and jumps set the `pc` local to a block and continue the loop.
+/// If the program has a `TRY`, a last block passes thrown values to the handlers.
+struct Lowering {
+    body: Vec<WasmInstruction>,
+    starts: Vec<usize>,
+    block: usize,
+    catches: bool,
+}
+
+impl Lowering {
+    fn new(instructions: &[Instruction]) -> Self {
+        let mut starts: BTreeSet<usize> = instructions
+            .iter()
+            .filter_map(|instruction| instruction.target())
+            .collect();
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Instruction::CALL(_) = instruction {
+                starts.insert(index + 1);
+            }
+        }
+
+        starts.insert(0);
+        starts.insert(instructions.len());
+
+        // The block that catches thrown values starts after the end of the program.
+        let catches = instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::TRY(_)));
+
+        if catches {
+            starts.insert(instructions.len() + 1);
+        }
+
+        Self {
+            body: vec![],
+            starts: starts.into_iter().collect(),
+            block: 0,
+            catches,
+        }
+    }
+
+    fn lower(mut self, instructions: &[Instruction]) -> Vec<WasmInstruction> {
+        use WasmInstruction::*;
+
+        let last_block = self.starts.len() - 1;
+
+        self.body.extend([
+            I32Const(STACK_START),
+            GlobalSet(SP),
+            I32Const(CALL_STACK_START),
+            GlobalSet(CSP),
+            I32Const(HANDLER_STACK_START),
+            GlobalSet(HSP),
+            Loop,
+        ]);
+        self.body.extend((0..=last_block).map(|_| Block));
+        self.body.extend([
+            LocalGet(PC),
+            BrTable((0..=last_block as u32).collect(), last_block as u32),
+            End,
+        ]);
+
+        for (index, &instruction) in instructions.iter().enumerate() {
+            if self.starts[self.block + 1] == index {
+                self.body.push(End);
+                self.block += 1;
+            }
+
+            self.instruction(index, instruction);
+        }
+
+        let end_block = self.block_of(instructions.len()) as usize;
+
+        if self.block < end_block {
+            self.body.push(End);
+            self.block = end_block;
+        }
+
+        self.body.extend([I32Const(RET_OPCODE_NOT_FOUND), Return]);
+
+        if self.catches {
+            self.body.push(End);
+            self.block = last_block;
+            self.catch();
+        }
+
+        self.body.extend([End, Unreachable]);
+
+        self.body
+    }
+
+    /// Lowers the block that passes the value in `a` to the innermost handler, or returns the status in `pc` if there is none.
+    /// It cuts the stacks back to the depths of the handler before the value is pushed.
+    fn catch(&mut self) {
+        use WasmInstruction::*;
+
+        self.body.extend([
+            GlobalGet(HSP),
+            I32Const(HANDLER_STACK_START),
+            I32Eq,
+            If(None),
+            LocalGet(PC),
+            Return,
+            End,
+            GlobalGet(HSP),
+            I32Const(HANDLER_SIZE),
+            I32Sub,
+            GlobalSet(HSP),
+        ]);
+
+        for (offset, global) in [(4, SP), (8, CSP)] {
+            self.body.extend([
+                GlobalGet(global),
+                GlobalGet(HSP),
+                I32Const(offset),
+                I32Add,
+                I32Load,
+                I32GtU,
+                If(None),
+                GlobalGet(HSP),
+                I32Const(offset),
+                I32Add,
+                I32Load,
+                GlobalSet(global),
+                End,
+            ]);
+        }
+
+        self.push(A);
+        self.body
+            .extend([GlobalGet(HSP), I32Load, LocalSet(PC), Br(0)]);
+    }
+
+    /// Returns the block that starts at an instruction index.
+    fn block_of(&self, index: usize) -> i32 {
+        self.starts.binary_search(&index).unwrap_or_default() as i32
+    }
+
+    /// Continues the loop at the block that starts at `target`. `nesting` is the number of `if`s around the jump.
+    fn jump(&mut self, target: usize, nesting: u32) {
+        let loop_depth = (self.starts.len() - 1 - self.block) as u32 + nesting;
+        self.body.extend([
+            WasmInstruction::I32Const(self.block_of(target)),
+            WasmInstruction::LocalSet(PC),
+            WasmInstruction::Br(loop_depth),
+        ]);
+    }
+
+    /// Returns `status` from `run` if the condition on the wasm stack is true.
+    fn fail_if(&mut self, status: i32) {
+        self.body.extend([
+            WasmInstruction::If(None),
+            WasmInstruction::I32Const(status),
+            WasmInstruction::Return,
+            WasmInstruction::End,
+        ]);
+    }
+
+    /// Returns `status` if the stack holds fewer than `required` values.
+    fn require(&mut self, required: usize, status: i32) {
+        self.body.extend([
+            WasmInstruction::GlobalGet(SP),
+            WasmInstruction::I32Const(STACK_START + required as i32 * 8),
+            WasmInstruction::I32LtU,
+        ]);
+
+        match status {
+            NO_VALUE_IN_STACK => self.throw_if(VmError::NoValueInStack, status),
+            _ => self.fail_if(status),
+        }
+    }
+
+    /// Throws the value of a catchable error if the condition on the wasm stack is true.
+    /// Programs without a `TRY` return `status` like `fail_if`.
+    fn throw_if(&mut self, error: VmError, status: i32) {
+        if !self.catches {
+            self.fail_if(status);
+            return;
+        }
+
+        self.body.push(WasmInstruction::If(None));
+
+        // The virtual machine has popped every value when it finds none.
+        if let VmError::NoValueInStack = error {
+            self.body.extend([
+                WasmInstruction::I32Const(STACK_START),
+                WasmInstruction::GlobalSet(SP),
+            ]);
+        }
+
+        self.body.extend([
+            WasmInstruction::I64Const(error.code().unwrap_or_default()),
+            WasmInstruction::LocalSet(A),
+        ]);
+        self.throw(status, 1);
+        self.body.push(WasmInstruction::End);
+    }
+
+    /// Continues at the block that catches the value in `a`, which returns `status` if there is no handler.
+    /// Programs without a `TRY` return `status` right away.
+    /// `nesting` is the number of `if`s around the throw.
+    fn throw(&mut self, status: i32, nesting: u32) {
+        if !self.catches {
+            self.body
+                .extend([WasmInstruction::I32Const(status), WasmInstruction::Return]);
+            return;
+        }
+
+        let catch_depth = (self.starts.len() - 2 - self.block) as u32 + nesting;
+        self.body.extend([
+            WasmInstruction::I32Const(status),
+            WasmInstruction::LocalSet(PC),
+            WasmInstruction::Br(catch_depth),
+        ]);
+    }
+
+    fn pop(&mut self, local: u32) {
+        self.body
+            .extend([WasmInstruction::Call(POP), WasmInstruction::LocalSet(local)]);
+    }
+
+    fn push(&mut self, local: u32) {
+        self.body.extend([
+            WasmInstruction::LocalGet(local),
+            WasmInstruction::Call(PUSH),
+        ]);
+    }
+
+    /// Returns `MEMORY_OUT_OF_BOUNDS` unless `length` bytes at the address in `a` are in the memory of the program.
+    /// Then it leaves their address in the linear memory on the wasm stack.
+    /// The unsigned comparisons also catch negative addresses.
+    fn address(&mut self, length: i64) {
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::GlobalGet(MEMORY_SIZE),
+            WasmInstruction::I64ExtendI32U,
+            WasmInstruction::I64GtU,
+        ]);
+        self.fail_if(MEMORY_OUT_OF_BOUNDS);
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::I64Const(length),
+            WasmInstruction::I64Add,
+            WasmInstruction::GlobalGet(MEMORY_SIZE),
+            WasmInstruction::I64ExtendI32U,
+            WasmInstruction::I64GtU,
+        ]);
+        self.fail_if(MEMORY_OUT_OF_BOUNDS);
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::I32WrapI64,
+            WasmInstruction::I32Const(MEMORY_START),
+            WasmInstruction::I32Add,
+        ]);
+    }
+
+    /// Pushes the value that is `depth` values below the top of the stack.
+    fn pick(&mut self, depth: usize) {
+        self.body.extend([
+            WasmInstruction::GlobalGet(SP),
+            WasmInstruction::I32Const((depth as i32 + 1) * 8),
+            WasmInstruction::I32Sub,
+            WasmInstruction::I64Load,
+            WasmInstruction::Call(PUSH),
+        ]);
+    }
+
+    /// Pops the right operand into `b` and the left operand into `a` and pushes `operation` on them.
+    fn binary(&mut self, operation: WasmInstruction) {
+        self.require(2, NO_VALUE_IN_STACK);
+        self.pop(B);
+        self.pop(A);
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::LocalGet(B),
+            operation,
+            WasmInstruction::Call(PUSH),
+        ]);
+    }
+
+    /// Pops the operands like `binary` and pushes the `i32` result of `comparison` as 1 or 0.
+    fn compare(&mut self, comparison: WasmInstruction) {
+        self.require(2, NO_VALUE_IN_STACK);
+        self.pop(B);
+        self.pop(A);
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::LocalGet(B),
+            comparison,
+            WasmInstruction::I64ExtendI32U,
+            WasmInstruction::Call(PUSH),
+        ]);
+    }
+
+    /// Checks the shift amount after both operands are popped, like the virtual machine does.
+    fn shift(&mut self, operation: WasmInstruction) {
+        self.require(2, NO_VALUE_IN_STACK);
+        self.pop(B);
+        self.pop(A);
+        self.body.extend([
+            WasmInstruction::LocalGet(B),
+            WasmInstruction::I64Const(63),
+            WasmInstruction::I64GtU,
+        ]);
+        self.fail_if(INVALID_SHIFT_AMOUNT);
+        self.body.extend([
+            WasmInstruction::LocalGet(A),
+            WasmInstruction::LocalGet(B),
+            operation,
+            WasmInstruction::Call(PUSH),
+        ]);
+    }
+
+    fn instruction(&mut self, index: usize, instruction: Instruction) {
+        use WasmInstruction::*;
+
+        match instruction {
+            Instruction::PUSH(value) => self.body.extend([I64Const(value), Call(PUSH)]),
+            Instruction::POP => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.body.extend([Call(POP), Drop]);
+            }
+            Instruction::STORE(register) => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.body
+                    .extend([I32Const(register as i32 * 8), Call(POP), I64Store]);
+            }
+            Instruction::LOAD(register) => {
+                self.body
+                    .extend([I32Const(register as i32 * 8), I64Load, Call(PUSH)]);
+            }
+            Instruction::ADD => self.binary(I64Add),
+            Instruction::SUB => self.binary(I64Sub),
+            Instruction::MUL => self.binary(I64Mul),
+            Instruction::DIV => {
+                self.require(2, NO_VALUE_IN_STACK);
+                self.pop(B);
+                self.pop(A);
+                self.body.extend([LocalGet(B), I64Eqz]);
+                self.throw_if(VmError::DivisionByZero, DIVISION_BY_ZERO);
+                // `i64.div_s` traps on `i64::MIN / -1`, so dividing by -1 negates with wrapping instead.
+                self.body.extend([
+                    LocalGet(B),
+                    I64Const(-1),
+                    I64Eq,
+                    If(Some(ValueType::I64)),
+                    I64Const(0),
+                    LocalGet(A),
+                    I64Sub,
+                    Else,
+                    LocalGet(A),
+                    LocalGet(B),
+                    I64DivS,
+                    End,
+                    Call(PUSH),
+                ]);
+            }
+            Instruction::MOD => {
+                self.require(2, NO_VALUE_IN_STACK);
+                self.pop(B);
+                self.pop(A);
+                self.body.extend([LocalGet(B), I64Eqz]);
+                self.throw_if(VmError::DivisionByZero, DIVISION_BY_ZERO);
+                self.body
+                    .extend([LocalGet(A), LocalGet(B), I64RemS, Call(PUSH)]);
+            }
+            Instruction::RET => self.body.extend([I32Const(SUCCESS), Return]),
+            Instruction::DUP => {
+                self.require(1, STACK_UNDERFLOW);
+                self.pick(0);
+            }
+            Instruction::SWAP => {
+                self.require(2, STACK_UNDERFLOW);
+                self.pop(B);
+                self.pop(A);
+                self.push(B);
+                self.push(A);
+            }
+            Instruction::OVER => {
+                self.require(2, STACK_UNDERFLOW);
+                self.pick(1);
+            }
+            Instruction::ROT => {
+                self.require(3, STACK_UNDERFLOW);
+                self.pop(C);
+                self.pop(B);
+                self.pop(A);
+                self.push(B);
+                self.push(C);
+                self.push(A);
+            }
+            Instruction::NIP => {
+                self.require(2, STACK_UNDERFLOW);
+                self.pop(B);
+                self.body.extend([Call(POP), Drop]);
+                self.push(B);
+            }
+            Instruction::TUCK => {
+                self.require(2, STACK_UNDERFLOW);
+                self.pop(B);
+                self.pop(A);
+                self.push(B);
+                self.push(A);
+                self.push(B);
+            }
+            Instruction::PICK(depth) => {
+                self.require(depth as usize + 1, STACK_UNDERFLOW);
+                self.pick(depth as usize);
+            }
+            Instruction::DEPTH => self.body.extend([
+                GlobalGet(SP),
+                I32Const(STACK_START),
+                I32Sub,
+                I32Const(3),
+                I32ShrU,
+                I64ExtendI32U,
+                Call(PUSH),
+            ]),
+            Instruction::BAND => self.binary(I64And),
+            Instruction::BOR => self.binary(I64Or),
+            Instruction::BXOR => self.binary(I64Xor),
+            Instruction::BNOT => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.body
+                    .extend([Call(POP), I64Const(-1), I64Xor, Call(PUSH)]);
+            }
+            Instruction::SHL => self.shift(I64Shl),
+            Instruction::SHR => self.shift(I64ShrS),
+            Instruction::USHR => self.shift(I64ShrU),
+            Instruction::ROTL => self.shift(I64Rotl),
+            Instruction::ROTR => self.shift(I64Rotr),
+            Instruction::POPCNT => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.body.extend([Call(POP), I64Popcnt, Call(PUSH)]);
+            }
+            Instruction::EQ => self.compare(I64Eq),
+            Instruction::NE => self.compare(I64Ne),
+            Instruction::LT => self.compare(I64LtS),
+            Instruction::LE => self.compare(I64LeS),
+            Instruction::GT => self.compare(I64GtS),
+            Instruction::GE => self.compare(I64GeS),
+            Instruction::PUT(depth) => {
+                self.require(depth as usize + 2, STACK_UNDERFLOW);
+                self.pop(B);
+                self.body.extend([
+                    GlobalGet(SP),
+                    I32Const((depth as i32 + 1) * 8),
+                    I32Sub,
+                    LocalGet(B),
+                    I64Store,
+                ]);
+            }
+            Instruction::LOAD8 | Instruction::LOAD64 => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.pop(A);
+                match instruction {
+                    Instruction::LOAD8 => {
+                        self.address(1);
+                        self.body.push(I64Load8U);
+                    }
+                    _ => {
+                        self.address(8);
+                        self.body.push(I64Load);
+                    }
+                }
+                self.body.push(Call(PUSH));
+            }
+            Instruction::STORE8 | Instruction::STORE64 => {
+                self.require(2, NO_VALUE_IN_STACK);
+                self.pop(B);
+                self.pop(A);
+                match instruction {
+                    Instruction::STORE8 => {
+                        self.address(1);
+                        self.body.extend([LocalGet(B), I64Store8]);
+                    }
+                    _ => {
+                        self.address(8);
+                        self.body.extend([LocalGet(B), I64Store]);
+                    }
+                }
+            }
+            Instruction::MEMSIZE => {
+                self.body
+                    .extend([GlobalGet(MEMORY_SIZE), I64ExtendI32U, Call(PUSH)]);
+            }
+            Instruction::MEMGROW => {
+                // The old size is pushed, or -1 if the memory can't grow by the amount.
+                self.require(1, NO_VALUE_IN_STACK);
+                self.pop(A);
+                self.body.extend([
+                    LocalGet(A),
+                    I64Const(0),
+                    I64LtS,
+                    LocalGet(A),
+                    I64Const(MEMORY_LIMIT as i64),
+                    GlobalGet(MEMORY_SIZE),
+                    I64ExtendI32U,
+                    I64Sub,
+                    I64GtS,
+                    I32Or,
+                    If(Some(ValueType::I64)),
+                    I64Const(-1),
+                    Else,
+                    GlobalGet(MEMORY_SIZE),
+                    I64ExtendI32U,
+                    GlobalGet(MEMORY_SIZE),
+                    LocalGet(A),
+                    I32WrapI64,
+                    I32Add,
+                    GlobalSet(MEMORY_SIZE),
+                    End,
+                    Call(PUSH),
+                ]);
+            }
+            Instruction::JMP(target) => self.jump(target, 0),
+            Instruction::JZ(target) | Instruction::JNZ(target) => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.body.extend([Call(POP), I64Const(0)]);
+                self.body.push(match instruction {
+                    Instruction::JZ(_) => I64Eq,
+                    _ => I64Ne,
+                });
+                self.body.push(If(None));
+                self.jump(target, 1);
+                self.body.push(End);
+            }
+            Instruction::CALL(target) => {
+                // The call stack ends where the handler stack starts.
+                self.body
+                    .extend([GlobalGet(CSP), I32Const(HANDLER_STACK_START), I32Eq]);
+                self.fail_if(CALL_STACK_FULL);
+                self.body.extend([
+                    GlobalGet(CSP),
+                    I32Const(self.block_of(index + 1)),
+                    I32Store,
+                    GlobalGet(CSP),
+                    I32Const(4),
+                    I32Add,
+                    GlobalSet(CSP),
+                ]);
+                self.jump(target, 0);
+            }
+            Instruction::RETURN => {
+                let loop_depth = (self.starts.len() - 1 - self.block) as u32;
+                self.body
+                    .extend([GlobalGet(CSP), I32Const(CALL_STACK_START), I32Eq]);
+                self.fail_if(NO_ADDRESS_IN_CALL_STACK);
+                self.body.extend([
+                    GlobalGet(CSP),
+                    I32Const(4),
+                    I32Sub,
+                    GlobalSet(CSP),
+                    GlobalGet(CSP),
+                    I32Load,
+                    LocalSet(PC),
+                    Br(loop_depth),
+                ]);
+            }
+            Instruction::TRY(target) => {
+                // The handler stack ends where the memory of the program starts.
+                self.body
+                    .extend([GlobalGet(HSP), I32Const(MEMORY_START), I32Eq]);
+                self.fail_if(HANDLER_STACK_FULL);
+                self.body.extend([
+                    GlobalGet(HSP),
+                    I32Const(self.block_of(target)),
+                    I32Store,
+                    GlobalGet(HSP),
+                    I32Const(4),
+                    I32Add,
+                    GlobalGet(SP),
+                    I32Store,
+                    GlobalGet(HSP),
+                    I32Const(8),
+                    I32Add,
+                    GlobalGet(CSP),
+                    I32Store,
+                    GlobalGet(HSP),
+                    I32Const(HANDLER_SIZE),
+                    I32Add,
+                    GlobalSet(HSP),
+                ]);
+            }
+            Instruction::THROW(_) => {
+                self.require(1, NO_VALUE_IN_STACK);
+                self.pop(A);
+                self.throw(UNCAUGHT_THROW, 0);
+            }
+            Instruction::ENDTRY => {
+                self.body
+                    .extend([GlobalGet(HSP), I32Const(HANDLER_STACK_START), I32Eq]);
+                self.fail_if(NO_HANDLER_IN_HANDLER_STACK);
+                self.body.extend([
+                    GlobalGet(HSP),
+                    I32Const(HANDLER_SIZE),
+                    I32Sub,
+                    GlobalSet(HSP),
+                ]);
+            }
+            Instruction::SPAWN(_)
+            | Instruction::YIELD
+            | Instruction::RESUME
+            | Instruction::FIBERSTATUS
+            | Instruction::SEND
+            | Instruction::RECV => {
+                unreachable!("fibers and channels are rejected by `backend::generate`")
+            }
+        }
+    }
+}
+
+impl Module {
+    /// Encodes the module in the binary format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = b"\0asm".to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+
+        let mut types = vec![];
+        write_unsigned(&mut types, TYPES.len() as u64);
+        for (params, results) in TYPES {
+            types.push(0x60);
+            for value_types in [params, results] {
+                write_unsigned(&mut types, value_types.len() as u64);
+                types.extend(value_types.iter().map(|value_type| value_type.byte()));
+            }
+        }
+        write_section(&mut bytes, 1, &types);
+
+        let mut functions = vec![];
+        write_unsigned(&mut functions, self.functions.len() as u64);
+        for function in &self.functions {
+            write_unsigned(&mut functions, function.type_index as u64);
+        }
+        write_section(&mut bytes, 3, &functions);
+
+        let mut memories = vec![1, 0x00];
+        write_unsigned(&mut memories, INITIAL_PAGES as u64);
+        write_section(&mut bytes, 5, &memories);
+
+        let mut globals = vec![4];
+        for value in [
+            STACK_START,
+            CALL_STACK_START,
+            self.data.len() as i32,
+            HANDLER_STACK_START,
+        ] {
+            globals.extend_from_slice(&[ValueType::I32.byte(), 0x01]);
+            WasmInstruction::I32Const(value).encode(&mut globals);
+            WasmInstruction::End.encode(&mut globals);
+        }
+        write_section(&mut bytes, 6, &globals);
+
+        let exported: Vec<(usize, &Function)> = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter(|(_, function)| function.exported)
+            .collect();
+        let mut exports = vec![];
+        write_unsigned(&mut exports, exported.len() as u64 + 1);
+        write_name(&mut exports, "memory");
+        exports.extend_from_slice(&[0x02, 0]);
+        for (index, function) in exported {
+            write_name(&mut exports, function.name);
+            exports.push(0x00);
+            write_unsigned(&mut exports, index as u64);
+        }
+        write_section(&mut bytes, 7, &exports);
+
+        let mut code = vec![];
+        write_unsigned(&mut code, self.functions.len() as u64);
+        for function in &self.functions {
+            let mut body = vec![];
+            let mut groups: Vec<(u32, ValueType)> = vec![];
+            for &local in &function.locals {
+                match groups.last_mut() {
+                    Some((count, value_type)) if *value_type == local => *count += 1,
+                    _ => groups.push((1, local)),
+                }
+            }
+            write_unsigned(&mut body, groups.len() as u64);
+            for (count, value_type) in groups {
+                write_unsigned(&mut body, count as u64);
+                body.push(value_type.byte());
+            }
+            for instruction in &function.body {
+                instruction.encode(&mut body);
+            }
+            WasmInstruction::End.encode(&mut body);
+
+            write_unsigned(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+        write_section(&mut bytes, 10, &code);
+
+        if !self.data.is_empty() {
+            let mut data = vec![1, 0x00];
+            WasmInstruction::I32Const(MEMORY_START).encode(&mut data);
+            WasmInstruction::End.encode(&mut data);
+            write_unsigned(&mut data, self.data.len() as u64);
+            data.extend_from_slice(&self.data);
+            write_section(&mut bytes, 11, &data);
+        }
+
+        bytes
+    }
+}
+
+impl Display for Module {
+    /// Formats the module in the text format.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "(module")?;
+
+        for (index, (params, results)) in TYPES.iter().enumerate() {
+            write!(f, "  (type (;{index};) (func")?;
+            write_value_types(f, "param", params)?;
+            write_value_types(f, "result", results)?;
+            writeln!(f, "))")?;
+        }
+
+        writeln!(f, "  (memory (;0;) {INITIAL_PAGES})")?;
+        writeln!(f, "  (global $sp (mut i32) (i32.const {STACK_START}))")?;
+        writeln!(
+            f,
+            "  (global $csp (mut i32) (i32.const {CALL_STACK_START}))"
+        )?;
+        writeln!(
+            f,
+            "  (global $memory_size (mut i32) (i32.const {}))",
+            self.data.len()
+        )?;
+        writeln!(
+            f,
+            "  (global $hsp (mut i32) (i32.const {HANDLER_STACK_START}))"
+        )?;
+        writeln!(f, "  (export \"memory\" (memory 0))")?;
+
+        for function in &self.functions {
+            let (params, results) = TYPES[function.type_index as usize];
+            write!(f, "  (func ${}", function.name)?;
+            if function.exported {
+                write!(f, " (export \"{}\")", function.name)?;
+            }
+            write!(f, " (type {})", function.type_index)?;
+            write_value_types(f, "param", params)?;
+            write_value_types(f, "result", results)?;
+            writeln!(f)?;
+
+            if !function.locals.is_empty() {
+                write!(f, "   ")?;
+                write_value_types(f, "local", &function.locals)?;
+                writeln!(f)?;
+            }
+
+            let mut indentation = 2;
+            for instruction in &function.body {
+                if matches!(instruction, WasmInstruction::End | WasmInstruction::Else) {
+                    indentation -= 1;
+                }
+
+                writeln!(f, "{}{instruction}", "  ".repeat(indentation))?;
+
+                if matches!(
+                    instruction,
+                    WasmInstruction::Block
+                        | WasmInstruction::Loop
+                        | WasmInstruction::If(_)
+                        | WasmInstruction::Else
+                ) {
+                    indentation += 1;
+                }
+            }
+
+            writeln!(f, "  )")?;
+        }
+
+        if !self.data.is_empty() {
+            write!(f, "  (data (i32.const {MEMORY_START}) \"")?;
+            for byte in &self.data {
+                write!(f, "\\{byte:02x}")?;
+            }
+            writeln!(f, "\")")?;
+        }
+
+        writeln!(f, ")")
+    }
+}
+
+/// Writes value types like ` (param i32 i64)`, or nothing if there are none.
+fn write_value_types(
+    f: &mut std::fmt::Formatter<'_>,
+    keyword: &str,
+    value_types: &[ValueType],
+) -> std::fmt::Result {
+    if value_types.is_empty() {
+        return Ok(());
+    }
+
+    write!(f, " ({keyword}")?;
+
+    for value_type in value_types {
+        write!(f, " {}", value_type.name())?;
+    }
+
+    write!(f, ")")
+}
+
+fn write_section(bytes: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    bytes.push(id);
+    write_unsigned(bytes, contents.len() as u64);
+    bytes.extend_from_slice(contents);
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_unsigned(bytes, name.len() as u64);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+/// Writes an unsigned LEB128 number.
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            return bytes.push(byte);
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 number.
+fn write_signed(bytes: &mut Vec<u8>, mut value: Value) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            return bytes.push(byte);
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[test]
+fn test_encoding_wasm() {
+    fn read_unsigned(bytes: &[u8], position: &mut usize) -> usize {
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = bytes[*position];
+            *position += 1;
+            value |= ((byte & 0x7F) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    let module = generate(&[Instruction::RET], &[]);
+    let bytes = module.encode();
+
+    assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0");
+
+    let mut sections = vec![];
+    let mut position = 8;
+
+    while position < bytes.len() {
+        let id = bytes[position];
+        position += 1;
+        let size = read_unsigned(&bytes, &mut position);
+        sections.push((id, &bytes[position..position + size]));
+        position += size;
+    }
+
+    let ids: Vec<u8> = sections.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids, &[1, 3, 5, 6, 7, 10]);
+
+    #[rustfmt::skip]
+    let types = [
+        0x04,
+        0x60, 0x00, 0x01, 0x7F,
+        0x60, 0x01, 0x7F, 0x01, 0x7E,
+        0x60, 0x01, 0x7E, 0x00,
+        0x60, 0x00, 0x01, 0x7E,
+    ];
+    assert_eq!(sections[0].1, types);
+    assert_eq!(sections[1].1, [0x05, 0x02, 0x03, 0x00, 0x00, 0x01]);
+    assert_eq!(sections[2].1, [0x01, 0x00, 0x15]);
+
+    #[rustfmt::skip]
+    let globals = [
+        0x04,
+        0x7F, 0x01, 0x41, 0x80, 0x90, 0xD3, 0x00, 0x0B,
+        0x7F, 0x01, 0x41, 0x80, 0x10, 0x0B,
+        0x7F, 0x01, 0x41, 0x00, 0x0B,
+        0x7F, 0x01, 0x41, 0x80, 0x90, 0x10, 0x0B,
+    ];
+    assert_eq!(sections[3].1, globals);
+
+    #[rustfmt::skip]
+    let exports = [
+        0x04,
+        0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+        0x03, b'r', b'u', b'n', 0x00, 0x02,
+        0x05, b'd', b'e', b'p', b't', b'h', 0x00, 0x03,
+        0x05, b'v', b'a', b'l', b'u', b'e', 0x00, 0x04,
+    ];
+    assert_eq!(sections[4].1, exports);
+
+    let code = sections[5].1;
+    let mut position = 0;
+    assert_eq!(read_unsigned(code, &mut position), 5);
+
+    let mut bodies = vec![];
+    for _ in 0..5 {
+        let size = read_unsigned(code, &mut position);
+        bodies.push(&code[position..position + size]);
+        position += size;
+    }
+
+    #[rustfmt::skip]
+    let run = [
+        0x02, 0x01, 0x7F, 0x03, 0x7E,
+        0x41, 0x80, 0x90, 0xD3, 0x00, 0x24, 0x00,
+        0x41, 0x80, 0x10, 0x24, 0x01,
+        0x41, 0x80, 0x90, 0x10, 0x24, 0x03,
+        0x03, 0x40,
+        0x02, 0x40, 0x02, 0x40,
+        0x20, 0x00,
+        0x0E, 0x02, 0x00, 0x01, 0x01,
+        0x0B,
+        0x41, 0x00, 0x0F,
+        0x0B,
+        0x41, 0x01, 0x0F,
+        0x0B, 0x00, 0x0B,
+    ];
+    assert_eq!(bodies[2], run);
+
+    let text = module.to_string();
+    assert!(text.contains("  (func $run (export \"run\") (type 0) (result i32)\n"));
+    assert!(text.contains("        br_table 0 1 1\n"));
+}
+
+#[test]
+#[ignore = "needs `node`, run it with `cargo test -- --include-ignored`"]
+fn test_running_wasm() {
+    use crate::error::VmError;
+    use crate::{
+        compiler::compile, lexer::tokenize, parser::parse, virtual_machine::VirtualMachine,
+    };
+    use std::process::Command;
+
+    Command::new("node")
+        .arg("--version")
+        .output()
+        .expect("the WebAssembly test needs `node`");
+
+    let directory = std::env::temp_dir().join(format!("wasm-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let script = directory.join("run.js");
+    std::fs::write(
+        &script,
+        "const instance = new WebAssembly.Instance(new WebAssembly.Module(\
+         require('fs').readFileSync(process.argv[2])));\n\
+         const status = instance.exports.run();\n\
+         const values = [];\n\
+         for (let i = 0; i < instance.exports.depth(); i++) values.push(instance.exports.value(i));\n\
+         console.log(status + ':' + values.join(','));\n",
+    )
+    .unwrap();
+
+    let programs = [
+        include_str!("../../examples/loop.code"),
+        "PUSH -9223372036854775808 PUSH -1 DIV PUSH -7 PUSH 2 MOD PUSH 5000000000 RET",
+        "PUSH 1 PUSH 2 PUSH 3 ROT TUCK OVER SWAP NIP PICK 2 DEPTH DUP RET",
+        "PUSH -16 PUSH 2 SHR PUSH -1 PUSH 60 USHR PUSH 1 PUSH 63 ROTR PUSH 255 POPCNT BNOT RET",
+        "PUSH 6 CALL square PUSH 1 JZ end PUSH 7 end: RET square: DUP MUL RETURN",
+        "f: CALL f RET",
+        "PUSH 1 PUSH 0 DIV RET",
+        "PUSH -1 PUSH 2 LT PUSH 2 PUSH 2 LE PUSH 3 PUSH 2 GT PUSH 2 PUSH 3 GE PUSH 1 PUSH 1 EQ PUSH 1 PUSH 2 NE RET",
+        "PUSH 1 PUSH 2 PUSH 3 PUSH 4 PUT 2 RET",
+        "PUSH 1 PUT 1 RET",
+        "PUSH 1 PICK 3 RET",
+        "PUSH 1 PUSH 64 SHL RET",
+        "PUSH -1 LOAD8 RET",
+        "PUSH 8 MEMGROW PUSH 1 PUSH 1 STORE64 RET",
+        "MEMSIZE PUSH 1048576 MEMGROW PUSH 1 MEMGROW PUSH -1 MEMGROW MEMSIZE RET",
+        "PUSH 16 MEMGROW PUSH 8 PUSH -2 STORE64 PUSH 8 LOAD64 PUSH 8 PUSH 300 STORE8 PUSH 8 LOAD64 PUSH 15 LOAD8 RET",
+        ".data\nvalues: .quad 5 -7\ntext: .ascii \"hi\"\n.text\nPUSH values PUSH 8 ADD LOAD64 PUSH text LOAD8 MEMSIZE RET",
+        include_str!("../../examples/exceptions.code"),
+        "PUSH 1 TRY h PUSH 2 PUSH 3 THROW h: RET",
+        "TRY outer TRY inner PUSH 1 THROW inner: PUSH 2 ADD THROW outer: RET",
+        "PUSH 1 TRY h PUSH 2 CALL f h: RET f: PUSH 3 PUSH 0 MOD RETURN",
+        "TRY h POP RET h: RET",
+        "PUSH 5 THROW RET",
+        "THROW RET",
+        "ENDTRY RET",
+        "ADD RET",
+        "RETURN",
+        "PUSH 1",
+    ];
+
+    for (index, program) in programs.iter().enumerate() {
+        let bytecode = compile(parse(tokenize(program)).unwrap());
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        let instructions = virtual_machine.decode().unwrap().to_vec();
+        let module = generate(&instructions, virtual_machine.memory());
+
+        let path = directory.join(format!("program{index}.wasm"));
+        std::fs::write(&path, module.encode()).unwrap();
+
+        let output = Command::new("node")
+            .arg(&script)
+            .arg(&path)
+            .output()
+            .unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        let (status, values) = output.trim().split_once(':').unwrap();
+
+        let result = virtual_machine.run().map(|result| result.to_vec());
+        let expected = match &result {
+            Ok(_) => SUCCESS,
+            Err(VmError::RetOpcodeNotFound) => RET_OPCODE_NOT_FOUND,
+            Err(VmError::NoValueInStack) => NO_VALUE_IN_STACK,
+            Err(VmError::StackUnderflow { .. }) => STACK_UNDERFLOW,
+            Err(VmError::InvalidShiftAmount(_)) => INVALID_SHIFT_AMOUNT,
+            Err(VmError::DivisionByZero) => DIVISION_BY_ZERO,
+            Err(VmError::NoAddressInCallStack) => NO_ADDRESS_IN_CALL_STACK,
+            Err(VmError::CallStackOverflow) => CALL_STACK_FULL,
+            Err(VmError::MemoryOutOfBounds { .. }) => MEMORY_OUT_OF_BOUNDS,
+            Err(VmError::UncaughtThrow { .. }) => UNCAUGHT_THROW,
+            Err(VmError::NoHandlerInHandlerStack) => NO_HANDLER_IN_HANDLER_STACK,
+            Err(error) => panic!("{error}"),
+        };
+        assert_eq!(status, expected.to_string(), "{program}");
+
+        if let Ok(result) = result {
+            let result: Vec<String> = result.iter().map(|value| value.to_string()).collect();
+            assert_eq!(values, result.join(","), "{program}");
+        }
+    }
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,741 @@
+use crate::{
+    error::VmError,
+    instruction::Instruction,
+    opcode::Opcode,
+    virtual_machine::{MAX_CALL_DEPTH, MEMORY_LIMIT},
+};
+
+use super::HANDLER_STACK_SIZE;
+
+/// The message printed when a generated program runs out of handlers.
+const HANDLER_STACK_FULL: &str = "RUNTIME ERROR: the handler stack is full";
+
+/// The size in bytes of a handler: the address of its code, `%rsp` and `%r13`.
+const HANDLER_SIZE: usize = 24;
+
+/// Generates GNU assembly for x86-64 Linux from instructions decoded from current-format bytecode without fibers or channels.
+/// The stack is the machine stack, with `%r12` pointing at its bottom, and the registers are a static array.
+/// `%r13` points past the top of the call stack and `%r15` past the top of the handler stack.
+/// The memory is a static array, and `data` is copied to its start before the program runs.
+/// The program prints its result like the `run` command, or prints a runtime error and exits with status 1.
+pub fn generate(instructions: &[Instruction], data: &[u8]) -> String {
+    let mut generator = Generator::default();
+
+    generator.text.push_str(".globl _start\n.text\n_start:\n");
+    generator.emit("movq %rsp, %r12");
+    generator.emit("leaq call_stack(%rip), %r13");
+    generator.emit("leaq handlers(%rip), %r15");
+
+    if !data.is_empty() {
+        generator.emit("leaq memory_data(%rip), %rsi");
+        generator.emit("leaq memory(%rip), %rdi");
+        generator.emit(&format!("movq ${}, %rcx", data.len()));
+        generator.emit("rep movsb");
+    }
+
+    for (index, &instruction) in instructions.iter().enumerate() {
+        generator.instruction(index, instruction);
+    }
+
+    generator.finish(instructions.len(), data)
+}
+
+/// Collects the lines of a program and the messages it prints.
+#[derive(Default)]
+struct Generator {
+    text: String,
+    messages: Vec<String>,
+    underflows: Vec<usize>,
+}
+
+impl Generator {
+    /// Appends an indented line.
+    fn emit(&mut self, line: &str) {
+        self.text.push_str("    ");
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn label(&mut self, label: &str) {
+        self.text.push_str(label);
+        self.text.push_str(":\n");
+    }
+
+    /// Returns the index of a message in the data section, adding it if it is new.
+    fn message(&mut self, message: &str) -> usize {
+        match self.messages.iter().position(|known| known == message) {
+            Some(index) => index,
+            None => {
+                self.messages.push(message.to_string());
+                self.messages.len() - 1
+            }
+        }
+    }
+
+    /// Writes a message to a file descriptor.
+    fn write_message(&mut self, file_descriptor: u8, message: &str) {
+        let index = self.message(message);
+        self.emit(&format!("leaq .Lmessage{index}(%rip), %rsi"));
+        self.emit(&format!("movq ${}, %rdx", message.len()));
+        self.emit(&format!("movl ${file_descriptor}, %edi"));
+        self.emit("call write_bytes");
+    }
+
+    /// Jumps to `error` if the stack holds fewer than `required` values.
+    fn require_values(&mut self, required: usize, error: &str) {
+        self.emit(&format!("leaq {}(%rsp), %rax", required * 8));
+        self.emit("cmpq %r12, %rax");
+        self.emit(&format!("ja {error}"));
+    }
+
+    /// Reports `VmError::NoValueInStack` if the stack holds fewer than `required` values.
+    fn require_operands(&mut self, required: usize) {
+        self.require_values(required, "no_value_in_stack");
+    }
+
+    /// Reports `VmError::StackUnderflow` if the stack holds fewer than `required` values for `opcode`.
+    fn require_stack(&mut self, opcode: Opcode, required: usize) {
+        let error = VmError::StackUnderflow {
+            opcode,
+            required,
+            found: 0,
+        };
+        // The number of values found is printed at runtime.
+        let message = error.to_string();
+        let index = self.message(message.strip_suffix('0').unwrap_or(&message));
+
+        if !self.underflows.contains(&index) {
+            self.underflows.push(index);
+        }
+
+        self.require_values(required, &format!(".Lunderflow{index}"));
+    }
+
+    fn instruction(&mut self, index: usize, instruction: Instruction) {
+        self.label(&format!(".L{index}"));
+
+        match instruction {
+            Instruction::PUSH(value) => match i32::try_from(value) {
+                Ok(value) => self.emit(&format!("pushq ${value}")),
+                Err(_) => {
+                    self.emit(&format!("movabsq ${value}, %rax"));
+                    self.emit("pushq %rax");
+                }
+            },
+            Instruction::POP => {
+                self.require_operands(1);
+                self.emit("addq $8, %rsp");
+            }
+            Instruction::STORE(register) => {
+                self.require_operands(1);
+                self.emit(&format!("popq registers+{}(%rip)", register as usize * 8));
+            }
+            Instruction::LOAD(register) => {
+                self.emit(&format!("pushq registers+{}(%rip)", register as usize * 8));
+            }
+            Instruction::ADD => self.binary("addq %rax, (%rsp)"),
+            Instruction::SUB => self.binary("subq %rax, (%rsp)"),
+            Instruction::MUL => {
+                self.require_operands(2);
+                self.emit("popq %rax");
+                self.emit("imulq (%rsp), %rax");
+                self.emit("movq %rax, (%rsp)");
+            }
+            Instruction::DIV => self.division("negq (%rsp)", "%rax"),
+            Instruction::MOD => self.division("movq $0, (%rsp)", "%rdx"),
+            Instruction::RET => self.emit("jmp program_result"),
+            Instruction::DUP => {
+                self.require_stack(Opcode::DUP, 1);
+                self.emit("pushq (%rsp)");
+            }
+            Instruction::SWAP => {
+                self.require_stack(Opcode::SWAP, 2);
+                self.emit("movq (%rsp), %rax");
+                self.emit("movq 8(%rsp), %rcx");
+                self.emit("movq %rcx, (%rsp)");
+                self.emit("movq %rax, 8(%rsp)");
+            }
+            Instruction::OVER => {
+                self.require_stack(Opcode::OVER, 2);
+                self.emit("pushq 8(%rsp)");
+            }
+            Instruction::ROT => {
+                self.require_stack(Opcode::ROT, 3);
+                self.emit("movq 16(%rsp), %rax");
+                self.emit("movq 8(%rsp), %rcx");
+                self.emit("movq (%rsp), %rdx");
+                self.emit("movq %rcx, 16(%rsp)");
+                self.emit("movq %rdx, 8(%rsp)");
+                self.emit("movq %rax, (%rsp)");
+            }
+            Instruction::NIP => {
+                self.require_stack(Opcode::NIP, 2);
+                self.emit("popq %rax");
+                self.emit("movq %rax, (%rsp)");
+            }
+            Instruction::TUCK => {
+                self.require_stack(Opcode::TUCK, 2);
+                self.emit("movq (%rsp), %rax");
+                self.emit("movq 8(%rsp), %rcx");
+                self.emit("movq %rax, 8(%rsp)");
+                self.emit("movq %rcx, (%rsp)");
+                self.emit("pushq %rax");
+            }
+            Instruction::PICK(depth) => {
+                self.require_stack(Opcode::PICK, depth as usize + 1);
+                self.emit(&format!("pushq {}(%rsp)", depth as usize * 8));
+            }
+            Instruction::DEPTH => {
+                self.emit("movq %r12, %rax");
+                self.emit("subq %rsp, %rax");
+                self.emit("shrq $3, %rax");
+                self.emit("pushq %rax");
+            }
+            Instruction::BAND => self.binary("andq %rax, (%rsp)"),
+            Instruction::BOR => self.binary("orq %rax, (%rsp)"),
+            Instruction::BXOR => self.binary("xorq %rax, (%rsp)"),
+            Instruction::BNOT => {
+                self.require_operands(1);
+                self.emit("notq (%rsp)");
+            }
+            Instruction::SHL => self.shift("shlq %cl, (%rsp)"),
+            Instruction::SHR => self.shift("sarq %cl, (%rsp)"),
+            Instruction::USHR => self.shift("shrq %cl, (%rsp)"),
+            Instruction::ROTL => self.shift("rolq %cl, (%rsp)"),
+            Instruction::ROTR => self.shift("rorq %cl, (%rsp)"),
+            Instruction::POPCNT => {
+                self.require_operands(1);
+                self.emit("popcntq (%rsp), %rax");
+                self.emit("movq %rax, (%rsp)");
+            }
+            Instruction::EQ => self.compare("sete"),
+            Instruction::NE => self.compare("setne"),
+            Instruction::LT => self.compare("setl"),
+            Instruction::LE => self.compare("setle"),
+            Instruction::GT => self.compare("setg"),
+            Instruction::GE => self.compare("setge"),
+            Instruction::PUT(depth) => {
+                self.require_stack(Opcode::PUT, depth as usize + 2);
+                self.emit("popq %rax");
+                self.emit(&format!("movq %rax, {}(%rsp)", depth as usize * 8));
+            }
+            Instruction::LOAD8 => {
+                self.require_operands(1);
+                self.emit("popq %rax");
+                self.address(1);
+                self.emit("movzbq (%rcx,%rax), %rax");
+                self.emit("pushq %rax");
+            }
+            Instruction::LOAD64 => {
+                self.require_operands(1);
+                self.emit("popq %rax");
+                self.address(8);
+                self.emit("pushq (%rcx,%rax)");
+            }
+            Instruction::STORE8 => {
+                self.require_operands(2);
+                self.emit("popq %rdx");
+                self.emit("popq %rax");
+                self.address(1);
+                self.emit("movb %dl, (%rcx,%rax)");
+            }
+            Instruction::STORE64 => {
+                self.require_operands(2);
+                self.emit("popq %rdx");
+                self.emit("popq %rax");
+                self.address(8);
+                self.emit("movq %rdx, (%rcx,%rax)");
+            }
+            Instruction::MEMSIZE => self.emit("pushq memory_size(%rip)"),
+            Instruction::MEMGROW => {
+                // The old size is pushed, or -1 if the memory can't grow by the amount.
+                self.require_operands(1);
+                self.emit("popq %rax");
+                self.emit(&format!("movq ${MEMORY_LIMIT}, %rdx"));
+                self.emit("subq memory_size(%rip), %rdx");
+                self.emit("testq %rax, %rax");
+                self.emit("js 1f");
+                self.emit("cmpq %rdx, %rax");
+                self.emit("jg 1f");
+                self.emit("pushq memory_size(%rip)");
+                self.emit("addq %rax, memory_size(%rip)");
+                self.emit("jmp 2f");
+                self.label("1");
+                self.emit("pushq $-1");
+                self.label("2");
+            }
+            Instruction::JMP(target) => self.emit(&format!("jmp .L{target}")),
+            Instruction::JZ(target) => self.conditional_jump("jz", target),
+            Instruction::JNZ(target) => self.conditional_jump("jnz", target),
+            Instruction::CALL(target) => {
+                self.emit("leaq call_stack_end(%rip), %rax");
+                self.emit("cmpq %rax, %r13");
+                self.emit("je call_stack_overflow");
+                self.emit(&format!("leaq .L{}(%rip), %rax", index + 1));
+                self.emit("movq %rax, (%r13)");
+                self.emit("addq $8, %r13");
+                self.emit(&format!("jmp .L{target}"));
+            }
+            Instruction::RETURN => {
+                self.emit("leaq call_stack(%rip), %rax");
+                self.emit("cmpq %rax, %r13");
+                self.emit("je no_address_in_call_stack");
+                self.emit("subq $8, %r13");
+                self.emit("jmp *(%r13)");
+            }
+            Instruction::TRY(target) => {
+                self.emit("leaq handlers_end(%rip), %rax");
+                self.emit("cmpq %rax, %r15");
+                self.emit("je handler_stack_full");
+                self.emit(&format!("leaq .L{target}(%rip), %rax"));
+                self.emit("movq %rax, (%r15)");
+                self.emit("movq %rsp, 8(%r15)");
+                self.emit("movq %r13, 16(%r15)");
+                self.emit(&format!("addq ${HANDLER_SIZE}, %r15"));
+            }
+            Instruction::THROW(address) => {
+                self.require_operands(1);
+                self.emit("popq %rax");
+                self.emit(&format!("movq ${address}, %rbx"));
+                self.emit("jmp throw");
+            }
+            Instruction::ENDTRY => {
+                self.emit("leaq handlers(%rip), %rax");
+                self.emit("cmpq %rax, %r15");
+                self.emit("je no_handler_in_handler_stack");
+                self.emit(&format!("subq ${HANDLER_SIZE}, %r15"));
+            }
+            Instruction::SPAWN(_)
+            | Instruction::YIELD
+            | Instruction::RESUME
+            | Instruction::FIBERSTATUS
+            | Instruction::SEND
+            | Instruction::RECV => {
+                unreachable!("fibers and channels are rejected by `backend::generate`")
+            }
+        }
+    }
+
+    /// Pops the right operand into `%rax` and applies `operation` to the left operand on the stack.
+    fn binary(&mut self, operation: &str) {
+        self.require_operands(2);
+        self.emit("popq %rax");
+        self.emit(operation);
+    }
+
+    /// Pops the right operand and replaces the left operand with 1 if `set` holds for them or 0 otherwise.
+    fn compare(&mut self, set: &str) {
+        self.require_operands(2);
+        self.emit("popq %rax");
+        self.emit("cmpq %rax, (%rsp)");
+        self.emit(&format!("{set} %al"));
+        self.emit("movzbq %al, %rax");
+        self.emit("movq %rax, (%rsp)");
+    }
+
+    /// Pops the shift amount into `%rcx`, checks it and applies `operation` to the value on the stack.
+    fn shift(&mut self, operation: &str) {
+        self.require_operands(2);
+        self.emit("popq %rcx");
+        self.emit("cmpq $63, %rcx");
+        self.emit("ja invalid_shift_amount");
+        self.emit(operation);
+    }
+
+    /// Divides with `idivq`, which traps on `i64::MIN / -1`, so a divisor of -1 uses `minus_one` instead.
+    fn division(&mut self, minus_one: &str, result: &str) {
+        self.require_operands(2);
+        self.emit("popq %rcx");
+        self.emit("testq %rcx, %rcx");
+        self.emit("jz division_by_zero");
+        self.emit("cmpq $-1, %rcx");
+        self.emit("jne 1f");
+        self.emit(minus_one);
+        self.emit("jmp 2f");
+        self.label("1");
+        self.emit("movq (%rsp), %rax");
+        self.emit("cqto");
+        self.emit("idivq %rcx");
+        self.emit(&format!("movq {result}, (%rsp)"));
+        self.label("2");
+    }
+
+    /// Checks that `length` bytes at the address in `%rax` are in the memory and points `%rcx` at the memory.
+    /// The unsigned comparisons also catch negative addresses.
+    fn address(&mut self, length: usize) {
+        self.emit(&format!("leaq {length}(%rax), %rsi"));
+        self.emit("cmpq memory_size(%rip), %rax");
+        self.emit("ja memory_out_of_bounds");
+        self.emit("cmpq memory_size(%rip), %rsi");
+        self.emit("ja memory_out_of_bounds");
+        self.emit("leaq memory(%rip), %rcx");
+    }
+
+    fn conditional_jump(&mut self, jump: &str, target: usize) {
+        self.require_operands(1);
+        self.emit("popq %rax");
+        self.emit("testq %rax, %rax");
+        self.emit(&format!("{jump} .L{target}"));
+    }
+
+    /// Appends the end of the program, the runtime routines, the messages and the static arrays.
+    fn finish(mut self, end: usize, data: &[u8]) -> String {
+        self.label(&format!(".L{end}"));
+        self.emit("jmp ret_opcode_not_found");
+
+        self.label("program_result");
+        self.emit("cmpq %r12, %rsp");
+        self.emit("jne 1f");
+        self.write_message(1, "PROGRAM RESULT: []\n");
+        self.emit("jmp exit_success");
+        self.label("1");
+        self.write_message(1, "PROGRAM RESULT: [\n");
+        self.emit("movq %r12, %rbx");
+        self.label("2");
+        self.emit("subq $8, %rbx");
+        self.write_message(1, "    ");
+        self.emit("movq (%rbx), %rax");
+        self.emit("movl $1, %edi");
+        self.emit("call write_number");
+        self.write_message(1, ",\n");
+        self.emit("cmpq %rsp, %rbx");
+        self.emit("jne 2b");
+        self.write_message(1, "]\n");
+        self.label("exit_success");
+        self.emit("movl $60, %eax");
+        self.emit("xorl %edi, %edi");
+        self.emit("syscall");
+
+        let errors = [
+            (
+                "ret_opcode_not_found",
+                VmError::RetOpcodeNotFound.to_string(),
+            ),
+            (
+                "no_address_in_call_stack",
+                VmError::NoAddressInCallStack.to_string(),
+            ),
+            (
+                "call_stack_overflow",
+                VmError::CallStackOverflow.to_string(),
+            ),
+            (
+                "no_handler_in_handler_stack",
+                VmError::NoHandlerInHandlerStack.to_string(),
+            ),
+            ("handler_stack_full", HANDLER_STACK_FULL.to_string()),
+        ];
+
+        for (label, message) in errors {
+            self.label(label);
+            self.write_message(2, &format!("{message}\n"));
+            self.emit("jmp exit_failure");
+        }
+
+        // A handler catches these errors with the stack left as the virtual machine leaves it,
+        // which is empty when there is no value and without both operands of a division by zero.
+        let catchable_errors = [
+            (
+                "no_value_in_stack",
+                "movq %r12, %rsp",
+                VmError::NoValueInStack,
+            ),
+            ("division_by_zero", "addq $8, %rsp", VmError::DivisionByZero),
+        ];
+
+        for (label, drop, error) in catchable_errors {
+            self.label(label);
+            self.emit("leaq handlers(%rip), %rcx");
+            self.emit("cmpq %rcx, %r15");
+            self.emit("je 1f");
+            self.emit(drop);
+            self.emit(&format!("movq ${}, %rax", error.code().unwrap_or_default()));
+            self.emit("jmp catch");
+            self.label("1");
+            self.write_message(2, &format!("{error}\n"));
+            self.emit("jmp exit_failure");
+        }
+
+        // Pops the innermost handler, cuts the stacks back to it and pushes the value in `%rax`.
+        self.label("catch");
+        self.emit(&format!("subq ${HANDLER_SIZE}, %r15"));
+        self.emit("movq 8(%r15), %rcx");
+        self.emit("cmpq %rcx, %rsp");
+        self.emit("jae 1f");
+        self.emit("movq %rcx, %rsp");
+        self.label("1");
+        self.emit("movq 16(%r15), %rcx");
+        self.emit("cmpq %rcx, %r13");
+        self.emit("jbe 2f");
+        self.emit("movq %rcx, %r13");
+        self.label("2");
+        self.emit("pushq %rax");
+        self.emit("jmp *(%r15)");
+
+        // The value in `%rax` and the address in `%rbx` are printed between the three parts of the message.
+        let message = VmError::UncaughtThrow {
+            value: 0,
+            address: 0,
+        }
+        .to_string();
+        let parts: Vec<&str> = message.splitn(3, '0').collect();
+        let (first, second, third) = (
+            parts[0].to_string(),
+            parts.get(1).unwrap_or(&"").to_string(),
+            format!("{}\n", parts.get(2).unwrap_or(&"")),
+        );
+        self.label("throw");
+        self.emit("leaq handlers(%rip), %rcx");
+        self.emit("cmpq %rcx, %r15");
+        self.emit("jne catch");
+        self.emit("movq %rax, %r14");
+        self.write_message(2, &first);
+        self.emit("movq %r14, %rax");
+        self.emit("movl $2, %edi");
+        self.emit("call write_number");
+        self.write_message(2, &second);
+        self.emit("movq %rbx, %rax");
+        self.emit("movl $2, %edi");
+        self.emit("call write_number");
+        self.write_message(2, &third);
+        self.emit("jmp exit_failure");
+
+        for index in std::mem::take(&mut self.underflows) {
+            let message = self.messages[index].clone();
+            self.label(&format!(".Lunderflow{index}"));
+            self.write_message(2, &message);
+            self.emit("movq %r12, %rax");
+            self.emit("subq %rsp, %rax");
+            self.emit("shrq $3, %rax");
+            self.emit("movl $2, %edi");
+            self.emit("call write_number");
+            self.write_message(2, "\n");
+            self.emit("jmp exit_failure");
+        }
+
+        // The amount is printed at runtime between the two halves of the message.
+        let message = VmError::InvalidShiftAmount(0).to_string();
+        let (before, after) = message.split_once('0').unwrap_or((&message, ""));
+        let (before, after) = (before.to_string(), format!("{after}\n"));
+        self.label("invalid_shift_amount");
+        self.emit("movq %rcx, %rbx");
+        self.write_message(2, &before);
+        self.emit("movq %rbx, %rax");
+        self.emit("movl $2, %edi");
+        self.emit("call write_number");
+        self.write_message(2, &after);
+        self.emit("jmp exit_failure");
+
+        // The length and the address are printed at runtime between the three parts of the message.
+        let message = VmError::MemoryOutOfBounds { addr: 0, len: 0 }.to_string();
+        let parts: Vec<&str> = message.splitn(3, '0').collect();
+        let (first, second, third) = (
+            parts[0].to_string(),
+            parts.get(1).unwrap_or(&"").to_string(),
+            format!("{}\n", parts.get(2).unwrap_or(&"")),
+        );
+        self.label("memory_out_of_bounds");
+        self.emit("movq %rax, %rbx");
+        self.emit("subq %rax, %rsi");
+        self.emit("movq %rsi, %r14");
+        self.write_message(2, &first);
+        self.emit("movq %r14, %rax");
+        self.emit("movl $2, %edi");
+        self.emit("call write_number");
+        self.write_message(2, &second);
+        self.emit("movq %rbx, %rax");
+        self.emit("movl $2, %edi");
+        self.emit("call write_number");
+        self.write_message(2, &third);
+
+        self.label("exit_failure");
+        self.emit("movl $60, %eax");
+        self.emit("movl $1, %edi");
+        self.emit("syscall");
+
+        self.text.push_str(RUNTIME);
+
+        self.text.push_str(".section .rodata\n");
+        for (index, message) in self.messages.iter().enumerate() {
+            self.text.push_str(&format!(
+                ".Lmessage{index}:\n    .ascii \"{}\"\n",
+                escape(message)
+            ));
+        }
+
+        if !data.is_empty() {
+            let bytes: Vec<String> = data.iter().map(|byte| byte.to_string()).collect();
+            self.text
+                .push_str(&format!("memory_data:\n    .byte {}\n", bytes.join(", ")));
+        }
+
+        self.text.push_str(".data\n.align 8\n");
+        self.text
+            .push_str(&format!("memory_size:\n    .quad {}\n", data.len()));
+
+        self.text.push_str(".bss\n.align 8\n");
+        self.text.push_str(&format!(
+            "registers:\n    .zero {}\n",
+            (u8::MAX as usize + 1) * 8
+        ));
+        self.text
+            .push_str(&format!("call_stack:\n    .zero {}\n", MAX_CALL_DEPTH * 8));
+        self.text.push_str("call_stack_end:\n");
+        self.text.push_str(&format!(
+            "handlers:\n    .zero {}\n",
+            HANDLER_STACK_SIZE * HANDLER_SIZE
+        ));
+        self.text.push_str("handlers_end:\n");
+        self.text
+            .push_str(&format!("memory:\n    .zero {MEMORY_LIMIT}\n"));
+
+        self.text
+    }
+}
+
+/// Routines that write bytes and decimal numbers to a file descriptor.
+const RUNTIME: &str = "\
+# Writes %rdx bytes at %rsi to the file descriptor in %rdi.
+write_bytes:
+    movl $1, %eax
+    syscall
+    ret
+# Writes the number in %rax in decimal to the file descriptor in %rdi.
+write_number:
+    subq $32, %rsp
+    movq %rax, %r8
+    testq %rax, %rax
+    jns 1f
+    negq %rax
+1:
+    leaq 32(%rsp), %rsi
+    movq $10, %rcx
+2:
+    xorl %edx, %edx
+    divq %rcx
+    addb $48, %dl
+    decq %rsi
+    movb %dl, (%rsi)
+    testq %rax, %rax
+    jnz 2b
+    testq %r8, %r8
+    jns 3f
+    decq %rsi
+    movb $45, (%rsi)
+3:
+    leaq 32(%rsp), %rdx
+    subq %rsi, %rdx
+    call write_bytes
+    addq $32, %rsp
+    ret
+";
+
+/// Escapes a message for an `.ascii` directive.
+fn escape(message: &str) -> String {
+    message
+        .chars()
+        .map(|char| match char {
+            '\n' => "\\n".to_string(),
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            _ => char.to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_generating_x86_64() {
+    use crate::{
+        compiler::compile, lexer::tokenize, parser::parse, virtual_machine::VirtualMachine,
+    };
+    use std::process::Command;
+
+    if Command::new("as").arg("--version").output().is_err() {
+        return;
+    }
+
+    let directory = std::env::temp_dir().join(format!("x86_64-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let programs = [
+        include_str!("../../examples/adding.code"),
+        include_str!("../../examples/complex.code"),
+        include_str!("../../examples/loop.code"),
+        "RET",
+        "PUSH -9223372036854775808 PUSH -1 DIV PUSH -7 PUSH 2 MOD PUSH 5000000000 RET",
+        "PUSH 1 PUSH 2 PUSH 3 ROT TUCK OVER SWAP NIP PICK 2 DEPTH DUP RET",
+        "PUSH -16 PUSH 2 SHR PUSH -1 PUSH 60 USHR PUSH 1 PUSH 63 ROTR PUSH 255 POPCNT BNOT RET",
+        "PUSH 6 CALL square PUSH 1 JZ end PUSH 7 end: RET square: DUP MUL RETURN",
+        "f: CALL f RET",
+        "PUSH 1 PUSH 0 DIV RET",
+        "PUSH -1 PUSH 2 LT PUSH 2 PUSH 2 LE PUSH 3 PUSH 2 GT PUSH 2 PUSH 3 GE PUSH 1 PUSH 1 EQ PUSH 1 PUSH 2 NE RET",
+        "PUSH 1 PUSH 2 PUSH 3 PUSH 4 PUT 2 RET",
+        "PUSH 1 PUT 1 RET",
+        "PUSH 1 PICK 3 RET",
+        "PUSH 1 PUSH 64 SHL RET",
+        "PUSH 1 PUSH -1 SHL RET",
+        "PUSH -1 LOAD8 RET",
+        "PUSH 8 MEMGROW PUSH 1 PUSH 1 STORE64 RET",
+        "MEMSIZE PUSH 1048576 MEMGROW PUSH 1 MEMGROW PUSH -1 MEMGROW MEMSIZE RET",
+        "PUSH 16 MEMGROW PUSH 8 PUSH -2 STORE64 PUSH 8 LOAD64 PUSH 8 PUSH 300 STORE8 PUSH 8 LOAD64 PUSH 15 LOAD8 RET",
+        ".data\nvalues: .quad 5 -7\ntext: .ascii \"hi\"\n.text\nPUSH values PUSH 8 ADD LOAD64 PUSH text LOAD8 MEMSIZE RET",
+        include_str!("../../examples/exceptions.code"),
+        "PUSH 1 TRY h PUSH 2 PUSH 3 THROW h: RET",
+        "TRY outer TRY inner PUSH 1 THROW inner: PUSH 2 ADD THROW outer: RET",
+        "PUSH 1 TRY h PUSH 2 CALL f h: RET f: PUSH 3 PUSH 0 MOD RETURN",
+        "TRY h POP RET h: RET",
+        "PUSH 5 THROW RET",
+        "THROW RET",
+        "ENDTRY RET",
+        "ADD RET",
+        "RETURN",
+        "PUSH 1",
+    ];
+
+    for (index, program) in programs.iter().enumerate() {
+        let bytecode = compile(parse(tokenize(program)).unwrap());
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        let instructions = virtual_machine.decode().unwrap().to_vec();
+        let assembly = generate(&instructions, virtual_machine.memory());
+
+        let path = directory.join(format!("program{index}"));
+        std::fs::write(path.with_extension("s"), assembly).unwrap();
+
+        let status = Command::new("as")
+            .arg(path.with_extension("s"))
+            .arg("-o")
+            .arg(path.with_extension("o"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let status = Command::new("ld")
+            .arg(path.with_extension("o"))
+            .arg("-o")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(&path).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        match virtual_machine.run() {
+            Ok(result) => {
+                assert_eq!(
+                    stdout,
+                    format!("PROGRAM RESULT: {:#?}\n", result),
+                    "{program}"
+                );
+                assert_eq!(output.status.code(), Some(0));
+            }
+            Err(error) => {
+                assert_eq!(stderr, format!("{error}\n"), "{program}");
+                assert_eq!(output.status.code(), Some(1));
+            }
+        }
+    }
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,96 @@
+use std::ops::Range;
+
+use crate::error::VmError;
+
+/// Marks the start of a bytecode header.
+/// `0xFF` is never a valid opcode, so bytecode without a header can still be recognized.
+pub const MAGIC: [u8; 4] = [0xFF, b'B', b'C', b'V'];
+
+/// The format of bytecode files created before the header was introduced.
+/// `SUB`, `DIV` and `MOD` compute "top OP second-from-top" in this format.
+pub const LEGACY_VERSION: u8 = 0;
+
+/// The first format with a data section after the header.
+/// The section is the length of the data as a little-endian `u32` followed by the data.
+pub const DATA_VERSION: u8 = 2;
+
+/// The format created by the compiler.
+/// `SUB`, `DIV` and `MOD` compute "second-from-top OP top" in this format.
+pub const CURRENT_VERSION: u8 = DATA_VERSION;
+
+/// The size of a bytecode header in bytes.
+pub const HEADER_SIZE: usize = MAGIC.len() + 1;
+
+/// Writes a header for the current format version, followed by the data section.
+pub fn write_header(bytecode: &mut Vec<u8>, data: &[u8]) {
+    bytecode.extend_from_slice(&MAGIC);
+    bytecode.push(CURRENT_VERSION);
+    bytecode.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytecode.extend_from_slice(data);
+}
+
+/// Reads the format version of the bytecode and the index its code starts at.
+/// Bytecode without a header is treated as the legacy format.
+pub fn read_header(bytecode: &[u8]) -> Result<(u8, usize), VmError> {
+    if !bytecode.starts_with(&MAGIC) {
+        return Ok((LEGACY_VERSION, 0));
+    }
+
+    match bytecode.get(MAGIC.len()) {
+        Some(&version) if version < DATA_VERSION => Ok((version, HEADER_SIZE)),
+        Some(&version) if version <= CURRENT_VERSION => Ok((version, data_range(bytecode)?.end)),
+        Some(&version) => Err(VmError::UnsupportedVersion(version)),
+        None => Err(VmError::NoVersionInBytecode),
+    }
+}
+
+/// Reads the data that initializes the memory. Formats before `DATA_VERSION` have no data.
+pub fn read_data(bytecode: &[u8]) -> Result<&[u8], VmError> {
+    match read_header(bytecode)? {
+        (version, _) if version >= DATA_VERSION => Ok(&bytecode[data_range(bytecode)?]),
+        _ => Ok(&[]),
+    }
+}
+
+/// Returns where the data of the data section is in the bytecode.
+fn data_range(bytecode: &[u8]) -> Result<Range<usize>, VmError> {
+    let start = HEADER_SIZE + 4;
+    let length = bytecode
+        .get(HEADER_SIZE..start)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .filter(|&length| length <= bytecode.len() - start)
+        .ok_or(VmError::NoDataInBytecode)?;
+
+    Ok(start..start + length)
+}
+
+#[test]
+fn test_header() {
+    let mut bytecode = vec![];
+    write_header(&mut bytecode, &[7, 8]);
+    bytecode.push(9);
+
+    assert_eq!(
+        read_header(&bytecode).unwrap(),
+        (CURRENT_VERSION, HEADER_SIZE + 6)
+    );
+    assert_eq!(read_data(&bytecode).unwrap(), &[7, 8]);
+    assert_eq!(read_header(&[9]).unwrap(), (LEGACY_VERSION, 0));
+    assert_eq!(
+        read_header(&[0xFF, b'B', b'C', b'V', 1, 9]).unwrap(),
+        (1, HEADER_SIZE)
+    );
+    assert_eq!(read_data(&[0xFF, b'B', b'C', b'V', 1, 9]).unwrap(), &[]);
+    assert!(matches!(
+        read_header(&bytecode[..HEADER_SIZE + 5]),
+        Err(VmError::NoDataInBytecode)
+    ));
+    assert!(matches!(
+        read_header(&[0xFF, b'B', b'C', b'V', 200]),
+        Err(VmError::UnsupportedVersion(200))
+    ));
+    assert!(matches!(
+        read_header(&MAGIC),
+        Err(VmError::NoVersionInBytecode)
+    ));
+}
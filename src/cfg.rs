@@ -0,0 +1,151 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::parser::Expression;
+
+/// A sequence of expressions that is only entered at its start and only left at its end.
+#[derive(Debug, PartialEq)]
+pub struct BasicBlock {
+    pub range: Range<usize>,
+    pub successors: Vec<usize>,
+}
+
+/// A struct that represents the control flow graph of a program.
+/// The first block is the entry of the program, and the blocks of exported labels are entries too.
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub entries: Vec<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Splits expressions into basic blocks and connects them.
+    pub fn new(expressions: &[Expression]) -> Self {
+        let mut starts = vec![0];
+
+        for (index, expression) in expressions.iter().enumerate() {
+            match expression {
+                Expression::LABEL(_) => starts.push(index),
+                Expression::JMP(_)
+                | Expression::JZ(_)
+                | Expression::JNZ(_)
+                | Expression::CALL(_)
+                | Expression::TRY(_)
+                | Expression::SPAWN(_)
+                | Expression::RET
+                | Expression::RETURN
+                | Expression::THROW => starts.push(index + 1),
+                _ => {}
+            }
+        }
+
+        starts.retain(|&start| start < expressions.len());
+        starts.dedup();
+
+        let mut label_blocks: HashMap<&str, usize> = HashMap::new();
+
+        for (block_index, &start) in starts.iter().enumerate() {
+            for expression in &expressions[start..] {
+                match expression {
+                    Expression::LABEL(label) => label_blocks.insert(label, block_index),
+                    _ => break,
+                };
+            }
+        }
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block_index, &start)| {
+                let end = starts
+                    .get(block_index + 1)
+                    .copied()
+                    .unwrap_or(expressions.len());
+                let next_block = Some(block_index + 1).filter(|&next| next < starts.len());
+                let label_block = |label: &String| label_blocks.get(label.as_str()).copied();
+
+                let successors = match &expressions[end - 1] {
+                    Expression::JMP(label) => vec![label_block(label)],
+                    // The handler of `TRY` is entered when a value is thrown after it, and a fiber of `SPAWN` when it is resumed.
+                    Expression::JZ(label)
+                    | Expression::JNZ(label)
+                    | Expression::CALL(label)
+                    | Expression::TRY(label)
+                    | Expression::SPAWN(label) => vec![label_block(label), next_block],
+                    Expression::RET | Expression::RETURN | Expression::THROW => vec![],
+                    _ => vec![next_block],
+                };
+
+                BasicBlock {
+                    range: start..end,
+                    successors: successors.into_iter().flatten().collect(),
+                }
+            })
+            .collect();
+
+        let mut entries = vec![0];
+        entries.extend(
+            expressions
+                .iter()
+                .filter_map(|expression| match expression {
+                    Expression::GLOBAL(label) => label_blocks.get(label.as_str()).copied(),
+                    _ => None,
+                }),
+        );
+
+        Self { blocks, entries }
+    }
+
+    /// Returns which blocks can be reached from the entries of the program.
+    pub fn reachable_blocks(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = self.entries.clone();
+
+        while let Some(block_index) = pending.pop() {
+            if block_index >= self.blocks.len() || reachable[block_index] {
+                continue;
+            }
+
+            reachable[block_index] = true;
+            pending.extend_from_slice(&self.blocks[block_index].successors);
+        }
+
+        reachable
+    }
+}
+
+#[test]
+fn test_control_flow_graph() {
+    let expressions = vec![
+        Expression::PUSH(1),
+        Expression::JZ("end".to_string()),
+        Expression::JMP("end".to_string()),
+        Expression::PUSH(2),
+        Expression::LABEL("end".to_string()),
+        Expression::RET,
+    ];
+
+    let graph = ControlFlowGraph::new(&expressions);
+
+    assert_eq!(
+        graph.blocks,
+        &[
+            BasicBlock {
+                range: 0..2,
+                successors: vec![3, 1],
+            },
+            BasicBlock {
+                range: 2..3,
+                successors: vec![3],
+            },
+            BasicBlock {
+                range: 3..4,
+                successors: vec![3],
+            },
+            BasicBlock {
+                range: 4..6,
+                successors: vec![],
+            },
+        ]
+    );
+    assert_eq!(graph.reachable_blocks(), &[true, true, false, true]);
+}
@@ -1,14 +1,44 @@
-use crate::{opcode::Opcode, parser::Expression};
+use std::collections::HashMap;
+
+use crate::{
+    linker::link,
+    object::{ObjectFile, Relocation, RelocationTarget},
+    opcode::Opcode,
+    parser::Expression,
+};
 
 /// Compiles expressions to bytecode.
+/// Labels are expected to be validated by the parser.
 pub fn compile(expressions: Vec<Expression>) -> Vec<u8> {
+    link(&[compile_object(expressions)]).expect("every label is defined")
+}
+
+/// Compiles expressions to an object file.
+/// Labels that are referred to but not defined are imported, and labels of `.global` directives are exported.
+pub fn compile_object(expressions: Vec<Expression>) -> ObjectFile {
     let mut bytecode: Vec<u8> = vec![];
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut label_references: Vec<(usize, String)> = vec![];
+    let mut global_labels: Vec<String> = vec![];
+    let mut data: Vec<u8> = vec![];
 
     for expression in expressions {
         match expression {
             Expression::PUSH(value) => {
-                bytecode.push(Opcode::PUSH.into());
-                bytecode.extend_from_slice(&value.to_le_bytes());
+                // The smallest encoding that holds the value is used.
+                if let Ok(value) = i8::try_from(value) {
+                    bytecode.push(Opcode::PUSH8.into());
+                    bytecode.extend_from_slice(&value.to_le_bytes());
+                } else if let Ok(value) = i16::try_from(value) {
+                    bytecode.push(Opcode::PUSH16.into());
+                    bytecode.extend_from_slice(&value.to_le_bytes());
+                } else if let Ok(value) = i32::try_from(value) {
+                    bytecode.push(Opcode::PUSH32.into());
+                    bytecode.extend_from_slice(&value.to_le_bytes());
+                } else {
+                    bytecode.push(Opcode::PUSH.into());
+                    bytecode.extend_from_slice(&value.to_le_bytes());
+                }
             }
             Expression::POP => bytecode.push(Opcode::POP.into()),
             Expression::STORE(index) => {
@@ -25,10 +55,125 @@ pub fn compile(expressions: Vec<Expression>) -> Vec<u8> {
             Expression::DIV => bytecode.push(Opcode::DIV.into()),
             Expression::MOD => bytecode.push(Opcode::MOD.into()),
             Expression::RET => bytecode.push(Opcode::RET.into()),
+            Expression::DUP => bytecode.push(Opcode::DUP.into()),
+            Expression::SWAP => bytecode.push(Opcode::SWAP.into()),
+            Expression::OVER => bytecode.push(Opcode::OVER.into()),
+            Expression::ROT => bytecode.push(Opcode::ROT.into()),
+            Expression::NIP => bytecode.push(Opcode::NIP.into()),
+            Expression::TUCK => bytecode.push(Opcode::TUCK.into()),
+            Expression::PICK(index) => {
+                bytecode.push(Opcode::PICK.into());
+                bytecode.push(index);
+            }
+            Expression::DEPTH => bytecode.push(Opcode::DEPTH.into()),
+            Expression::BAND => bytecode.push(Opcode::BAND.into()),
+            Expression::BOR => bytecode.push(Opcode::BOR.into()),
+            Expression::BXOR => bytecode.push(Opcode::BXOR.into()),
+            Expression::BNOT => bytecode.push(Opcode::BNOT.into()),
+            Expression::SHL => bytecode.push(Opcode::SHL.into()),
+            Expression::SHR => bytecode.push(Opcode::SHR.into()),
+            Expression::USHR => bytecode.push(Opcode::USHR.into()),
+            Expression::ROTL => bytecode.push(Opcode::ROTL.into()),
+            Expression::ROTR => bytecode.push(Opcode::ROTR.into()),
+            Expression::POPCNT => bytecode.push(Opcode::POPCNT.into()),
+            Expression::EQ => bytecode.push(Opcode::EQ.into()),
+            Expression::NE => bytecode.push(Opcode::NE.into()),
+            Expression::LT => bytecode.push(Opcode::LT.into()),
+            Expression::LE => bytecode.push(Opcode::LE.into()),
+            Expression::GT => bytecode.push(Opcode::GT.into()),
+            Expression::GE => bytecode.push(Opcode::GE.into()),
+            Expression::PUT(index) => {
+                bytecode.push(Opcode::PUT.into());
+                bytecode.push(index);
+            }
+            Expression::LOAD8 => bytecode.push(Opcode::LOAD8.into()),
+            Expression::LOAD64 => bytecode.push(Opcode::LOAD64.into()),
+            Expression::STORE8 => bytecode.push(Opcode::STORE8.into()),
+            Expression::STORE64 => bytecode.push(Opcode::STORE64.into()),
+            Expression::MEMSIZE => bytecode.push(Opcode::MEMSIZE.into()),
+            Expression::MEMGROW => bytecode.push(Opcode::MEMGROW.into()),
+            Expression::DATA(bytes) => data.extend(bytes),
+            Expression::LABEL(label) => {
+                labels.insert(label, bytecode.len() as u32);
+            }
+            Expression::GLOBAL(label) => global_labels.push(label),
+            Expression::JMP(label) => {
+                bytecode.push(Opcode::JMP.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::JZ(label) => {
+                bytecode.push(Opcode::JZ.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::JNZ(label) => {
+                bytecode.push(Opcode::JNZ.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::CALL(label) => {
+                bytecode.push(Opcode::CALL.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::RETURN => bytecode.push(Opcode::RETURN.into()),
+            Expression::TRY(label) => {
+                bytecode.push(Opcode::TRY.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::THROW => bytecode.push(Opcode::THROW.into()),
+            Expression::ENDTRY => bytecode.push(Opcode::ENDTRY.into()),
+            Expression::SPAWN(label) => {
+                bytecode.push(Opcode::SPAWN.into());
+                label_references.push((bytecode.len(), label));
+                bytecode.extend_from_slice(&[0; 4]);
+            }
+            Expression::YIELD => bytecode.push(Opcode::YIELD.into()),
+            Expression::RESUME => bytecode.push(Opcode::RESUME.into()),
+            Expression::FIBERSTATUS => bytecode.push(Opcode::FIBERSTATUS.into()),
+            Expression::SEND => bytecode.push(Opcode::SEND.into()),
+            Expression::RECV => bytecode.push(Opcode::RECV.into()),
+        }
+    }
+
+    let mut object = ObjectFile::default();
+
+    for (position, label) in label_references {
+        let target = match labels.get(&label) {
+            Some(address) => {
+                bytecode[position..position + 4].copy_from_slice(&address.to_le_bytes());
+                RelocationTarget::Local
+            }
+            None => {
+                let index = match object.imports.iter().position(|import| *import == label) {
+                    Some(index) => index,
+                    None => {
+                        object.imports.push(label);
+                        object.imports.len() - 1
+                    }
+                };
+                RelocationTarget::Import(index as u32)
+            }
+        };
+
+        object.relocations.push(Relocation {
+            offset: position as u32,
+            target,
+        });
+    }
+
+    for label in global_labels {
+        if !object.exports.iter().any(|(name, _)| *name == label) {
+            let offset = labels[&label];
+            object.exports.push((label, offset));
         }
     }
 
-    bytecode
+    object.code = bytecode;
+    object.data = data;
+    object
 }
 
 #[test]
@@ -56,9 +201,57 @@ fn test_compiling() {
     assert_eq!(
         &bytecode,
         &[
-            0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 40, 0, 0, 0, 0, 0, 0, 0, 4, 2, 0, 0, 6, 0, 0, 0, 0, 0,
-            0, 0, 0, 254, 255, 255, 255, 255, 255, 255, 255, 5, 2, 1, 0, 10, 0, 0, 0, 0, 0, 0, 0,
-            0, 20, 0, 0, 0, 0, 0, 0, 0, 7, 3, 0, 3, 1, 6, 9
+            255, 66, 67, 86, 2, 0, 0, 0, 0, 33, 10, 33, 40, 4, 2, 0, 33, 6, 33, 254, 5, 2, 1, 33,
+            10, 33, 20, 7, 3, 0, 3, 1, 6, 9
+        ]
+    );
+}
+
+#[test]
+fn test_compiling_labels() {
+    let expressions = vec![
+        Expression::LABEL("start".to_string()),
+        Expression::CALL("end".to_string()),
+        Expression::JNZ("start".to_string()),
+        Expression::LABEL("end".to_string()),
+        Expression::RETURN,
+    ];
+
+    let bytecode = compile(expressions);
+
+    assert_eq!(
+        &bytecode,
+        &[255, 66, 67, 86, 2, 0, 0, 0, 0, 31, 19, 0, 0, 0, 30, 9, 0, 0, 0, 32]
+    );
+}
+
+#[test]
+fn test_compiling_compact_push() {
+    let expressions = vec![
+        Expression::PUSH(-128),
+        Expression::PUSH(128),
+        Expression::PUSH(-40_000),
+        Expression::PUSH(1 << 40),
+    ];
+
+    let bytecode = compile(expressions);
+
+    assert_eq!(
+        &bytecode,
+        &[
+            255, 66, 67, 86, 2, 0, 0, 0, 0, 33, 128, 34, 128, 0, 35, 192, 99, 255, 255, 0, 0, 0, 0,
+            0, 0, 1, 0, 0
         ]
     );
+
+    // Without compact encodings `adding.code` would be 29 bytes and `complex.code` would be 53 bytes.
+    for (source_code, size) in [
+        (include_str!("../examples/adding.code"), 15),
+        (include_str!("../examples/complex.code"), 25),
+    ] {
+        let tokens = crate::lexer::tokenize(source_code);
+        let expressions = crate::parser::parse(tokens).unwrap();
+
+        assert_eq!(compile(expressions).len(), size);
+    }
 }
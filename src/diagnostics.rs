@@ -0,0 +1,115 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::{cfg::ControlFlowGraph, parser::Expression};
+
+/// A problem in a program that doesn't stop it from compiling or running.
+#[derive(Debug, PartialEq)]
+pub enum Warning {
+    UnreachableCode { first: Expression, count: usize },
+    RegisterNeverLoaded(u8),
+    RegisterNeverStored(u8),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnreachableCode { first, count: 1 } => {
+                write!(f, "WARNING: `{first}` is unreachable")
+            }
+            Warning::UnreachableCode { first, count } => write!(
+                f,
+                "WARNING: {count} expressions starting with `{first}` are unreachable"
+            ),
+            Warning::RegisterNeverLoaded(index) => {
+                write!(f, "WARNING: register `{index}` is stored but never loaded")
+            }
+            Warning::RegisterNeverStored(index) => {
+                write!(f, "WARNING: register `{index}` is loaded but never stored")
+            }
+        }
+    }
+}
+
+/// Checks expressions for unreachable code and registers that are only stored or only loaded.
+pub fn check(expressions: &[Expression]) -> Vec<Warning> {
+    let mut warnings = vec![];
+
+    let graph = ControlFlowGraph::new(expressions);
+    let reachable = graph.reachable_blocks();
+    let mut unreachable: Vec<&Expression> = vec![];
+
+    for (block, reachable) in graph.blocks.iter().zip(reachable) {
+        if !reachable {
+            unreachable.extend(&expressions[block.range.clone()]);
+            continue;
+        }
+
+        warnings.extend(unreachable_code_warning(&unreachable));
+        unreachable.clear();
+    }
+
+    warnings.extend(unreachable_code_warning(&unreachable));
+
+    let mut stored = BTreeSet::new();
+    let mut loaded = BTreeSet::new();
+
+    for expression in expressions {
+        match expression {
+            Expression::STORE(index) => stored.insert(*index),
+            Expression::LOAD(index) => loaded.insert(*index),
+            _ => false,
+        };
+    }
+
+    for index in stored.difference(&loaded) {
+        warnings.push(Warning::RegisterNeverLoaded(*index));
+    }
+
+    for index in loaded.difference(&stored) {
+        warnings.push(Warning::RegisterNeverStored(*index));
+    }
+
+    warnings
+}
+
+/// Creates a warning for consecutive unreachable expressions. Labels, `.global` directives and data alone are not reported.
+fn unreachable_code_warning(expressions: &[&Expression]) -> Option<Warning> {
+    let first = expressions.iter().position(|expression| {
+        !matches!(
+            expression,
+            Expression::LABEL(_) | Expression::GLOBAL(_) | Expression::DATA(_)
+        )
+    })?;
+
+    Some(Warning::UnreachableCode {
+        first: expressions[first].clone(),
+        count: expressions[first..].len(),
+    })
+}
+
+#[test]
+fn test_checking() {
+    let expressions = vec![
+        Expression::PUSH(1),
+        Expression::STORE(0),
+        Expression::JMP("end".to_string()),
+        Expression::PUSH(2),
+        Expression::LABEL("unused".to_string()),
+        Expression::LOAD(1),
+        Expression::LABEL("end".to_string()),
+        Expression::RET,
+        Expression::LABEL("after".to_string()),
+    ];
+
+    assert_eq!(
+        check(&expressions),
+        &[
+            Warning::UnreachableCode {
+                first: Expression::PUSH(2),
+                count: 3,
+            },
+            Warning::RegisterNeverLoaded(0),
+            Warning::RegisterNeverStored(1),
+        ]
+    );
+}
@@ -1,5 +1,13 @@
 use std::fmt::Display;
 
+use crate::{
+    lang::ast::Type,
+    opcode::Opcode,
+    parser::{Expression, MAX_MACRO_DEPTH},
+    value::Value,
+    virtual_machine::{MAX_CALL_DEPTH, MEMORY_LIMIT, REGISTER_SIZE},
+};
+
 #[derive(Debug)]
 pub enum VmError {
     RetOpcodeNotFound,
@@ -7,6 +15,56 @@ pub enum VmError {
     NoValueInBytecode,
     NoValueInStack,
     InvalidOpcode,
+    StackUnderflow {
+        opcode: Opcode,
+        required: usize,
+        found: usize,
+    },
+    InvalidShiftAmount(Value),
+    DivisionByZero,
+    NoAddressInBytecode,
+    NoAddressInCallStack,
+    NoVersionInBytecode,
+    UnsupportedVersion(u8),
+    InvalidAddress(usize),
+    NoDataInBytecode,
+    DataTooLarge(usize),
+    MemoryOutOfBounds {
+        addr: Value,
+        len: usize,
+    },
+    NoHandlerInHandlerStack,
+    UncaughtThrow {
+        value: Value,
+        address: usize,
+    },
+    InvalidFiber(Value),
+    FiberNotSuspended(Value),
+    NoFiberToYieldTo,
+    NotInPool,
+    InvalidJob(Value),
+    Deadlock,
+    InvalidSnapshot,
+    SnapshotMismatch,
+    ByteAddressSnapshot,
+    Interrupted,
+    InvalidRegister {
+        index: usize,
+        size: usize,
+    },
+    CallStackOverflow,
+}
+
+impl VmError {
+    /// Returns the value that a `TRY` handler receives for the error, or `None` if it can't be caught.
+    pub fn code(&self) -> Option<Value> {
+        match self {
+            Self::UncaughtThrow { value, .. } => Some(*value),
+            Self::NoValueInStack => Some(-1),
+            Self::DivisionByZero => Some(-2),
+            _ => None,
+        }
+    }
 }
 
 impl Display for VmError {
@@ -17,6 +75,88 @@ impl Display for VmError {
             Self::NoValueInBytecode => "there is no value in bytecode",
             Self::NoValueInStack => "there is no value in stack",
             Self::InvalidOpcode => "there is an invalid opcode",
+            Self::DivisionByZero => "there is a division by zero",
+            Self::NoAddressInBytecode => "there is no address in bytecode",
+            Self::NoAddressInCallStack => "there is no address in call stack",
+            Self::NoVersionInBytecode => "there is no version in bytecode header",
+            Self::NoDataInBytecode => "the data section of bytecode is incomplete",
+            Self::NoHandlerInHandlerStack => "there is no handler in handler stack",
+            Self::NoFiberToYieldTo => "there is no fiber to yield to",
+            Self::NotInPool => "values are only sent and received in a pool",
+            Self::Deadlock => "every program in the pool is waiting to receive a value",
+            Self::InvalidSnapshot => "the snapshot is not valid",
+            Self::SnapshotMismatch => "the snapshot is taken from another program",
+            Self::ByteAddressSnapshot => {
+                "the snapshot is taken with the byte-level interpreter and can't be restored"
+            }
+            Self::Interrupted => "the program is interrupted",
+            Self::StackUnderflow {
+                opcode,
+                required,
+                found,
+            } => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: `{opcode:?}` requires {required} values in stack but there are {found}"
+                )
+            }
+            Self::InvalidShiftAmount(amount) => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: `{amount}` is not a valid shift amount, it must be in `0..64`"
+                )
+            }
+            Self::InvalidAddress(address) => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: `{address}` is not the address of an opcode"
+                )
+            }
+            Self::DataTooLarge(length) => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: the data of {length} bytes doesn't fit in the memory of {MEMORY_LIMIT} bytes"
+                )
+            }
+            Self::MemoryOutOfBounds { addr, len } => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: accessing {len} bytes at address `{addr}` is out of bounds of the memory"
+                )
+            }
+            Self::UncaughtThrow { value, address } => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: `{value}` is thrown at address `{address}` and not caught"
+                )
+            }
+            Self::CallStackOverflow => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: there are more than {MAX_CALL_DEPTH} nested calls"
+                )
+            }
+            Self::InvalidRegister { index, size } => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: `{index}` is not a register, registers are numbered below {size}"
+                )
+            }
+            Self::InvalidFiber(fiber) => {
+                return write!(f, "RUNTIME ERROR: `{fiber}` is not a fiber")
+            }
+            Self::InvalidJob(job) => {
+                return write!(f, "RUNTIME ERROR: `{job}` is not a job in the pool")
+            }
+            Self::FiberNotSuspended(fiber) => {
+                return write!(f, "RUNTIME ERROR: fiber `{fiber}` is not suspended")
+            }
+            Self::UnsupportedVersion(version) => {
+                return write!(
+                    f,
+                    "RUNTIME ERROR: bytecode format version `{version}` is not supported"
+                )
+            }
         };
 
         write!(f, "RUNTIME ERROR: {}", msg)
@@ -24,16 +164,64 @@ impl Display for VmError {
 }
 
 #[derive(Debug)]
-pub enum ParseError<'a> {
-    MistakenOpcode(&'a str),
-    OpcodeRequired(&'a str),
-    ValueRequired(&'a str),
-    IndexRequired(&'a str),
-    MistakenValue(&'a str),
-    MistakenIndex(&'a str),
+pub enum BackendError {
+    InvalidBytecode(VmError),
+    UnsupportedOpcode {
+        opcode: Opcode,
+        target: &'static str,
+    },
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidBytecode(error) => write!(f, "{error}"),
+            Self::UnsupportedOpcode { opcode, target } => write!(
+                f,
+                "COMPILING ERROR: `{opcode:?}` is not supported by the `{target}` target"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    MistakenOpcode(String),
+    OpcodeRequired(String),
+    ValueRequired(String),
+    IndexRequired(String),
+    MistakenValue(String),
+    MistakenIndex(String),
+    InvalidRegister(Value),
+    LabelRequired(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    PathRequired,
+    IncludedFileNotFound(String),
+    IncludeCycle(String),
+    MacroNameRequired,
+    MistakenParameter(String),
+    UnclosedMacro(String),
+    NestedMacro(String),
+    DuplicateMacro(String),
+    ArgumentRequired {
+        macro_name: String,
+        parameter: String,
+    },
+    MacroTooDeep(String),
+    MistakenDefinition(String),
+    MistakenExpression(String),
+    InvalidConstant(String),
+    DuplicateName(String),
+    UnknownName {
+        name: String,
+        suggestion: Option<String>,
+    },
+    DataOutsideSection(String),
+    CodeInDataSection(String),
 }
 
-impl<'a> Display for ParseError<'a> {
+impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::MistakenOpcode(mistaken_opcode) => write!(
@@ -58,6 +246,350 @@ impl<'a> Display for ParseError<'a> {
             ParseError::MistakenIndex(index_string) => {
                 write!(f, "PARSING ERROR: `{index_string}` is not a valid index")
             }
+            ParseError::InvalidRegister(index) => write!(
+                f,
+                "PARSING ERROR: `{index}` is not a register, registers are numbered below {REGISTER_SIZE}"
+            ),
+            ParseError::LabelRequired(opcode_string) => write!(
+                f,
+                "PARSING ERROR: a label is required after `{opcode_string}`"
+            ),
+            ParseError::DuplicateLabel(label) => {
+                write!(
+                    f,
+                    "PARSING ERROR: label `{label}` is defined more than once"
+                )
+            }
+            ParseError::UndefinedLabel(label) => {
+                write!(f, "PARSING ERROR: label `{label}` is not defined")
+            }
+            ParseError::PathRequired => {
+                write!(
+                    f,
+                    "PARSING ERROR: a quoted path is required after `.include`"
+                )
+            }
+            ParseError::IncludedFileNotFound(path) => {
+                write!(f, "PARSING ERROR: included file `{path}` is not found")
+            }
+            ParseError::IncludeCycle(path) => {
+                write!(
+                    f,
+                    "PARSING ERROR: `{path}` is included while it is being read"
+                )
+            }
+            ParseError::MacroNameRequired => {
+                write!(f, "PARSING ERROR: a name is required after `.macro`")
+            }
+            ParseError::MistakenParameter(parameter) => {
+                write!(f, "PARSING ERROR: `{parameter}` is not a valid parameter name")
+            }
+            ParseError::UnclosedMacro(name) => {
+                write!(f, "PARSING ERROR: macro `{name}` is not closed with `.endm`")
+            }
+            ParseError::NestedMacro(name) => write!(
+                f,
+                "PARSING ERROR: a macro can't be defined inside macro `{name}`"
+            ),
+            ParseError::DuplicateMacro(name) => {
+                write!(f, "PARSING ERROR: macro `{name}` is defined more than once")
+            }
+            ParseError::ArgumentRequired {
+                macro_name,
+                parameter,
+            } => write!(
+                f,
+                "PARSING ERROR: an argument is required for `{parameter}` of macro `{macro_name}`"
+            ),
+            ParseError::MacroTooDeep(name) => write!(
+                f,
+                "PARSING ERROR: macro `{name}` is used inside more than {MAX_MACRO_DEPTH} macros, it may be recursive"
+            ),
+            ParseError::MistakenDefinition(directive) => write!(
+                f,
+                "PARSING ERROR: `{directive}` must be followed by a name, `=` and an expression"
+            ),
+            ParseError::MistakenExpression(text) => {
+                write!(f, "PARSING ERROR: `{text}` is not a valid constant expression")
+            }
+            ParseError::InvalidConstant(name) => write!(
+                f,
+                "PARSING ERROR: the value of `{name}` can't be computed, because of a division by zero or an invalid shift amount"
+            ),
+            ParseError::DuplicateName(name) => {
+                write!(f, "PARSING ERROR: `{name}` is defined more than once")
+            }
+            ParseError::UnknownName { name, suggestion } => {
+                write!(f, "PARSING ERROR: `{name}` is not defined")?;
+
+                match suggestion {
+                    Some(suggestion) => write!(f, ", did you mean `{suggestion}`?"),
+                    None => Ok(()),
+                }
+            }
+            ParseError::DataOutsideSection(directive) => write!(
+                f,
+                "PARSING ERROR: `{directive}` must be written in a `.data` section"
+            ),
+            ParseError::CodeInDataSection(word) => write!(
+                f,
+                "PARSING ERROR: `{word}` can't be written in a `.data` section, which ends at `.text`"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TranslationError {
+    UnsupportedExpression(Expression),
+    StackUnderflow(Expression),
+    StackDepthMismatch(String),
+    UnknownStackDepth(String),
+    TooManyTemporaries,
+}
+
+impl Display for TranslationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranslationError::UnsupportedExpression(expression) => write!(
+                f,
+                "TRANSLATION ERROR: `{expression}` is not supported by the register machine"
+            ),
+            TranslationError::StackUnderflow(expression) => write!(
+                f,
+                "TRANSLATION ERROR: there are not enough values in stack for `{expression}`"
+            ),
+            TranslationError::StackDepthMismatch(label) => write!(
+                f,
+                "TRANSLATION ERROR: label `{label}` is reached with different stack depths"
+            ),
+            TranslationError::UnknownStackDepth(label) => write!(
+                f,
+                "TRANSLATION ERROR: the stack depth at label `{label}` is not known"
+            ),
+            TranslationError::TooManyTemporaries => write!(
+                f,
+                "TRANSLATION ERROR: the stack is too deep for the register machine"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExprError {
+    UnexpectedCharacter(char),
+    InvalidNumber(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UndefinedVariable(String),
+    TooManyVariables(String),
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::UnexpectedCharacter(char) => {
+                write!(f, "EXPRESSION ERROR: `{char}` is not a valid character")
+            }
+            ExprError::InvalidNumber(number) => {
+                write!(f, "EXPRESSION ERROR: `{number}` is not a valid number")
+            }
+            ExprError::UnexpectedToken(token) => {
+                write!(f, "EXPRESSION ERROR: `{token}` is not expected here")
+            }
+            ExprError::UnexpectedEnd => {
+                write!(f, "EXPRESSION ERROR: the program ends unexpectedly")
+            }
+            ExprError::UndefinedVariable(name) => {
+                write!(f, "EXPRESSION ERROR: variable `{name}` is not defined")
+            }
+            ExprError::TooManyVariables(name) => write!(
+                f,
+                "EXPRESSION ERROR: there is no register left for variable `{name}`"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LangError {
+    UnexpectedCharacter(usize, char),
+    InvalidNumber(usize, String),
+    UnexpectedToken(usize, String),
+    UnexpectedEnd,
+    UnknownType(usize, String),
+    UndefinedVariable(usize, String),
+    UndefinedFunction(usize, String),
+    DuplicateVariable(usize, String),
+    DuplicateFunction(usize, String),
+    WrongArgumentCount {
+        line: usize,
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    MainNotFound,
+    MainHasParameters(usize),
+    TypeMismatch {
+        line: usize,
+        expected: Type,
+        found: Type,
+    },
+    NoValue(usize),
+    MissingReturn(String),
+    UnreachableStatement(usize),
+    TooManyLocals(usize),
+}
+
+impl Display for LangError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LangError::UnexpectedCharacter(line, char) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: `{char}` is not a valid character"
+            ),
+            LangError::InvalidNumber(line, number) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: `{number}` is not a valid number"
+            ),
+            LangError::UnexpectedToken(line, token) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: `{token}` is not expected here"
+            ),
+            LangError::UnexpectedEnd => {
+                write!(f, "LANGUAGE ERROR: the program ends unexpectedly")
+            }
+            LangError::UnknownType(line, name) => {
+                write!(f, "LANGUAGE ERROR: line {line}: `{name}` is not a type")
+            }
+            LangError::UndefinedVariable(line, name) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: variable `{name}` is not defined"
+            ),
+            LangError::UndefinedFunction(line, name) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: function `{name}` is not defined"
+            ),
+            LangError::DuplicateVariable(line, name) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: variable `{name}` is already defined in this block"
+            ),
+            LangError::DuplicateFunction(line, name) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: function `{name}` is already defined"
+            ),
+            LangError::WrongArgumentCount {
+                line,
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: function `{function}` takes {expected} arguments, but {found} are given"
+            ),
+            LangError::MainNotFound => {
+                write!(f, "LANGUAGE ERROR: there is no `main` function")
+            }
+            LangError::MainHasParameters(line) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: `main` can't have parameters"
+            ),
+            LangError::TypeMismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: `{expected}` is expected, but `{found}` is found"
+            ),
+            LangError::NoValue(line) => {
+                write!(f, "LANGUAGE ERROR: line {line}: the expression has no value")
+            }
+            LangError::MissingReturn(function) => write!(
+                f,
+                "LANGUAGE ERROR: function `{function}` can end without returning a value"
+            ),
+            LangError::UnreachableStatement(line) => {
+                write!(f, "LANGUAGE ERROR: line {line}: the statement is never reached")
+            }
+            LangError::TooManyLocals(line) => write!(
+                f,
+                "LANGUAGE ERROR: line {line}: the variable is too deep in the stack to be reached"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ForthError {
+    UnknownWord(String),
+    UnexpectedWord(String),
+    UnexpectedEnd,
+    Unclosed(String),
+    InvalidNumber(String),
+    AccessRequired(String),
+    TooManyVariables(String),
+}
+
+impl Display for ForthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForthError::UnknownWord(word) => {
+                write!(f, "FORTH ERROR: `{word}` is not a defined word")
+            }
+            ForthError::UnexpectedWord(word) => {
+                write!(f, "FORTH ERROR: `{word}` is not expected here")
+            }
+            ForthError::UnexpectedEnd => {
+                write!(f, "FORTH ERROR: the program ends unexpectedly")
+            }
+            ForthError::Unclosed(word) => write!(f, "FORTH ERROR: `{word}` is not closed"),
+            ForthError::InvalidNumber(number) => {
+                write!(f, "FORTH ERROR: `{number}` is not a valid number")
+            }
+            ForthError::AccessRequired(name) => write!(
+                f,
+                "FORTH ERROR: variable `{name}` must be followed by `@`, `!` or `+!`"
+            ),
+            ForthError::TooManyVariables(name) => write!(
+                f,
+                "FORTH ERROR: there is no register left for variable `{name}`"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    NotAnObjectFile(String),
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    SeveralDataSections,
+    ProgramTooLarge,
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::NotAnObjectFile(file_name) => {
+                write!(f, "LINKING ERROR: `{file_name}` is not an object file")
+            }
+            LinkError::DuplicateSymbol(symbol) => write!(
+                f,
+                "LINKING ERROR: symbol `{symbol}` is exported by more than one object file"
+            ),
+            LinkError::UndefinedSymbol(symbol) => write!(
+                f,
+                "LINKING ERROR: symbol `{symbol}` is used but no object file exports it"
+            ),
+            LinkError::SeveralDataSections => write!(
+                f,
+                "LINKING ERROR: more than one object file has a data section"
+            ),
+            LinkError::ProgramTooLarge => write!(
+                f,
+                "LINKING ERROR: the program is too large for its addresses to fit in 4 bytes"
+            ),
         }
     }
 }
@@ -65,6 +597,11 @@ impl<'a> Display for ParseError<'a> {
 pub enum UserError<'a> {
     FileNotFound(&'a str),
     NoFilenameGiven,
+    UnknownOption(&'a str),
+    TargetRequired,
+    UnknownTarget(&'a str),
+    ConflictingOptions(&'a str, &'a str),
+    NumberRequired(&'a str),
 }
 
 impl<'a> Display for UserError<'a> {
@@ -76,6 +613,21 @@ impl<'a> Display for UserError<'a> {
             UserError::NoFilenameGiven => {
                 write!(f, "USER ERROR: no file name is given")
             }
+            UserError::UnknownOption(option) => {
+                write!(f, "USER ERROR: `{option}` is not a known option")
+            }
+            UserError::TargetRequired => {
+                write!(f, "USER ERROR: a target is required after `--target`")
+            }
+            UserError::UnknownTarget(target) => {
+                write!(f, "USER ERROR: `{target}` is not a known target")
+            }
+            UserError::ConflictingOptions(option, other) => {
+                write!(f, "USER ERROR: `{option}` can't be used with `{other}`")
+            }
+            UserError::NumberRequired(option) => {
+                write!(f, "USER ERROR: a number is required after `{option}`")
+            }
         }
     }
 }
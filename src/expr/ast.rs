@@ -0,0 +1,69 @@
+use crate::value::Value;
+
+/// A statement of an `.expr` file. The value of an expression statement is left in the stack.
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    Let(String, Expr),
+    Expression(Expr),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Number(Value),
+    Variable(String),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Ushr,
+}
+
+impl BinaryOperator {
+    /// Finds the operator written like `op`.
+    pub fn from_symbol(op: &str) -> Option<Self> {
+        match op {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            "%" => Some(Self::Mod),
+            "&" => Some(Self::And),
+            "|" => Some(Self::Or),
+            "^" => Some(Self::Xor),
+            "<<" => Some(Self::Shl),
+            ">>" => Some(Self::Shr),
+            ">>>" => Some(Self::Ushr),
+            _ => None,
+        }
+    }
+
+    /// Returns how tightly the operator binds. Operators with a higher precedence are applied first.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::Xor => 2,
+            Self::And => 3,
+            Self::Shl | Self::Shr | Self::Ushr => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::Mod => 6,
+        }
+    }
+}
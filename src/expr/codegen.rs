@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::ExprError,
+    expr::ast::{BinaryOperator, Expr, Statement, UnaryOperator},
+    parser::Expression,
+    virtual_machine::REGISTER_SIZE,
+};
+
+/// Generates stack machine expressions. Every variable gets its own register.
+struct Generator {
+    registers: HashMap<String, u8>,
+    expressions: Vec<Expression>,
+}
+
+impl Generator {
+    fn expression(&mut self, expr: &Expr) -> Result<(), ExprError> {
+        match expr {
+            Expr::Number(value) => self.expressions.push(Expression::PUSH(*value)),
+            Expr::Variable(name) => {
+                let register = self
+                    .registers
+                    .get(name)
+                    .ok_or_else(|| ExprError::UndefinedVariable(name.clone()))?;
+                self.expressions.push(Expression::LOAD(*register));
+            }
+            Expr::Unary(UnaryOperator::Negate, operand) => match **operand {
+                Expr::Number(value) => self
+                    .expressions
+                    .push(Expression::PUSH(value.wrapping_neg())),
+                _ => {
+                    self.expressions.push(Expression::PUSH(0));
+                    self.expression(operand)?;
+                    self.expressions.push(Expression::SUB);
+                }
+            },
+            Expr::Unary(UnaryOperator::Not, operand) => {
+                self.expression(operand)?;
+                self.expressions.push(Expression::BNOT);
+            }
+            Expr::Binary(operator, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.expressions.push(match operator {
+                    BinaryOperator::Add => Expression::ADD,
+                    BinaryOperator::Sub => Expression::SUB,
+                    BinaryOperator::Mul => Expression::MUL,
+                    BinaryOperator::Div => Expression::DIV,
+                    BinaryOperator::Mod => Expression::MOD,
+                    BinaryOperator::And => Expression::BAND,
+                    BinaryOperator::Or => Expression::BOR,
+                    BinaryOperator::Xor => Expression::BXOR,
+                    BinaryOperator::Shl => Expression::SHL,
+                    BinaryOperator::Shr => Expression::SHR,
+                    BinaryOperator::Ushr => Expression::USHR,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register of a variable, allocating the next free one for a new variable.
+    fn allocate(&mut self, name: &str) -> Result<u8, ExprError> {
+        if let Some(&register) = self.registers.get(name) {
+            return Ok(register);
+        }
+
+        if self.registers.len() >= REGISTER_SIZE {
+            return Err(ExprError::TooManyVariables(name.to_string()));
+        }
+
+        let register = self.registers.len() as u8;
+        self.registers.insert(name.to_string(), register);
+        Ok(register)
+    }
+}
+
+/// Generates expressions that leave the value of every expression statement in the stack and return it.
+pub fn generate(statements: &[Statement]) -> Result<Vec<Expression>, ExprError> {
+    let mut generator = Generator {
+        registers: HashMap::new(),
+        expressions: vec![],
+    };
+
+    for statement in statements {
+        match statement {
+            Statement::Let(name, expr) => {
+                generator.expression(expr)?;
+                let register = generator.allocate(name)?;
+                generator.expressions.push(Expression::STORE(register));
+            }
+            Statement::Expression(expr) => generator.expression(expr)?,
+        }
+    }
+
+    generator.expressions.push(Expression::RET);
+
+    Ok(generator.expressions)
+}
+
+#[test]
+fn test_generating_from_expressions() {
+    use crate::{compiler::compile, expr, virtual_machine::VirtualMachine};
+
+    let expressions = expr::compile("let x = (10 + 40) * 2; x / 3; -x % 7").unwrap();
+
+    assert_eq!(
+        expressions[..6],
+        [
+            Expression::PUSH(10),
+            Expression::PUSH(40),
+            Expression::ADD,
+            Expression::PUSH(2),
+            Expression::MUL,
+            Expression::STORE(0),
+        ]
+    );
+
+    let mut virtual_machine = VirtualMachine::new(compile(expressions));
+    assert_eq!(virtual_machine.run().unwrap(), &[33, -2]);
+
+    let source_code: String = (0..=REGISTER_SIZE)
+        .map(|index| format!("let x{index} = {index};"))
+        .collect();
+    assert!(matches!(
+        expr::compile(&source_code),
+        Err(ExprError::TooManyVariables(name)) if name == format!("x{REGISTER_SIZE}")
+    ));
+    assert!(matches!(
+        expr::compile("y + 1"),
+        Err(ExprError::UndefinedVariable(name)) if name == "y"
+    ));
+}
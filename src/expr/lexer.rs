@@ -0,0 +1,109 @@
+use std::fmt::Display;
+
+use crate::error::ExprError;
+
+/// It represents each part of the syntax of `.expr` files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token<'a> {
+    Number(&'a str),
+    Identifier(&'a str),
+    Operator(&'a str),
+    Let,
+    Equals,
+    LeftParen,
+    RightParen,
+    Semicolon,
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(text) | Self::Identifier(text) | Self::Operator(text) => {
+                write!(f, "{text}")
+            }
+            Self::Let => write!(f, "let"),
+            Self::Equals => write!(f, "="),
+            Self::LeftParen => write!(f, "("),
+            Self::RightParen => write!(f, ")"),
+            Self::Semicolon => write!(f, ";"),
+        }
+    }
+}
+
+/// Operators, longest first so that `>>>` isn't read as `>>` and `>`.
+const OPERATORS: [&str; 12] = [
+    ">>>", "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+];
+
+/// Converts the source of an `.expr` file into tokens.
+pub fn tokenize(source_code: &str) -> Result<Vec<Token<'_>>, ExprError> {
+    let mut tokens = vec![];
+    let mut rest = source_code.trim_start();
+
+    while let Some(char) = rest.chars().next() {
+        let length = if char.is_ascii_digit() || char.is_alphabetic() || char == '_' {
+            let length = rest
+                .find(|char: char| !(char.is_alphanumeric() || char == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..length];
+
+            tokens.push(match word {
+                "let" => Token::Let,
+                _ if char.is_ascii_digit() => Token::Number(word),
+                _ => Token::Identifier(word),
+            });
+
+            length
+        } else if let Some(operator) = OPERATORS
+            .iter()
+            .find(|&&operator| rest.starts_with(operator))
+        {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else {
+            tokens.push(match char {
+                '=' => Token::Equals,
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                ';' => Token::Semicolon,
+                _ => return Err(ExprError::UnexpectedCharacter(char)),
+            });
+
+            1
+        };
+
+        rest = rest[length..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+#[test]
+fn test_tokenizing_expressions() {
+    let tokens = tokenize("let x1 = (10 + 40) * 2;\n x1 >>> 3").unwrap();
+
+    assert_eq!(
+        tokens,
+        &[
+            Token::Let,
+            Token::Identifier("x1"),
+            Token::Equals,
+            Token::LeftParen,
+            Token::Number("10"),
+            Token::Operator("+"),
+            Token::Number("40"),
+            Token::RightParen,
+            Token::Operator("*"),
+            Token::Number("2"),
+            Token::Semicolon,
+            Token::Identifier("x1"),
+            Token::Operator(">>>"),
+            Token::Number("3"),
+        ]
+    );
+
+    assert!(matches!(
+        tokenize("1 $ 2"),
+        Err(ExprError::UnexpectedCharacter('$'))
+    ));
+}
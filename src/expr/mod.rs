@@ -0,0 +1,13 @@
+pub mod ast;
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+
+use crate::{error::ExprError, parser::Expression};
+
+/// Compiles the source of an `.expr` file into expressions of the stack machine.
+pub fn compile(source_code: &str) -> Result<Vec<Expression>, ExprError> {
+    let tokens = lexer::tokenize(source_code)?;
+    let statements = parser::parse(&tokens)?;
+    codegen::generate(&statements)
+}
@@ -0,0 +1,197 @@
+use crate::{
+    error::ExprError,
+    expr::{
+        ast::{BinaryOperator, Expr, Statement, UnaryOperator},
+        lexer::Token,
+    },
+    value::Value,
+};
+
+/// Prefix operators bind tighter than every binary operator.
+const PREFIX_PRECEDENCE: u8 = 7;
+
+/// A Pratt parser over the tokens of an `.expr` file.
+struct Parser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    /// Reads the next token and returns an error if it isn't `expected`.
+    fn expect(&mut self, expected: Token<'a>) -> Result<(), ExprError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ExprError> {
+        if self.peek() != Some(Token::Let) {
+            return self.expression(0).map(Statement::Expression);
+        }
+
+        self.position += 1;
+
+        let name = match self.next() {
+            Some(Token::Identifier(name)) => name.to_string(),
+            Some(token) => return Err(ExprError::UnexpectedToken(token.to_string())),
+            None => return Err(ExprError::UnexpectedEnd),
+        };
+
+        self.expect(Token::Equals)?;
+
+        Ok(Statement::Let(name, self.expression(0)?))
+    }
+
+    /// Parses an expression whose binary operators bind at least as tightly as `min_precedence`.
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, ExprError> {
+        let mut left = self.prefix()?;
+
+        while let Some(Token::Operator(symbol)) = self.peek() {
+            let Some(operator) = BinaryOperator::from_symbol(symbol) else {
+                break;
+            };
+
+            if operator.precedence() < min_precedence {
+                break;
+            }
+
+            self.position += 1;
+
+            // Operators of the same precedence are left-associative.
+            let right = self.expression(operator.precedence() + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, ExprError> {
+        match self.next() {
+            Some(Token::Number(number)) => number
+                .parse()
+                .map(Expr::Number)
+                .map_err(|_| ExprError::InvalidNumber(number.to_string())),
+            Some(Token::Identifier(name)) => Ok(Expr::Variable(name.to_string())),
+            Some(Token::Operator("-")) => {
+                // The digits of the smallest number are too large for a `Value` without the sign.
+                if let Some(Token::Number(number)) = self.peek() {
+                    if number.parse::<Value>().is_err() {
+                        if let Ok(value) = format!("-{number}").parse() {
+                            self.position += 1;
+                            return Ok(Expr::Number(value));
+                        }
+                    }
+                }
+
+                let operand = self.expression(PREFIX_PRECEDENCE)?;
+                Ok(Expr::Unary(UnaryOperator::Negate, Box::new(operand)))
+            }
+            Some(Token::Operator("~")) => {
+                let operand = self.expression(PREFIX_PRECEDENCE)?;
+                Ok(Expr::Unary(UnaryOperator::Not, Box::new(operand)))
+            }
+            Some(Token::LeftParen) => {
+                let expression = self.expression(0)?;
+                self.expect(Token::RightParen)?;
+                Ok(expression)
+            }
+            Some(token) => Err(ExprError::UnexpectedToken(token.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+}
+
+/// Parses statements separated by semicolons.
+pub fn parse(tokens: &[Token]) -> Result<Vec<Statement>, ExprError> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let mut statements = vec![];
+
+    while let Some(token) = parser.peek() {
+        if token == Token::Semicolon {
+            parser.position += 1;
+            continue;
+        }
+
+        statements.push(parser.statement()?);
+
+        match parser.next() {
+            None | Some(Token::Semicolon) => {}
+            Some(token) => return Err(ExprError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    Ok(statements)
+}
+
+#[test]
+fn test_parsing_expressions() {
+    use crate::expr::lexer::tokenize;
+
+    let tokens = tokenize("let x = 1 + 2 * -3 - 4; (x | 1) << 2").unwrap();
+
+    let number = |value| Box::new(Expr::Number(value));
+    let sum = Expr::Binary(
+        BinaryOperator::Add,
+        number(1),
+        Box::new(Expr::Binary(
+            BinaryOperator::Mul,
+            number(2),
+            Box::new(Expr::Unary(UnaryOperator::Negate, number(3))),
+        )),
+    );
+
+    assert_eq!(
+        parse(&tokens).unwrap(),
+        &[
+            Statement::Let(
+                "x".to_string(),
+                Expr::Binary(BinaryOperator::Sub, Box::new(sum), number(4))
+            ),
+            Statement::Expression(Expr::Binary(
+                BinaryOperator::Shl,
+                Box::new(Expr::Binary(
+                    BinaryOperator::Or,
+                    Box::new(Expr::Variable("x".to_string())),
+                    number(1)
+                )),
+                number(2)
+            )),
+        ]
+    );
+
+    let tokens = tokenize("(1 + 2").unwrap();
+    assert!(matches!(parse(&tokens), Err(ExprError::UnexpectedEnd)));
+
+    let tokens = tokenize("-9223372036854775808 - -1").unwrap();
+    assert_eq!(
+        parse(&tokens).unwrap(),
+        &[Statement::Expression(Expr::Binary(
+            BinaryOperator::Sub,
+            number(Value::MIN),
+            Box::new(Expr::Unary(UnaryOperator::Negate, number(1)))
+        ))]
+    );
+
+    let tokens = tokenize("-9223372036854775809").unwrap();
+    assert!(
+        matches!(parse(&tokens), Err(ExprError::InvalidNumber(number)) if number == "9223372036854775809")
+    );
+
+    let tokens = tokenize("let 1 = 2").unwrap();
+    assert!(matches!(parse(&tokens), Err(ExprError::UnexpectedToken(token)) if token == "1"));
+}
@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{error::ForthError, parser::Expression, value::Value, virtual_machine::REGISTER_SIZE};
+
+/// Forth uses -1 as the true flag, so a comparison negates the 1 that the opcodes push.
+const NEGATE: [Expression; 3] = [Expression::PUSH(0), Expression::SWAP, Expression::SUB];
+
+/// Returns the expressions that a built-in word is expanded to.
+fn builtin(word: &str) -> Option<Vec<Expression>> {
+    let comparison = |opcode: Expression| Some([vec![opcode], NEGATE.to_vec()].concat());
+
+    let expressions = match word {
+        "+" => vec![Expression::ADD],
+        "-" => vec![Expression::SUB],
+        "*" => vec![Expression::MUL],
+        "/" => vec![Expression::DIV],
+        "mod" => vec![Expression::MOD],
+        "negate" => NEGATE.to_vec(),
+        "1+" => vec![Expression::PUSH(1), Expression::ADD],
+        "1-" => vec![Expression::PUSH(1), Expression::SUB],
+        "dup" => vec![Expression::DUP],
+        "drop" => vec![Expression::POP],
+        "swap" => vec![Expression::SWAP],
+        "over" => vec![Expression::OVER],
+        "rot" => vec![Expression::ROT],
+        "nip" => vec![Expression::NIP],
+        "tuck" => vec![Expression::TUCK],
+        "depth" => vec![Expression::DEPTH],
+        "and" => vec![Expression::BAND],
+        "or" => vec![Expression::BOR],
+        "xor" => vec![Expression::BXOR],
+        "invert" => vec![Expression::BNOT],
+        "lshift" => vec![Expression::SHL],
+        "rshift" => vec![Expression::USHR],
+        "=" => return comparison(Expression::EQ),
+        "<>" => return comparison(Expression::NE),
+        "<" => return comparison(Expression::LT),
+        ">" => return comparison(Expression::GT),
+        "<=" => return comparison(Expression::LE),
+        ">=" => return comparison(Expression::GE),
+        "0=" => [vec![Expression::PUSH(0), Expression::EQ], NEGATE.to_vec()].concat(),
+        _ => return None,
+    };
+
+    Some(expressions)
+}
+
+/// An open control structure and the labels it still needs.
+enum Control {
+    If {
+        else_label: String,
+    },
+    Else {
+        then_label: String,
+    },
+    Begin {
+        start_label: String,
+    },
+    While {
+        start_label: String,
+        end_label: String,
+    },
+}
+
+impl Control {
+    fn opening_word(&self) -> &'static str {
+        match self {
+            Self::If { .. } | Self::Else { .. } => "if",
+            Self::Begin { .. } | Self::While { .. } => "begin",
+        }
+    }
+}
+
+/// A word that is being defined.
+struct Definition {
+    name: String,
+    label: String,
+    expressions: Vec<Expression>,
+}
+
+/// Compiles words one at a time. The code outside of definitions is the program, and every definition is a
+/// subroutine that is placed after it if it is called.
+#[derive(Default)]
+struct Compiler {
+    program: Vec<Expression>,
+    subroutines: Vec<Definition>,
+    definition: Option<Definition>,
+    controls: Vec<Control>,
+    words: HashMap<String, String>,
+    variables: HashMap<String, u8>,
+    definitions: usize,
+    labels: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, expression: Expression) {
+        match &mut self.definition {
+            Some(definition) => definition.expressions.push(expression),
+            None => self.program.push(expression),
+        }
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    /// Compiles a word, reading the words it takes as operands from `words`.
+    fn word<'a>(
+        &mut self,
+        word: &'a str,
+        words: &mut impl Iterator<Item = &'a str>,
+    ) -> Result<(), ForthError> {
+        let lowercase = word.to_lowercase();
+
+        match lowercase.as_str() {
+            ":" => {
+                if self.definition.is_some() {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                }
+
+                let name = words.next().ok_or(ForthError::UnexpectedEnd)?;
+                let label = format!(".W{}", self.definitions);
+                self.definitions += 1;
+
+                // Control structures can't continue across definitions.
+                if let Some(control) = self.controls.last() {
+                    return Err(ForthError::Unclosed(control.opening_word().to_string()));
+                }
+
+                self.definition = Some(Definition {
+                    name: name.to_lowercase(),
+                    label: label.clone(),
+                    expressions: vec![Expression::LABEL(label)],
+                });
+            }
+            ";" => {
+                let Some(mut definition) = self.definition.take() else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                if let Some(control) = self.controls.last() {
+                    return Err(ForthError::Unclosed(control.opening_word().to_string()));
+                }
+
+                // A definition that ends with `again` or `exit` never reaches its end.
+                if !matches!(
+                    definition.expressions.last(),
+                    Some(Expression::JMP(_) | Expression::RETURN)
+                ) {
+                    definition.expressions.push(Expression::RETURN);
+                }
+
+                // The name is only visible after its definition, so a word can be redefined in terms of itself.
+                self.words
+                    .insert(definition.name.clone(), definition.label.clone());
+                self.subroutines.push(definition);
+            }
+            "recurse" | "exit" => {
+                let Some(definition) = &self.definition else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                let expression = match lowercase.as_str() {
+                    "recurse" => Expression::CALL(definition.label.clone()),
+                    _ => Expression::RETURN,
+                };
+                self.emit(expression);
+            }
+            "variable" => {
+                let name = words
+                    .next()
+                    .ok_or(ForthError::UnexpectedEnd)?
+                    .to_lowercase();
+
+                if !self.variables.contains_key(&name) {
+                    if self.variables.len() >= REGISTER_SIZE {
+                        return Err(ForthError::TooManyVariables(name));
+                    }
+
+                    self.variables.insert(name, self.variables.len() as u8);
+                }
+            }
+            "if" => {
+                let else_label = self.new_label();
+                self.emit(Expression::JZ(else_label.clone()));
+                self.controls.push(Control::If { else_label });
+            }
+            "else" => {
+                let Some(Control::If { else_label }) = self.controls.pop() else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                let then_label = self.new_label();
+                self.emit(Expression::JMP(then_label.clone()));
+                self.emit(Expression::LABEL(else_label));
+                self.controls.push(Control::Else { then_label });
+            }
+            "then" => match self.controls.pop() {
+                Some(Control::If { else_label: label } | Control::Else { then_label: label }) => {
+                    self.emit(Expression::LABEL(label))
+                }
+                _ => return Err(ForthError::UnexpectedWord(word.to_string())),
+            },
+            "begin" => {
+                let start_label = self.new_label();
+                self.emit(Expression::LABEL(start_label.clone()));
+                self.controls.push(Control::Begin { start_label });
+            }
+            "until" | "again" => {
+                let Some(Control::Begin { start_label }) = self.controls.pop() else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                self.emit(match lowercase.as_str() {
+                    "until" => Expression::JZ(start_label),
+                    _ => Expression::JMP(start_label),
+                });
+            }
+            "while" => {
+                let Some(Control::Begin { start_label }) = self.controls.pop() else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                let end_label = self.new_label();
+                self.emit(Expression::JZ(end_label.clone()));
+                self.controls.push(Control::While {
+                    start_label,
+                    end_label,
+                });
+            }
+            "repeat" => {
+                let Some(Control::While {
+                    start_label,
+                    end_label,
+                }) = self.controls.pop()
+                else {
+                    return Err(ForthError::UnexpectedWord(word.to_string()));
+                };
+
+                self.emit(Expression::JMP(start_label));
+                self.emit(Expression::LABEL(end_label));
+            }
+            _ => {
+                if let Some(label) = self.words.get(&lowercase) {
+                    let label = label.clone();
+                    self.emit(Expression::CALL(label));
+                } else if let Some(&register) = self.variables.get(&lowercase) {
+                    self.variable_access(lowercase, register, words.next())?;
+                } else if let Some(expressions) = builtin(&lowercase) {
+                    for expression in expressions {
+                        self.emit(expression);
+                    }
+                } else {
+                    self.emit(Expression::PUSH(number(word)?));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A variable is only used as the address of `@`, `!` or `+!`, which become `LOAD` and `STORE` on its register.
+    fn variable_access(
+        &mut self,
+        name: String,
+        register: u8,
+        access: Option<&str>,
+    ) -> Result<(), ForthError> {
+        match access {
+            Some("@") => self.emit(Expression::LOAD(register)),
+            Some("!") => self.emit(Expression::STORE(register)),
+            Some("+!") => {
+                self.emit(Expression::LOAD(register));
+                self.emit(Expression::ADD);
+                self.emit(Expression::STORE(register));
+            }
+            _ => return Err(ForthError::AccessRequired(name)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a word that isn't defined as a number.
+fn number(word: &str) -> Result<Value, ForthError> {
+    word.parse().map_err(|_| {
+        let digits = word.strip_prefix('-').unwrap_or(word);
+
+        match !digits.is_empty() && digits.chars().all(|char| char.is_ascii_digit()) {
+            true => ForthError::InvalidNumber(word.to_string()),
+            false => ForthError::UnknownWord(word.to_string()),
+        }
+    })
+}
+
+/// Splits the source of a `.fs` file into words, leaving out `( ... )` and `\ ...` comments.
+fn words(source_code: &str) -> Result<Vec<&str>, ForthError> {
+    let mut words = vec![];
+
+    for line in source_code.lines() {
+        let mut line_words = line.split_whitespace();
+
+        while let Some(word) = line_words.next() {
+            match word {
+                "\\" => break,
+                "(" => {
+                    // A comment that isn't closed on its line is an error, so it can't hide the rest of the program.
+                    if !line_words.any(|word| word.ends_with(')')) {
+                        return Err(ForthError::Unclosed("(".to_string()));
+                    }
+                }
+                _ => words.push(word),
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+/// Compiles the source of a `.fs` file into expressions of the stack machine.
+/// Words are not case-sensitive. The data stack is the stack of the virtual machine, and it is returned at the end.
+pub fn compile(source_code: &str) -> Result<Vec<Expression>, ForthError> {
+    let mut compiler = Compiler::default();
+    let mut words = words(source_code)?.into_iter();
+
+    while let Some(word) = words.next() {
+        compiler.word(word, &mut words)?;
+    }
+
+    if compiler.definition.is_some() {
+        return Err(ForthError::Unclosed(":".to_string()));
+    }
+
+    if let Some(control) = compiler.controls.last() {
+        return Err(ForthError::Unclosed(control.opening_word().to_string()));
+    }
+
+    // Words that are never called are left out, so that their code isn't reported as unreachable.
+    let mut called = HashSet::new();
+    let mut pending = vec![&compiler.program];
+
+    while let Some(expressions) = pending.pop() {
+        for expression in expressions {
+            let Expression::CALL(label) = expression else {
+                continue;
+            };
+
+            if called.insert(label.clone()) {
+                if let Some(definition) = compiler
+                    .subroutines
+                    .iter()
+                    .find(|definition| &definition.label == label)
+                {
+                    pending.push(&definition.expressions);
+                }
+            }
+        }
+    }
+
+    let mut expressions = compiler.program;
+    expressions.push(Expression::RET);
+
+    for mut definition in compiler.subroutines {
+        if called.contains(&definition.label) {
+            expressions.append(&mut definition.expressions);
+        }
+    }
+
+    Ok(expressions)
+}
+
+#[test]
+fn test_compiling_forth() {
+    use crate::{compiler, virtual_machine::VirtualMachine};
+
+    let run = |source_code: &str| {
+        let expressions = compile(source_code).unwrap();
+        let mut virtual_machine = VirtualMachine::new(compiler::compile(expressions));
+        virtual_machine.run().unwrap().to_vec()
+    };
+
+    assert_eq!(
+        compile(": square dup * ; 7 square").unwrap(),
+        &[
+            Expression::PUSH(7),
+            Expression::CALL(".W0".to_string()),
+            Expression::RET,
+            Expression::LABEL(".W0".to_string()),
+            Expression::DUP,
+            Expression::MUL,
+            Expression::RETURN,
+        ]
+    );
+
+    // `f` is only called by `g`, which the program doesn't call.
+    assert_eq!(
+        compile(": f ; : g f ; : h ; 1 h").unwrap(),
+        &[
+            Expression::PUSH(1),
+            Expression::CALL(".W2".to_string()),
+            Expression::RET,
+            Expression::LABEL(".W2".to_string()),
+            Expression::RETURN,
+        ]
+    );
+    assert!(crate::diagnostics::check(&compile(": f ; : g f ;").unwrap()).is_empty());
+
+    assert_eq!(run("3 4 < 4 3 < 2 2 = 0 0= 5 negate"), &[-1, 0, -1, -1, -5]);
+    assert_eq!(
+        run(": abs ( n -- n ) dup 0 < if negate then ; \\ comment\n -3 abs 3 ABS"),
+        &[3, 3]
+    );
+    assert_eq!(
+        run(": sign dup 0 < if drop -1 else 0 > if 1 else 0 then then ; -5 sign 0 sign 9 sign"),
+        &[-1, 0, 1]
+    );
+    assert_eq!(
+        run("variable total 0 total ! 10 begin dup total +! 1- dup 0= until drop total @"),
+        &[55]
+    );
+    assert_eq!(
+        run(": fact dup 1 > if dup 1- recurse * then ; 10 fact"),
+        &[3_628_800]
+    );
+    assert_eq!(
+        run(": double 2 * ; : double double double ; : double double double ; 3 double"),
+        &[48]
+    );
+    assert_eq!(run("1 begin dup 100 < while 2 * repeat"), &[128]);
+
+    for (source_code, message) in [
+        ("1 foo", "FORTH ERROR: `foo` is not a defined word"),
+        (": f 1 if ;", "FORTH ERROR: `if` is not closed"),
+        (": f 1", "FORTH ERROR: `:` is not closed"),
+        ("then", "FORTH ERROR: `then` is not expected here"),
+        (
+            "variable x x",
+            "FORTH ERROR: variable `x` must be followed by `@`, `!` or `+!`",
+        ),
+        (
+            "99999999999999999999",
+            "FORTH ERROR: `99999999999999999999` is not a valid number",
+        ),
+        ("( comment", "FORTH ERROR: `(` is not closed"),
+        (":", "FORTH ERROR: the program ends unexpectedly"),
+    ] {
+        assert_eq!(compile(source_code).unwrap_err().to_string(), message);
+    }
+}
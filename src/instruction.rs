@@ -0,0 +1,92 @@
+use crate::value::Value;
+
+/// An opcode decoded together with its operands.
+/// Jump targets are either bytecode addresses or instruction indices, depending on who decoded it.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    PUSH(Value),
+    POP,
+    STORE(u8),
+    LOAD(u8),
+    ADD,
+    SUB,
+    MUL,
+    DIV,
+    MOD,
+    RET,
+    DUP,
+    SWAP,
+    OVER,
+    ROT,
+    NIP,
+    TUCK,
+    PICK(u8),
+    DEPTH,
+    BAND,
+    BOR,
+    BXOR,
+    BNOT,
+    SHL,
+    SHR,
+    USHR,
+    ROTL,
+    ROTR,
+    POPCNT,
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+    PUT(u8),
+    LOAD8,
+    LOAD64,
+    STORE8,
+    STORE64,
+    MEMSIZE,
+    MEMGROW,
+    JMP(usize),
+    JZ(usize),
+    JNZ(usize),
+    CALL(usize),
+    RETURN,
+    TRY(usize),
+    /// Holds the bytecode address of the `THROW` itself, which is reported if the value is not caught.
+    THROW(usize),
+    ENDTRY,
+    SPAWN(usize),
+    YIELD,
+    RESUME,
+    FIBERSTATUS,
+    SEND,
+    RECV,
+}
+
+impl Instruction {
+    /// Returns the jump target of the instruction if it has one.
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Self::JMP(target)
+            | Self::JZ(target)
+            | Self::JNZ(target)
+            | Self::CALL(target)
+            | Self::TRY(target)
+            | Self::SPAWN(target) => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// Returns the same instruction with its jump target replaced.
+    pub fn with_target(self, target: usize) -> Self {
+        match self {
+            Self::JMP(_) => Self::JMP(target),
+            Self::JZ(_) => Self::JZ(target),
+            Self::JNZ(_) => Self::JNZ(target),
+            Self::CALL(_) => Self::CALL(target),
+            Self::TRY(_) => Self::TRY(target),
+            Self::SPAWN(_) => Self::SPAWN(target),
+            _ => self,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the `jit` feature requires x86-64 Linux");
+
+use std::{collections::HashMap, ffi::c_void};
+
+use crate::{instruction::Instruction, value::Value, virtual_machine::REGISTER_SIZE};
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(
+        address: *mut c_void,
+        length: usize,
+        protection: i32,
+        flags: i32,
+        file_descriptor: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(address: *mut c_void, length: usize, protection: i32) -> i32;
+    fn munmap(address: *mut c_void, length: usize) -> i32;
+}
+
+/// Regions shorter than this are left to the interpreter.
+pub const MIN_REGION_LENGTH: usize = 2;
+
+/// Machine code that is mapped as executable. It is unmapped when dropped.
+struct ExecutableBuffer {
+    pointer: *mut c_void,
+    length: usize,
+}
+
+impl ExecutableBuffer {
+    /// Copies code into a new mapping and makes it executable instead of writable.
+    fn new(code: &[u8]) -> Option<Self> {
+        // SAFETY: a new anonymous mapping doesn't alias any memory, and it is only written before it is executable.
+        unsafe {
+            let pointer = mmap(
+                std::ptr::null_mut(),
+                code.len(),
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+
+            if pointer as isize == -1 {
+                return None;
+            }
+
+            let buffer = Self {
+                pointer,
+                length: code.len(),
+            };
+
+            std::ptr::copy_nonoverlapping(code.as_ptr(), pointer as *mut u8, code.len());
+
+            if mprotect(pointer, code.len(), PROT_READ | PROT_EXEC) != 0 {
+                return None;
+            }
+
+            Some(buffer)
+        }
+    }
+}
+
+// SAFETY: the mapping is only read and executed after it is created, so it can be used from any thread.
+unsafe impl Send for ExecutableBuffer {}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is owned by the buffer and no function pointer into it outlives it.
+        unsafe {
+            munmap(self.pointer, self.length);
+        }
+    }
+}
+
+/// Straight-line instructions compiled into a function that takes a pointer past the top of the stack
+/// and a pointer to the registers, and returns the new pointer past the top of the stack.
+pub struct Region {
+    /// The index of the instruction after the region.
+    pub end: usize,
+    /// The number of values the stack must hold when the region is entered.
+    pub required: usize,
+    /// The largest number of values the region adds to the stack at any point.
+    pub growth: usize,
+    buffer: ExecutableBuffer,
+}
+
+impl Region {
+    /// Runs the region.
+    ///
+    /// # Safety
+    /// `top` must point past at least `required` values and before room for `growth` more,
+    /// and `registers` must point to `REGISTER_SIZE` values.
+    pub unsafe fn call(&self, top: *mut Value, registers: *mut Value) -> *mut Value {
+        let function: extern "C" fn(*mut Value, *mut Value) -> *mut Value =
+            std::mem::transmute(self.buffer.pointer);
+
+        function(top, registers)
+    }
+}
+
+/// Returns how many values an instruction pops and pushes if it has a template.
+fn stack_effect(instruction: Instruction) -> Option<(usize, usize)> {
+    match instruction {
+        Instruction::PUSH(_) => Some((0, 1)),
+        Instruction::LOAD(index) if (index as usize) < REGISTER_SIZE => Some((0, 1)),
+        Instruction::STORE(index) if (index as usize) < REGISTER_SIZE => Some((1, 0)),
+        Instruction::POP => Some((1, 0)),
+        Instruction::ADD
+        | Instruction::SUB
+        | Instruction::MUL
+        | Instruction::BAND
+        | Instruction::BOR
+        | Instruction::BXOR => Some((2, 1)),
+        Instruction::BNOT => Some((1, 1)),
+        Instruction::DUP => Some((1, 2)),
+        Instruction::SWAP => Some((2, 2)),
+        Instruction::OVER => Some((2, 3)),
+        _ => None,
+    }
+}
+
+/// Appends the machine code of an instruction. `%rdi` points past the top of the stack and `%rsi` to the registers.
+/// `SUB` computes "second-from-top - top" like the current format.
+fn emit_template(code: &mut Vec<u8>, instruction: Instruction) {
+    const PUSH_RAX: [u8; 7] = [0x48, 0x89, 0x07, 0x48, 0x83, 0xC7, 0x08];
+    const POP_RAX: [u8; 7] = [0x48, 0x83, 0xEF, 0x08, 0x48, 0x8B, 0x07];
+
+    match instruction {
+        Instruction::PUSH(value) => {
+            code.extend_from_slice(&[0x48, 0xB8]);
+            code.extend_from_slice(&value.to_le_bytes());
+            code.extend_from_slice(&PUSH_RAX);
+        }
+        Instruction::LOAD(index) => {
+            code.extend_from_slice(&[0x48, 0x8B, 0x86]);
+            code.extend_from_slice(&(index as u32 * 8).to_le_bytes());
+            code.extend_from_slice(&PUSH_RAX);
+        }
+        Instruction::STORE(index) => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x89, 0x86]);
+            code.extend_from_slice(&(index as u32 * 8).to_le_bytes());
+        }
+        Instruction::POP => code.extend_from_slice(&[0x48, 0x83, 0xEF, 0x08]),
+        Instruction::ADD => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x01, 0x47, 0xF8]);
+        }
+        Instruction::SUB => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x29, 0x47, 0xF8]);
+        }
+        Instruction::MUL => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x0F, 0xAF, 0x47, 0xF8]);
+            code.extend_from_slice(&[0x48, 0x89, 0x47, 0xF8]);
+        }
+        Instruction::BAND => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x21, 0x47, 0xF8]);
+        }
+        Instruction::BOR => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x09, 0x47, 0xF8]);
+        }
+        Instruction::BXOR => {
+            code.extend_from_slice(&POP_RAX);
+            code.extend_from_slice(&[0x48, 0x31, 0x47, 0xF8]);
+        }
+        Instruction::BNOT => code.extend_from_slice(&[0x48, 0xF7, 0x57, 0xF8]),
+        Instruction::DUP => {
+            code.extend_from_slice(&[0x48, 0x8B, 0x47, 0xF8]);
+            code.extend_from_slice(&PUSH_RAX);
+        }
+        Instruction::SWAP => code.extend_from_slice(&[
+            0x48, 0x8B, 0x47, 0xF8, 0x48, 0x8B, 0x4F, 0xF0, 0x48, 0x89, 0x4F, 0xF8, 0x48, 0x89,
+            0x47, 0xF0,
+        ]),
+        Instruction::OVER => {
+            code.extend_from_slice(&[0x48, 0x8B, 0x47, 0xF0]);
+            code.extend_from_slice(&PUSH_RAX);
+        }
+        _ => unreachable!("`{instruction:?}` has no template"),
+    }
+}
+
+/// Compiles every run of at least `MIN_REGION_LENGTH` instructions with templates into a region, found by its first index.
+/// Regions are split at jump targets so that jumps can enter them. Instructions are from current-format bytecode.
+pub fn compile(instructions: &[Instruction]) -> HashMap<usize, Region> {
+    let mut boundaries = vec![false; instructions.len() + 1];
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let Some(target) = instruction.target() {
+            boundaries[target] = true;
+        }
+
+        if let Instruction::CALL(_) = instruction {
+            boundaries[index + 1] = true;
+        }
+    }
+
+    let mut regions = HashMap::new();
+    let mut start = 0;
+
+    while start < instructions.len() {
+        let mut end = start;
+        let mut depth: isize = 0;
+        let mut required: isize = 0;
+        let mut growth: isize = 0;
+
+        while let Some((pops, pushes)) = instructions.get(end).and_then(|&i| stack_effect(i)) {
+            if end > start && boundaries[end] {
+                break;
+            }
+
+            depth -= pops as isize;
+            required = required.max(-depth);
+            depth += pushes as isize;
+            growth = growth.max(depth);
+            end += 1;
+        }
+
+        if end - start >= MIN_REGION_LENGTH {
+            let mut code = vec![];
+
+            for &instruction in &instructions[start..end] {
+                emit_template(&mut code, instruction);
+            }
+
+            // mov %rdi, %rax; ret
+            code.extend_from_slice(&[0x48, 0x89, 0xF8, 0xC3]);
+
+            if let Some(buffer) = ExecutableBuffer::new(&code) {
+                let region = Region {
+                    end,
+                    required: required as usize,
+                    growth: growth as usize,
+                    buffer,
+                };
+                regions.insert(start, region);
+            }
+        }
+
+        start = end.max(start + 1);
+    }
+
+    regions
+}
+
+#[test]
+fn test_jit_matches_interpreter() {
+    use crate::{
+        parser::Expression,
+        random_program::{generate, Random},
+        virtual_machine::VirtualMachine,
+    };
+
+    let mut random = Random::new(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..500 {
+        // Programs mostly stay valid, but some underflow to check the fallback.
+        let expressions = generate(&mut random, 40, true, |random| match random.below(14) {
+            0..=2 => Expression::PUSH(random.below(u64::MAX) as Value >> random.below(64)),
+            3 => Expression::LOAD(random.below(4) as u8),
+            4 => Expression::STORE(random.below(4) as u8),
+            5 => Expression::POP,
+            6 => Expression::ADD,
+            7 => Expression::SUB,
+            8 => Expression::MUL,
+            9 => Expression::BAND,
+            10 => Expression::BXOR,
+            11 => Expression::DUP,
+            12 => Expression::SWAP,
+            _ => Expression::OVER,
+        });
+
+        let bytecode = crate::compiler::compile(expressions.clone());
+        let mut interpreter = VirtualMachine::new(bytecode.clone());
+        let mut jit = VirtualMachine::new(bytecode);
+
+        let expected = interpreter.run_bytecode().map(|stack| stack.to_vec());
+        let result = jit.run().map(|stack| stack.to_vec());
+
+        match (expected, result) {
+            (Ok(expected), Ok(result)) => assert_eq!(expected, result, "{expressions:?}"),
+            (Err(expected), Err(result)) => {
+                assert_eq!(expected.to_string(), result.to_string(), "{expressions:?}")
+            }
+            (expected, result) => panic!("{expressions:?}: {expected:?} != {result:?}"),
+        }
+    }
+
+    let instructions = [
+        Instruction::PUSH(1),
+        Instruction::PUSH(2),
+        Instruction::ADD,
+        Instruction::JMP(0),
+        Instruction::DUP,
+    ];
+    let regions = compile(&instructions);
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[&0].end, 3);
+    assert_eq!((regions[&0].required, regions[&0].growth), (0, 2));
+}
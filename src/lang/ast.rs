@@ -0,0 +1,190 @@
+use std::fmt::Display;
+
+use crate::value::Value;
+
+/// The type of a value. `Void` is only the return type of functions that return nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    Void,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Bool => write!(f, "bool"),
+            Self::Void => write!(f, "void"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub parameters: Vec<(String, Type)>,
+    pub return_type: Type,
+    pub body: Block,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+}
+
+impl Block {
+    /// Returns true if every path through the block ends with `return`.
+    pub fn returns(&self) -> bool {
+        self.statements.iter().any(Statement::returns)
+    }
+
+    /// Returns how many variables the block declares directly.
+    pub fn locals(&self) -> usize {
+        self.statements
+            .iter()
+            .filter(|statement| matches!(statement, Statement::Let { .. }))
+            .count()
+    }
+}
+
+/// Variables are found by their slot, which is their position in the frame of the function.
+/// Slots and called functions are filled in by the resolver.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Let {
+        name: String,
+        declared_type: Option<Type>,
+        value: Expr,
+        slot: usize,
+        line: usize,
+    },
+    Assign {
+        name: String,
+        value: Expr,
+        slot: usize,
+        line: usize,
+    },
+    If {
+        condition: Expr,
+        then_block: Block,
+        else_block: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    Return {
+        value: Option<Expr>,
+        line: usize,
+    },
+    Expression(Expr),
+}
+
+impl Statement {
+    /// Returns true if every path through the statement ends with `return`.
+    pub fn returns(&self) -> bool {
+        match self {
+            Self::Return { .. } => true,
+            Self::If {
+                then_block,
+                else_block: Some(else_block),
+                ..
+            } => then_block.returns() && else_block.returns(),
+            _ => false,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            Self::Let { line, .. } | Self::Assign { line, .. } | Self::Return { line, .. } => *line,
+            Self::If { condition, .. } | Self::While { condition, .. } => condition.line,
+            Self::Expression(expr) => expr.line,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(Value),
+    Bool(bool),
+    Variable {
+        name: String,
+        slot: usize,
+    },
+    Call {
+        name: String,
+        arguments: Vec<Expr>,
+        function: usize,
+    },
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl BinaryOperator {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            "%" => Some(Self::Mod),
+            "==" => Some(Self::Equal),
+            "!=" => Some(Self::NotEqual),
+            "<" => Some(Self::Less),
+            "<=" => Some(Self::LessEqual),
+            ">" => Some(Self::Greater),
+            ">=" => Some(Self::GreaterEqual),
+            "&&" => Some(Self::And),
+            "||" => Some(Self::Or),
+            _ => None,
+        }
+    }
+
+    /// Returns how tightly the operator binds. Higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Equal | Self::NotEqual => 3,
+            Self::Less | Self::LessEqual | Self::Greater | Self::GreaterEqual => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::Mod => 6,
+        }
+    }
+}
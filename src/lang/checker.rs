@@ -0,0 +1,255 @@
+use crate::{
+    error::LangError,
+    lang::ast::{
+        BinaryOperator, Block, Expr, ExprKind, Function, Program, Statement, Type, UnaryOperator,
+    },
+};
+
+/// Checks the types in one function. Names must be resolved already.
+struct Checker<'a> {
+    functions: &'a [Function],
+    return_type: Type,
+    /// The types of the variables by slot.
+    slots: Vec<Type>,
+}
+
+impl Checker<'_> {
+    fn block(&mut self, block: &Block) -> Result<(), LangError> {
+        let mut returned = false;
+
+        for statement in &block.statements {
+            if returned {
+                return Err(LangError::UnreachableStatement(statement.line()));
+            }
+
+            self.statement(statement)?;
+            returned = statement.returns();
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), LangError> {
+        match statement {
+            Statement::Let {
+                declared_type,
+                value,
+                slot,
+                ..
+            } => {
+                let value_type = self.value(value)?;
+
+                if let Some(declared_type) = *declared_type {
+                    expect(value.line, declared_type, value_type)?;
+                }
+
+                // Slots are reused by variables of sibling blocks, which are checked in order.
+                self.slots.truncate(*slot);
+                self.slots.push(value_type);
+            }
+            Statement::Assign { value, slot, .. } => {
+                let value_type = self.value(value)?;
+                expect(value.line, self.slots[*slot], value_type)?;
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.condition(condition)?;
+                self.block(then_block)?;
+
+                if let Some(else_block) = else_block {
+                    self.block(else_block)?;
+                }
+            }
+            Statement::While { condition, body } => {
+                self.condition(condition)?;
+                self.block(body)?;
+            }
+            Statement::Return { value, line } => {
+                let value_type = match value {
+                    Some(value) => self.value(value)?,
+                    None => Type::Void,
+                };
+                expect(*line, self.return_type, value_type)?;
+            }
+            Statement::Expression(expr) => {
+                self.expression(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn condition(&mut self, condition: &Expr) -> Result<(), LangError> {
+        let condition_type = self.value(condition)?;
+        expect(condition.line, Type::Bool, condition_type)
+    }
+
+    /// Returns the type of an expression that must produce a value.
+    fn value(&mut self, expr: &Expr) -> Result<Type, LangError> {
+        match self.expression(expr)? {
+            Type::Void => Err(LangError::NoValue(expr.line)),
+            value_type => Ok(value_type),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<Type, LangError> {
+        match &expr.kind {
+            ExprKind::Number(_) => Ok(Type::Int),
+            ExprKind::Bool(_) => Ok(Type::Bool),
+            ExprKind::Variable { slot, .. } => Ok(self.slots[*slot]),
+            ExprKind::Call {
+                arguments,
+                function,
+                ..
+            } => {
+                let function = &self.functions[*function];
+
+                for (argument, (_, parameter_type)) in arguments.iter().zip(&function.parameters) {
+                    let argument_type = self.value(argument)?;
+                    expect(argument.line, *parameter_type, argument_type)?;
+                }
+
+                Ok(function.return_type)
+            }
+            ExprKind::Unary(operator, operand) => {
+                let operand_type = self.value(operand)?;
+                let expected = match operator {
+                    UnaryOperator::Negate => Type::Int,
+                    UnaryOperator::Not => Type::Bool,
+                };
+                expect(operand.line, expected, operand_type)?;
+                Ok(expected)
+            }
+            ExprKind::Binary(operator, left, right) => {
+                let left_type = self.value(left)?;
+                let right_type = self.value(right)?;
+
+                let (operand_type, result_type) = match operator {
+                    BinaryOperator::Add
+                    | BinaryOperator::Sub
+                    | BinaryOperator::Mul
+                    | BinaryOperator::Div
+                    | BinaryOperator::Mod => (Type::Int, Type::Int),
+                    BinaryOperator::Less
+                    | BinaryOperator::LessEqual
+                    | BinaryOperator::Greater
+                    | BinaryOperator::GreaterEqual => (Type::Int, Type::Bool),
+                    BinaryOperator::And | BinaryOperator::Or => (Type::Bool, Type::Bool),
+                    // Both sides of `==` and `!=` only need to have the same type.
+                    BinaryOperator::Equal | BinaryOperator::NotEqual => (left_type, Type::Bool),
+                };
+
+                expect(left.line, operand_type, left_type)?;
+                expect(right.line, operand_type, right_type)?;
+                Ok(result_type)
+            }
+        }
+    }
+}
+
+fn expect(line: usize, expected: Type, found: Type) -> Result<(), LangError> {
+    match expected == found {
+        true => Ok(()),
+        false => Err(LangError::TypeMismatch {
+            line,
+            expected,
+            found,
+        }),
+    }
+}
+
+/// Checks that values have the types they are used as, and that functions with a return type always return.
+pub fn check(program: &Program) -> Result<(), LangError> {
+    for function in &program.functions {
+        let mut checker = Checker {
+            functions: &program.functions,
+            return_type: function.return_type,
+            slots: function
+                .parameters
+                .iter()
+                .map(|(_, parameter_type)| *parameter_type)
+                .collect(),
+        };
+
+        checker.block(&function.body)?;
+
+        if function.return_type != Type::Void && !function.body.returns() {
+            return Err(LangError::MissingReturn(function.name.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_checking_types() {
+    use crate::lang::{lexer::tokenize, parser::parse, resolver::resolve};
+
+    let check_source = |source_code: &str| {
+        let mut program = parse(&tokenize(source_code).unwrap()).unwrap();
+        resolve(&mut program).unwrap();
+        check(&program)
+    };
+
+    assert!(check_source(
+        "fn even(n: int) -> bool { if n == 0 { return true; } else { return !even(n - 1); } }
+         fn main() -> int { let b: bool = even(4) && 1 < 2; if b == true { return 1; } return 0; }"
+    )
+    .is_ok());
+
+    assert!(matches!(
+        check_source("fn main() {\n let x: bool = 1; }"),
+        Err(LangError::TypeMismatch {
+            line: 2,
+            expected: Type::Bool,
+            found: Type::Int
+        })
+    ));
+    assert!(matches!(
+        check_source("fn main() { let x = 1; x = false; }"),
+        Err(LangError::TypeMismatch {
+            expected: Type::Int,
+            found: Type::Bool,
+            ..
+        })
+    ));
+    assert!(matches!(
+        check_source("fn main() { while 1 { } }"),
+        Err(LangError::TypeMismatch {
+            expected: Type::Bool,
+            found: Type::Int,
+            ..
+        })
+    ));
+    assert!(matches!(
+        check_source("fn main() { if true == 1 { } }"),
+        Err(LangError::TypeMismatch {
+            expected: Type::Bool,
+            found: Type::Int,
+            ..
+        })
+    ));
+    assert!(matches!(
+        check_source("fn f() {} fn main() { let x = f(); }"),
+        Err(LangError::NoValue(1))
+    ));
+    assert!(matches!(
+        check_source("fn main() -> int { return; }"),
+        Err(LangError::TypeMismatch {
+            expected: Type::Int,
+            found: Type::Void,
+            ..
+        })
+    ));
+    assert!(matches!(
+        check_source("fn main() -> int { if true { return 1; } }"),
+        Err(LangError::MissingReturn(name)) if name == "main"
+    ));
+    assert!(matches!(
+        check_source("fn main() {\n return;\n main(); }"),
+        Err(LangError::UnreachableStatement(3))
+    ));
+}
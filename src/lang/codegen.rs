@@ -0,0 +1,322 @@
+use crate::{
+    error::LangError,
+    lang::ast::{
+        BinaryOperator, Block, Expr, ExprKind, Function, Program, Statement, Type, UnaryOperator,
+    },
+    parser::Expression,
+    value::Value,
+};
+
+/// Generates stack machine expressions for one function at a time.
+/// The parameters and local variables of a function live in the stack, and `height` counts the values
+/// above the first parameter, so a variable is reached with `PICK` and changed with `PUT`.
+struct Generator<'a> {
+    functions: &'a [Function],
+    expressions: Vec<Expression>,
+    height: usize,
+    labels: usize,
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, expression: Expression) {
+        let (pops, pushes) = expression.stack_effect();
+        self.height = self.height - pops + pushes;
+        self.expressions.push(expression);
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    /// Returns how many values are above the variable in `slot`, which must fit in the index of `PICK` and `PUT`.
+    fn depth(&self, slot: usize, line: usize) -> Result<u8, LangError> {
+        u8::try_from(self.height - 1 - slot).map_err(|_| LangError::TooManyLocals(line))
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), LangError> {
+        let height = self.height;
+
+        for statement in &block.statements {
+            self.statement(statement)?;
+        }
+
+        // A block that returns has already removed its variables.
+        if !block.returns() {
+            for _ in 0..block.locals() {
+                self.emit(Expression::POP);
+            }
+        }
+
+        self.height = height;
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), LangError> {
+        match statement {
+            // The value stays in the stack as the new variable.
+            Statement::Let { value, .. } => self.expression(value)?,
+            Statement::Assign {
+                value, slot, line, ..
+            } => {
+                self.expression(value)?;
+                // `PUT` counts from below the value it pops.
+                let depth = self.depth(*slot + 1, *line)?;
+                self.emit(Expression::PUT(depth));
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                let else_label = self.new_label();
+                let end_label = self.new_label();
+
+                self.expression(condition)?;
+                self.emit(Expression::JZ(else_label.clone()));
+                self.block(then_block)?;
+
+                match else_block {
+                    Some(else_block) => {
+                        if !then_block.returns() {
+                            self.emit(Expression::JMP(end_label.clone()));
+                        }
+
+                        self.emit(Expression::LABEL(else_label));
+                        self.block(else_block)?;
+
+                        if !statement.returns() {
+                            self.emit(Expression::LABEL(end_label));
+                        }
+                    }
+                    None => self.emit(Expression::LABEL(else_label)),
+                }
+            }
+            Statement::While { condition, body } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+
+                self.emit(Expression::LABEL(start_label.clone()));
+                self.expression(condition)?;
+                self.emit(Expression::JZ(end_label.clone()));
+                self.block(body)?;
+                self.emit(Expression::JMP(start_label));
+                self.emit(Expression::LABEL(end_label));
+            }
+            Statement::Return { value, .. } => self.return_from_function(value.as_ref())?,
+            Statement::Expression(expr) => {
+                self.expression(expr)?;
+
+                if self.type_of_call(expr) != Some(Type::Void) {
+                    self.emit(Expression::POP);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the whole frame, leaving only the returned value, and returns to the caller.
+    fn return_from_function(&mut self, value: Option<&Expr>) -> Result<(), LangError> {
+        let height = self.height;
+
+        match value {
+            Some(value) => {
+                self.expression(value)?;
+
+                for _ in 0..height {
+                    self.emit(Expression::NIP);
+                }
+            }
+            None => {
+                for _ in 0..height {
+                    self.emit(Expression::POP);
+                }
+            }
+        }
+
+        self.emit(Expression::RETURN);
+        // The code after a return is only reached by jumps, with the frame as it was.
+        self.height = height;
+        Ok(())
+    }
+
+    /// Returns the return type of the function if the expression is a call.
+    fn type_of_call(&self, expr: &Expr) -> Option<Type> {
+        match expr.kind {
+            ExprKind::Call { function, .. } => Some(self.functions[function].return_type),
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), LangError> {
+        match &expr.kind {
+            ExprKind::Number(value) => self.emit(Expression::PUSH(*value)),
+            ExprKind::Bool(value) => self.emit(Expression::PUSH(Value::from(*value))),
+            ExprKind::Variable { slot, .. } => {
+                let depth = self.depth(*slot, expr.line)?;
+                self.emit(Expression::PICK(depth));
+            }
+            ExprKind::Call {
+                name,
+                arguments,
+                function,
+            } => {
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+
+                self.emit(Expression::CALL(name.clone()));
+
+                // The arguments are replaced by the returned value.
+                self.height -= arguments.len();
+                if self.functions[*function].return_type != Type::Void {
+                    self.height += 1;
+                }
+            }
+            ExprKind::Unary(UnaryOperator::Negate, operand) => match operand.kind {
+                ExprKind::Number(value) => self.emit(Expression::PUSH(value.wrapping_neg())),
+                _ => {
+                    self.emit(Expression::PUSH(0));
+                    self.expression(operand)?;
+                    self.emit(Expression::SUB);
+                }
+            },
+            ExprKind::Unary(UnaryOperator::Not, operand) => {
+                self.expression(operand)?;
+                self.emit(Expression::PUSH(0));
+                self.emit(Expression::EQ);
+            }
+            ExprKind::Binary(
+                operator @ (BinaryOperator::And | BinaryOperator::Or),
+                left,
+                right,
+            ) => {
+                // The right side is only evaluated if the left side doesn't decide the result.
+                let end_label = self.new_label();
+
+                self.expression(left)?;
+                self.emit(Expression::DUP);
+                self.emit(match operator {
+                    BinaryOperator::And => Expression::JZ(end_label.clone()),
+                    _ => Expression::JNZ(end_label.clone()),
+                });
+                self.emit(Expression::POP);
+                self.expression(right)?;
+                self.emit(Expression::LABEL(end_label));
+            }
+            ExprKind::Binary(operator, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(match operator {
+                    BinaryOperator::Add => Expression::ADD,
+                    BinaryOperator::Sub => Expression::SUB,
+                    BinaryOperator::Mul => Expression::MUL,
+                    BinaryOperator::Div => Expression::DIV,
+                    BinaryOperator::Mod => Expression::MOD,
+                    BinaryOperator::Equal => Expression::EQ,
+                    BinaryOperator::NotEqual => Expression::NE,
+                    BinaryOperator::Less => Expression::LT,
+                    BinaryOperator::LessEqual => Expression::LE,
+                    BinaryOperator::Greater => Expression::GT,
+                    BinaryOperator::GreaterEqual => Expression::GE,
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates expressions that call `main` and return what it returns.
+/// Each function is labelled by its name and ends by returning to its caller.
+/// The program must be resolved and checked already.
+pub fn generate(program: &Program) -> Result<Vec<Expression>, LangError> {
+    let mut generator = Generator {
+        functions: &program.functions,
+        expressions: vec![Expression::CALL("main".to_string()), Expression::RET],
+        height: 0,
+        labels: 0,
+    };
+
+    for function in &program.functions {
+        generator.height = function.parameters.len();
+        generator
+            .expressions
+            .push(Expression::LABEL(function.name.clone()));
+        generator.block(&function.body)?;
+
+        // Only functions without a return type can reach their end.
+        if !function.body.returns() {
+            generator.return_from_function(None)?;
+        }
+    }
+
+    Ok(generator.expressions)
+}
+
+#[test]
+fn test_generating_from_language() {
+    use crate::{compiler::compile, lang, virtual_machine::VirtualMachine};
+
+    let run = |source_code: &str| {
+        let expressions = lang::compile(source_code).unwrap();
+        let mut virtual_machine = VirtualMachine::new(compile(expressions));
+        virtual_machine.run().unwrap().to_vec()
+    };
+
+    assert_eq!(
+        lang::compile("fn main() -> int { let x = 2; x = x * 3; return x; }").unwrap(),
+        &[
+            Expression::CALL("main".to_string()),
+            Expression::RET,
+            Expression::LABEL("main".to_string()),
+            Expression::PUSH(2),
+            Expression::PICK(0),
+            Expression::PUSH(3),
+            Expression::MUL,
+            Expression::PUT(0),
+            Expression::PICK(0),
+            Expression::NIP,
+            Expression::RETURN,
+        ]
+    );
+
+    assert_eq!(
+        run("fn sub(a: int, b: int) -> int { let c = a - b; return c; }
+             fn main() -> int { return sub(10, 3) * -sub(1, 2); }"),
+        &[7]
+    );
+    assert_eq!(
+        run("fn main() -> int {
+                 let total = 0;
+                 let i = 0;
+                 while i < 10 {
+                     let square = i * i;
+                     if square % 2 == 0 && !(i == 4) { total = total + square; } else if i > 7 { total = total - 1; }
+                     i = i + 1;
+                 }
+                 return total;
+             }"),
+        &[4 + 36 + 64 - 1]
+    );
+    assert_eq!(
+        run("fn check(n: int) -> bool { return n > 0 || 1 / n == 0; }
+             fn main() -> bool { return check(1) && check(-1) == false; }"),
+        &[1]
+    );
+    assert_eq!(
+        run("fn nothing(n: int) { if n > 0 { return; } let x = n; }
+             fn main() { nothing(1); nothing(-1); }"),
+        &[] as &[i64]
+    );
+
+    let locals: String = (0..300)
+        .map(|index| format!("let x{index} = {index};"))
+        .collect();
+    assert!(matches!(
+        lang::compile(&format!("fn main() -> int {{ {locals} return x0; }}")),
+        Err(LangError::TooManyLocals(1))
+    ));
+}
@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use crate::error::LangError;
+
+/// It represents each part of the syntax of `.lang` files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind<'a> {
+    Number(&'a str),
+    Identifier(&'a str),
+    Symbol(&'a str),
+    Fn,
+    Let,
+    If,
+    Else,
+    While,
+    Return,
+    True,
+    False,
+}
+
+impl Display for TokenKind<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(text) | Self::Identifier(text) | Self::Symbol(text) => {
+                write!(f, "{text}")
+            }
+            Self::Fn => write!(f, "fn"),
+            Self::Let => write!(f, "let"),
+            Self::If => write!(f, "if"),
+            Self::Else => write!(f, "else"),
+            Self::While => write!(f, "while"),
+            Self::Return => write!(f, "return"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+        }
+    }
+}
+
+/// A token together with the line it starts on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub line: usize,
+}
+
+/// Symbols, longest first so that `<=` isn't read as `<` and `=`.
+const SYMBOLS: [&str; 23] = [
+    "->", "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "=", "(",
+    ")", "{", "}", ",", ";", ":",
+];
+
+/// Converts the source of a `.lang` file into tokens. `//` starts a comment that runs to the end of the line.
+pub fn tokenize(source_code: &str) -> Result<Vec<Token<'_>>, LangError> {
+    let mut tokens = vec![];
+
+    for (index, line) in source_code.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split_once("//").map_or(line, |(code, _)| code);
+        let mut rest = line.trim_start();
+
+        while let Some(char) = rest.chars().next() {
+            let (kind, length) = if char.is_ascii_digit() || char.is_alphabetic() || char == '_' {
+                let length = rest
+                    .find(|char: char| !(char.is_alphanumeric() || char == '_'))
+                    .unwrap_or(rest.len());
+                let word = &rest[..length];
+
+                let kind = match word {
+                    "fn" => TokenKind::Fn,
+                    "let" => TokenKind::Let,
+                    "if" => TokenKind::If,
+                    "else" => TokenKind::Else,
+                    "while" => TokenKind::While,
+                    "return" => TokenKind::Return,
+                    "true" => TokenKind::True,
+                    "false" => TokenKind::False,
+                    _ if char.is_ascii_digit() => TokenKind::Number(word),
+                    _ => TokenKind::Identifier(word),
+                };
+
+                (kind, length)
+            } else if let Some(symbol) = SYMBOLS.iter().find(|&&symbol| rest.starts_with(symbol)) {
+                (TokenKind::Symbol(symbol), symbol.len())
+            } else {
+                return Err(LangError::UnexpectedCharacter(line_number, char));
+            };
+
+            tokens.push(Token {
+                kind,
+                line: line_number,
+            });
+            rest = rest[length..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[test]
+fn test_tokenizing_language() {
+    let tokens = tokenize("fn main() -> int { // comment\n  return 1 <= x_1;\n}").unwrap();
+    let kinds: Vec<TokenKind> = tokens.iter().map(|token| token.kind).collect();
+
+    assert_eq!(
+        kinds,
+        &[
+            TokenKind::Fn,
+            TokenKind::Identifier("main"),
+            TokenKind::Symbol("("),
+            TokenKind::Symbol(")"),
+            TokenKind::Symbol("->"),
+            TokenKind::Identifier("int"),
+            TokenKind::Symbol("{"),
+            TokenKind::Return,
+            TokenKind::Number("1"),
+            TokenKind::Symbol("<="),
+            TokenKind::Identifier("x_1"),
+            TokenKind::Symbol(";"),
+            TokenKind::Symbol("}"),
+        ]
+    );
+    assert_eq!(tokens[7].line, 2);
+    assert_eq!(tokens[12].line, 3);
+
+    assert!(matches!(
+        tokenize("\nlet x = 1 $ 2;"),
+        Err(LangError::UnexpectedCharacter(2, '$'))
+    ));
+}
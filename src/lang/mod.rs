@@ -0,0 +1,17 @@
+pub mod ast;
+pub mod checker;
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+pub mod resolver;
+
+use crate::{error::LangError, parser::Expression};
+
+/// Compiles the source of a `.lang` file into expressions of the stack machine.
+pub fn compile(source_code: &str) -> Result<Vec<Expression>, LangError> {
+    let tokens = lexer::tokenize(source_code)?;
+    let mut program = parser::parse(&tokens)?;
+    resolver::resolve(&mut program)?;
+    checker::check(&program)?;
+    codegen::generate(&program)
+}
@@ -0,0 +1,359 @@
+use crate::{
+    error::LangError,
+    lang::{
+        ast::{
+            BinaryOperator, Block, Expr, ExprKind, Function, Program, Statement, Type,
+            UnaryOperator,
+        },
+        lexer::{Token, TokenKind},
+    },
+};
+
+/// Prefix operators bind tighter than every binary operator.
+const PREFIX_PRECEDENCE: u8 = 7;
+
+/// A recursive-descent parser over the tokens of a `.lang` file.
+struct Parser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn peek(&self) -> Option<TokenKind<'a>> {
+        self.tokens.get(self.position).map(|token| token.kind)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, LangError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .copied()
+            .ok_or(LangError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Reads the next token if it is `symbol`.
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = self.peek() == Some(TokenKind::Symbol(symbol));
+
+        if found {
+            self.position += 1;
+        }
+
+        found
+    }
+
+    /// Reads the next token and returns an error if it isn't `expected`.
+    fn expect(&mut self, expected: TokenKind) -> Result<(), LangError> {
+        let token = self.next()?;
+
+        match token.kind == expected {
+            true => Ok(()),
+            false => Err(unexpected(token)),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<(String, usize), LangError> {
+        let token = self.next()?;
+
+        match token.kind {
+            TokenKind::Identifier(name) => Ok((name.to_string(), token.line)),
+            _ => Err(unexpected(token)),
+        }
+    }
+
+    fn type_name(&mut self) -> Result<Type, LangError> {
+        let (name, line) = self.identifier()?;
+
+        match name.as_str() {
+            "int" => Ok(Type::Int),
+            "bool" => Ok(Type::Bool),
+            _ => Err(LangError::UnknownType(line, name)),
+        }
+    }
+
+    /// Parses `fn name(parameter: type, ...) -> type { ... }`. The return type is optional.
+    fn function(&mut self) -> Result<Function, LangError> {
+        self.expect(TokenKind::Fn)?;
+        let (name, line) = self.identifier()?;
+        self.expect(TokenKind::Symbol("("))?;
+
+        let mut parameters = vec![];
+
+        while !self.eat(")") {
+            if !parameters.is_empty() {
+                self.expect(TokenKind::Symbol(","))?;
+            }
+
+            let (parameter, _) = self.identifier()?;
+            self.expect(TokenKind::Symbol(":"))?;
+            parameters.push((parameter, self.type_name()?));
+        }
+
+        let return_type = match self.eat("->") {
+            true => self.type_name()?,
+            false => Type::Void,
+        };
+
+        Ok(Function {
+            name,
+            parameters,
+            return_type,
+            body: self.block()?,
+            line,
+        })
+    }
+
+    fn block(&mut self) -> Result<Block, LangError> {
+        self.expect(TokenKind::Symbol("{"))?;
+
+        let mut statements = vec![];
+
+        while !self.eat("}") {
+            statements.push(self.statement()?);
+        }
+
+        Ok(Block { statements })
+    }
+
+    fn statement(&mut self) -> Result<Statement, LangError> {
+        let line = self.tokens.get(self.position).map_or(0, |token| token.line);
+
+        let statement = match self.peek() {
+            Some(TokenKind::Let) => {
+                self.position += 1;
+                let (name, _) = self.identifier()?;
+                let declared_type = match self.eat(":") {
+                    true => Some(self.type_name()?),
+                    false => None,
+                };
+                self.expect(TokenKind::Symbol("="))?;
+
+                Statement::Let {
+                    name,
+                    declared_type,
+                    value: self.expression(0)?,
+                    slot: 0,
+                    line,
+                }
+            }
+            Some(TokenKind::If) => return self.if_statement(),
+            Some(TokenKind::While) => {
+                self.position += 1;
+
+                return Ok(Statement::While {
+                    condition: self.expression(0)?,
+                    body: self.block()?,
+                });
+            }
+            Some(TokenKind::Return) => {
+                self.position += 1;
+
+                let value = match self.peek() {
+                    Some(TokenKind::Symbol(";")) => None,
+                    _ => Some(self.expression(0)?),
+                };
+
+                Statement::Return { value, line }
+            }
+            Some(TokenKind::Identifier(name))
+                if self.tokens.get(self.position + 1).map(|token| token.kind)
+                    == Some(TokenKind::Symbol("=")) =>
+            {
+                self.position += 2;
+
+                Statement::Assign {
+                    name: name.to_string(),
+                    value: self.expression(0)?,
+                    slot: 0,
+                    line,
+                }
+            }
+            _ => Statement::Expression(self.expression(0)?),
+        };
+
+        self.expect(TokenKind::Symbol(";"))?;
+
+        Ok(statement)
+    }
+
+    /// Parses `if condition { ... }` with an optional `else { ... }` or `else if ...`.
+    fn if_statement(&mut self) -> Result<Statement, LangError> {
+        self.expect(TokenKind::If)?;
+
+        let condition = self.expression(0)?;
+        let then_block = self.block()?;
+
+        let else_block = match self.peek() {
+            Some(TokenKind::Else) => {
+                self.position += 1;
+
+                match self.peek() {
+                    Some(TokenKind::If) => Some(Block {
+                        statements: vec![self.if_statement()?],
+                    }),
+                    _ => Some(self.block()?),
+                }
+            }
+            _ => None,
+        };
+
+        Ok(Statement::If {
+            condition,
+            then_block,
+            else_block,
+        })
+    }
+
+    /// Parses an expression whose binary operators bind at least as tightly as `min_precedence`.
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, LangError> {
+        let mut left = self.prefix()?;
+
+        while let Some(TokenKind::Symbol(symbol)) = self.peek() {
+            let Some(operator) = BinaryOperator::from_symbol(symbol) else {
+                break;
+            };
+
+            if operator.precedence() < min_precedence {
+                break;
+            }
+
+            let line = self.next()?.line;
+
+            // Operators of the same precedence are left-associative.
+            let right = self.expression(operator.precedence() + 1)?;
+            left = Expr {
+                kind: ExprKind::Binary(operator, Box::new(left), Box::new(right)),
+                line,
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn prefix(&mut self) -> Result<Expr, LangError> {
+        let token = self.next()?;
+
+        let kind = match token.kind {
+            TokenKind::Number(text) => ExprKind::Number(
+                text.parse()
+                    .map_err(|_| LangError::InvalidNumber(token.line, text.to_string()))?,
+            ),
+            TokenKind::True => ExprKind::Bool(true),
+            TokenKind::False => ExprKind::Bool(false),
+            TokenKind::Identifier(name) if self.eat("(") => {
+                let mut arguments = vec![];
+
+                while !self.eat(")") {
+                    if !arguments.is_empty() {
+                        self.expect(TokenKind::Symbol(","))?;
+                    }
+
+                    arguments.push(self.expression(0)?);
+                }
+
+                ExprKind::Call {
+                    name: name.to_string(),
+                    arguments,
+                    function: 0,
+                }
+            }
+            TokenKind::Identifier(name) => ExprKind::Variable {
+                name: name.to_string(),
+                slot: 0,
+            },
+            TokenKind::Symbol("(") => {
+                let expr = self.expression(0)?;
+                self.expect(TokenKind::Symbol(")"))?;
+                return Ok(expr);
+            }
+            TokenKind::Symbol("-") => ExprKind::Unary(
+                UnaryOperator::Negate,
+                Box::new(self.expression(PREFIX_PRECEDENCE)?),
+            ),
+            TokenKind::Symbol("!") => ExprKind::Unary(
+                UnaryOperator::Not,
+                Box::new(self.expression(PREFIX_PRECEDENCE)?),
+            ),
+            _ => return Err(unexpected(token)),
+        };
+
+        Ok(Expr {
+            kind,
+            line: token.line,
+        })
+    }
+}
+
+fn unexpected(token: Token) -> LangError {
+    LangError::UnexpectedToken(token.line, token.kind.to_string())
+}
+
+/// Parses the tokens of a `.lang` file into functions.
+pub fn parse(tokens: &[Token]) -> Result<Program, LangError> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let mut functions = vec![];
+
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+
+    Ok(Program { functions })
+}
+
+#[test]
+fn test_parsing_language() {
+    use crate::lang::lexer::tokenize;
+
+    let program = parse(
+        &tokenize("fn add(a: int, b: int) -> int { return a + b * 2; }\nfn main() { if a < 1 { } else if true { x = -1; } }")
+            .unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(program.functions.len(), 2);
+    assert_eq!(
+        program.functions[0].parameters,
+        &[("a".to_string(), Type::Int), ("b".to_string(), Type::Int)]
+    );
+    assert_eq!(program.functions[1].return_type, Type::Void);
+
+    let Statement::Return {
+        value: Some(value), ..
+    } = &program.functions[0].body.statements[0]
+    else {
+        panic!("a return statement is expected");
+    };
+    assert!(matches!(
+        &value.kind,
+        ExprKind::Binary(BinaryOperator::Add, _, right)
+            if matches!(right.kind, ExprKind::Binary(BinaryOperator::Mul, _, _))
+    ));
+
+    let Statement::If {
+        else_block: Some(else_block),
+        ..
+    } = &program.functions[1].body.statements[0]
+    else {
+        panic!("an if statement is expected");
+    };
+    assert!(matches!(else_block.statements[0], Statement::If { .. }));
+    assert_eq!(program.functions[1].line, 2);
+
+    assert!(matches!(
+        parse(&tokenize("fn main() {\n let x: string = 1; }").unwrap()),
+        Err(LangError::UnknownType(2, name)) if name == "string"
+    ));
+    assert!(matches!(
+        parse(&tokenize("fn main() { return 1 }").unwrap()),
+        Err(LangError::UnexpectedToken(1, token)) if token == "}"
+    ));
+    assert!(matches!(
+        parse(&tokenize("fn main() {").unwrap()),
+        Err(LangError::UnexpectedEnd)
+    ));
+}
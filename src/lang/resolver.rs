@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::LangError,
+    lang::ast::{Block, Expr, ExprKind, Program, Statement},
+};
+
+/// Finds the variables and functions that names refer to.
+/// A variable's slot is the number of variables that are alive when it is declared.
+struct Resolver<'a> {
+    functions: &'a HashMap<String, (usize, usize)>,
+    scopes: Vec<Vec<String>>,
+}
+
+impl Resolver<'_> {
+    /// Returns the slot of the innermost variable with the name.
+    fn lookup(&self, name: &str, line: usize) -> Result<usize, LangError> {
+        let mut slot = self.scopes.iter().map(Vec::len).sum::<usize>();
+
+        for scope in self.scopes.iter().rev() {
+            slot -= scope.len();
+
+            if let Some(index) = scope.iter().rposition(|variable| variable == name) {
+                return Ok(slot + index);
+            }
+        }
+
+        Err(LangError::UndefinedVariable(line, name.to_string()))
+    }
+
+    /// Adds a variable to the innermost scope and returns its slot.
+    fn declare(&mut self, name: &str, line: usize) -> Result<usize, LangError> {
+        let slot = self.scopes.iter().map(Vec::len).sum();
+        let scope = self.scopes.last_mut().expect("a scope is open");
+
+        if scope.iter().any(|variable| variable == name) {
+            return Err(LangError::DuplicateVariable(line, name.to_string()));
+        }
+
+        scope.push(name.to_string());
+        Ok(slot)
+    }
+
+    fn block(&mut self, block: &mut Block) -> Result<(), LangError> {
+        self.scopes.push(vec![]);
+
+        for statement in &mut block.statements {
+            self.statement(statement)?;
+        }
+
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &mut Statement) -> Result<(), LangError> {
+        match statement {
+            Statement::Let {
+                name,
+                value,
+                slot,
+                line,
+                ..
+            } => {
+                // The value is resolved first, so it can't refer to the variable it initializes.
+                self.expression(value)?;
+                *slot = self.declare(name, *line)?;
+            }
+            Statement::Assign {
+                name,
+                value,
+                slot,
+                line,
+            } => {
+                self.expression(value)?;
+                *slot = self.lookup(name, *line)?;
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.expression(condition)?;
+                self.block(then_block)?;
+
+                if let Some(else_block) = else_block {
+                    self.block(else_block)?;
+                }
+            }
+            Statement::While { condition, body } => {
+                self.expression(condition)?;
+                self.block(body)?;
+            }
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value)?;
+                }
+            }
+            Statement::Expression(expr) => self.expression(expr)?,
+        }
+
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &mut Expr) -> Result<(), LangError> {
+        match &mut expr.kind {
+            ExprKind::Number(_) | ExprKind::Bool(_) => {}
+            ExprKind::Variable { name, slot } => *slot = self.lookup(name, expr.line)?,
+            ExprKind::Call {
+                name,
+                arguments,
+                function,
+            } => {
+                let &(index, expected) = self
+                    .functions
+                    .get(name.as_str())
+                    .ok_or_else(|| LangError::UndefinedFunction(expr.line, name.clone()))?;
+
+                if arguments.len() != expected {
+                    return Err(LangError::WrongArgumentCount {
+                        line: expr.line,
+                        function: name.clone(),
+                        expected,
+                        found: arguments.len(),
+                    });
+                }
+
+                *function = index;
+
+                for argument in arguments {
+                    self.expression(argument)?;
+                }
+            }
+            ExprKind::Unary(_, operand) => self.expression(operand)?,
+            ExprKind::Binary(_, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fills in the slots of variables and the indices of called functions.
+/// It also checks that there is a `main` function without parameters.
+pub fn resolve(program: &mut Program) -> Result<(), LangError> {
+    let mut functions = HashMap::new();
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let signature = (index, function.parameters.len());
+
+        if functions.insert(function.name.clone(), signature).is_some() {
+            return Err(LangError::DuplicateFunction(
+                function.line,
+                function.name.clone(),
+            ));
+        }
+    }
+
+    match program
+        .functions
+        .iter()
+        .find(|function| function.name == "main")
+    {
+        None => return Err(LangError::MainNotFound),
+        Some(main) if !main.parameters.is_empty() => {
+            return Err(LangError::MainHasParameters(main.line))
+        }
+        Some(_) => {}
+    }
+
+    for function in &mut program.functions {
+        let mut resolver = Resolver {
+            functions: &functions,
+            scopes: vec![vec![]],
+        };
+
+        for (parameter, _) in &function.parameters {
+            resolver.declare(parameter, function.line)?;
+        }
+
+        resolver.block(&mut function.body)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resolving_names() {
+    use crate::lang::{lexer::tokenize, parser::parse};
+
+    let resolve_source = |source_code: &str| {
+        let mut program = parse(&tokenize(source_code).unwrap()).unwrap();
+        resolve(&mut program).map(|_| program)
+    };
+
+    let program = resolve_source(
+        "fn f(a: int) -> int { let b = a; if true { let a = b; a = 2; } return a; }
+         fn main() { f(1); }",
+    )
+    .unwrap();
+    let statements = &program.functions[0].body.statements;
+
+    assert!(matches!(statements[0], Statement::Let { slot: 1, .. }));
+    let Statement::If { then_block, .. } = &statements[1] else {
+        panic!("an if statement is expected");
+    };
+    assert!(matches!(
+        then_block.statements[0],
+        Statement::Let { slot: 2, .. }
+    ));
+    assert!(matches!(
+        then_block.statements[1],
+        Statement::Assign { slot: 2, .. }
+    ));
+    assert!(matches!(
+        &statements[2],
+        Statement::Return {
+            value: Some(Expr {
+                kind: ExprKind::Variable { slot: 0, .. },
+                ..
+            }),
+            ..
+        }
+    ));
+    assert!(matches!(
+        &program.functions[1].body.statements[0],
+        Statement::Expression(Expr {
+            kind: ExprKind::Call { function: 0, .. },
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        resolve_source("fn main() {\n x = 1; }"),
+        Err(LangError::UndefinedVariable(2, name)) if name == "x"
+    ));
+    assert!(matches!(
+        resolve_source("fn main() { let x = 1; let x = 2; }"),
+        Err(LangError::DuplicateVariable(1, name)) if name == "x"
+    ));
+    assert!(matches!(
+        resolve_source("fn main() { g(); }"),
+        Err(LangError::UndefinedFunction(1, name)) if name == "g"
+    ));
+    assert!(matches!(
+        resolve_source("fn f(a: int) {} fn main() { f(1, 2); }"),
+        Err(LangError::WrongArgumentCount {
+            expected: 1,
+            found: 2,
+            ..
+        })
+    ));
+    assert!(matches!(
+        resolve_source("fn f() {} fn f() {} fn main() {}"),
+        Err(LangError::DuplicateFunction(1, name)) if name == "f"
+    ));
+    assert!(matches!(
+        resolve_source("fn f() {}"),
+        Err(LangError::MainNotFound)
+    ));
+    assert!(matches!(
+        resolve_source("fn main(a: int) {}"),
+        Err(LangError::MainHasParameters(1))
+    ));
+}
@@ -1,58 +1,112 @@
+use std::fmt::Display;
+
+use crate::source::{Located, Location};
+
 /// It represents each part of the syntax.
 #[derive(Debug, PartialEq)]
-pub enum Token<'a> {
+pub enum TokenKind<'a> {
     Number(&'a str),
     Opcode(&'a str),
+    Label(&'a str),
+    /// A quoted text without its quotes, like the path of `.include`.
+    Text(&'a str),
+}
+
+impl Display for TokenKind<'_> {
+    /// Formats the token as it is written in source code.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Number(text) | TokenKind::Opcode(text) => write!(f, "{text}"),
+            TokenKind::Label(label) => write!(f, "{label}:"),
+            TokenKind::Text(text) => write!(f, "\"{text}\""),
+        }
+    }
 }
 
-/// Converts source code into tokens.
-pub fn tokenize<'a>(source_code: &'a str) -> Vec<Token<'a>> {
+/// A token together with the file and line it is written on.
+pub type Token<'a> = Located<TokenKind<'a>>;
+
+/// Converts source code that isn't from a file into tokens.
+#[cfg(test)]
+pub fn tokenize(source_code: &str) -> Vec<Token<'_>> {
+    tokenize_file(
+        source_code,
+        Location {
+            file: std::rc::Rc::from(""),
+            line: 1,
+            origin: None,
+        },
+    )
+}
+
+/// Converts source code into tokens. `start` is the location of the first line.
+pub fn tokenize_file(source_code: &str, start: Location) -> Vec<Token<'_>> {
     let mut tokens = vec![];
-    let mut current_index = 0;
-
-    let mut number_start_index: Option<usize> = None;
-    let mut opcode_start_index: Option<usize> = None;
-
-    while let Some(&char) = source_code.as_bytes().get(current_index) {
-        match char {
-            b' ' | b'\n' => {
-                if let Some(start_index) = opcode_start_index {
-                    let opcode = &source_code[start_index..current_index];
-                    tokens.push(Token::Opcode(opcode));
-                    opcode_start_index = None;
-                }
+    let mut word_start_index: Option<usize> = None;
+    let mut location = start;
+    let mut text_end = 0;
 
-                if let Some(start_index) = number_start_index {
-                    let number = &source_code[start_index..current_index];
-                    tokens.push(Token::Number(number));
-                    number_start_index = None;
-                }
+    for (current_index, char) in source_code.char_indices() {
+        if current_index < text_end {
+            continue;
+        }
+
+        if char.is_whitespace() {
+            if let Some(start_index) = word_start_index.take() {
+                tokens.push(Located {
+                    value: classify(&source_code[start_index..current_index]),
+                    location: location.clone(),
+                });
             }
-            b'0'..=b'9' | b'-' => {
-                if let None = number_start_index {
-                    number_start_index = Some(current_index);
-                }
+
+            if char == '\n' {
+                location.line += 1;
             }
-            _ => {
-                if let None = opcode_start_index {
-                    opcode_start_index = Some(current_index);
+        } else if word_start_index.is_none() {
+            // A quoted text can contain whitespace, but it must be closed on the same line.
+            let rest = &source_code[current_index + 1..];
+            let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+
+            match line.find('"').filter(|_| char == '"') {
+                Some(length) => {
+                    tokens.push(Located {
+                        value: TokenKind::Text(&line[..length]),
+                        location: location.clone(),
+                    });
+                    text_end = current_index + length + 2;
                 }
+                None => word_start_index = Some(current_index),
             }
-        };
-        current_index += 1;
+        }
     }
 
-    if let Some(start_index) = opcode_start_index {
-        let opcode = &source_code[start_index..current_index];
-        tokens.push(Token::Opcode(opcode));
+    if let Some(start_index) = word_start_index {
+        tokens.push(Located {
+            value: classify(&source_code[start_index..]),
+            location,
+        });
     }
 
-    if let Some(start_index) = number_start_index {
-        let number = &source_code[start_index..current_index];
-        tokens.push(Token::Number(number));
+    tokens
+}
+
+/// Decides which token a word separated by whitespace is.
+pub fn classify(word: &str) -> TokenKind<'_> {
+    if let Some(text) = word
+        .strip_prefix('"')
+        .and_then(|word| word.strip_suffix('"'))
+    {
+        return TokenKind::Text(text);
     }
 
-    tokens
+    if let Some(label) = word.strip_suffix(':') {
+        return TokenKind::Label(label);
+    }
+
+    match word.as_bytes()[0] {
+        b'0'..=b'9' | b'-' => TokenKind::Number(word),
+        _ => TokenKind::Opcode(word),
+    }
 }
 
 #[test]
@@ -75,36 +129,81 @@ fn test_tokenization() {
     RET
     ";
 
-    let tokens = tokenize(source_code);
+    let tokens: Vec<TokenKind> = tokenize(source_code)
+        .into_iter()
+        .map(|token| token.value)
+        .collect();
 
     assert_eq!(
         &tokens,
         &[
-            Token::Opcode("PUSH"),
-            Token::Number("10"),
-            Token::Opcode("PUSH"),
-            Token::Number("40"),
-            Token::Opcode("ADD"),
-            Token::Opcode("STORE"),
-            Token::Number("0"),
-            Token::Opcode("PUSH"),
-            Token::Number("6"),
-            Token::Opcode("PUSH"),
-            Token::Number("-2"),
-            Token::Opcode("SUB"),
-            Token::Opcode("STORE"),
-            Token::Number("1"),
-            Token::Opcode("PUSH"),
-            Token::Number("10"),
-            Token::Opcode("PUSH"),
-            Token::Number("20"),
-            Token::Opcode("DIV"),
-            Token::Opcode("LOAD"),
-            Token::Number("0"),
-            Token::Opcode("LOAD"),
-            Token::Number("1"),
-            Token::Opcode("MUL"),
-            Token::Opcode("RET")
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("10"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("40"),
+            TokenKind::Opcode("ADD"),
+            TokenKind::Opcode("STORE"),
+            TokenKind::Number("0"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("6"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("-2"),
+            TokenKind::Opcode("SUB"),
+            TokenKind::Opcode("STORE"),
+            TokenKind::Number("1"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("10"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("20"),
+            TokenKind::Opcode("DIV"),
+            TokenKind::Opcode("LOAD"),
+            TokenKind::Number("0"),
+            TokenKind::Opcode("LOAD"),
+            TokenKind::Number("1"),
+            TokenKind::Opcode("MUL"),
+            TokenKind::Opcode("RET")
         ],
     )
 }
+
+#[test]
+fn test_tokenizing_labels() {
+    let source_code = "loop1:\n\tPUSH -1\r\n\tJNZ loop1";
+
+    let tokens: Vec<TokenKind> = tokenize(source_code)
+        .into_iter()
+        .map(|token| token.value)
+        .collect();
+
+    assert_eq!(
+        &tokens,
+        &[
+            TokenKind::Label("loop1"),
+            TokenKind::Opcode("PUSH"),
+            TokenKind::Number("-1"),
+            TokenKind::Opcode("JNZ"),
+            TokenKind::Opcode("loop1"),
+        ],
+    )
+}
+
+#[test]
+fn test_tokenizing_locations() {
+    let tokens = tokenize("PUSH 1\n\n.include \"my lib.code\" RET\n\"open");
+
+    let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.value).collect();
+    assert_eq!(
+        kinds,
+        [
+            &TokenKind::Opcode("PUSH"),
+            &TokenKind::Number("1"),
+            &TokenKind::Opcode(".include"),
+            &TokenKind::Text("my lib.code"),
+            &TokenKind::Opcode("RET"),
+            &TokenKind::Opcode("\"open"),
+        ]
+    );
+
+    let lines: Vec<usize> = tokens.iter().map(|token| token.location.line).collect();
+    assert_eq!(lines, [1, 1, 3, 3, 3, 4]);
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::{
+    bytecode,
+    error::LinkError,
+    object::{ObjectFile, RelocationTarget},
+};
+
+/// Links object files into bytecode. The code of the object files is placed in the given order,
+/// so the program starts at the code of the first one.
+/// Only one object file can have data, since the offsets in its data are fixed when it is compiled.
+pub fn link(objects: &[ObjectFile]) -> Result<Vec<u8>, LinkError> {
+    let mut bytecode: Vec<u8> = vec![];
+    let mut symbols: HashMap<&str, u32> = HashMap::new();
+    let mut bases: Vec<usize> = vec![];
+
+    let mut data = objects.iter().filter(|object| !object.data.is_empty());
+    let first_data = data.next().map(|object| object.data.as_slice());
+
+    if data.next().is_some() {
+        return Err(LinkError::SeveralDataSections);
+    }
+
+    bytecode::write_header(&mut bytecode, first_data.unwrap_or_default());
+
+    for object in objects {
+        let base = bytecode.len();
+
+        for (name, offset) in &object.exports {
+            if symbols.insert(name, relocate(base, *offset)?).is_some() {
+                return Err(LinkError::DuplicateSymbol(name.clone()));
+            }
+        }
+
+        bases.push(base);
+        bytecode.extend_from_slice(&object.code);
+    }
+
+    for (object, base) in objects.iter().zip(bases) {
+        for relocation in &object.relocations {
+            let position = base + relocation.offset as usize;
+            let address = &mut bytecode[position..position + 4];
+
+            let resolved = match relocation.target {
+                RelocationTarget::Local => {
+                    relocate(base, u32::from_le_bytes(address.try_into().unwrap()))?
+                }
+                RelocationTarget::Import(index) => {
+                    let name = &object.imports[index as usize];
+                    *symbols
+                        .get(name.as_str())
+                        .ok_or_else(|| LinkError::UndefinedSymbol(name.clone()))?
+                }
+            };
+
+            address.copy_from_slice(&resolved.to_le_bytes());
+        }
+    }
+
+    Ok(bytecode)
+}
+
+/// Returns the address of an offset in the code that is placed at `base` in the bytecode.
+fn relocate(base: usize, offset: u32) -> Result<u32, LinkError> {
+    u32::try_from(base)
+        .ok()
+        .and_then(|base| base.checked_add(offset))
+        .ok_or(LinkError::ProgramTooLarge)
+}
+
+#[test]
+fn test_linking() {
+    use crate::{
+        compiler::compile_object, lexer::tokenize, parser::parse_module,
+        virtual_machine::VirtualMachine,
+    };
+
+    let object = |source_code: &str| compile_object(parse_module(tokenize(source_code)).unwrap());
+
+    let main = object("PUSH 5 CALL triple loop: PUSH 1 SUB DUP JNZ loop CALL double RET");
+    let library = object(
+        ".global double .global triple
+         triple: DUP DUP ADD ADD JMP done
+         done: RETURN
+         double: DUP ADD RETURN",
+    );
+
+    assert_eq!(main.imports, &["triple", "double"]);
+    assert_eq!(library.exports.len(), 2);
+
+    let bytecode = link(&[main.clone(), library.clone()]).unwrap();
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run().unwrap(), &[0]);
+
+    let main = object("PUSH 7 CALL triple CALL double RET");
+    let bytecode = link(&[main.clone(), library.clone()]).unwrap();
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run().unwrap(), &[42]);
+
+    assert!(matches!(
+        link(&[main.clone(), library.clone(), library.clone()]),
+        Err(LinkError::DuplicateSymbol(symbol)) if symbol == "double"
+    ));
+    assert!(matches!(
+        link(&[main]),
+        Err(LinkError::UndefinedSymbol(symbol)) if symbol == "triple"
+    ));
+
+    let main = object(".data\nvalue: .quad 6\n.text\nPUSH value LOAD64 CALL double RET");
+    let bytecode = link(&[main.clone(), library.clone()]).unwrap();
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run().unwrap(), &[12]);
+
+    assert!(matches!(
+        relocate(9, u32::MAX),
+        Err(LinkError::ProgramTooLarge)
+    ));
+
+    let data = object(".data\n.byte 1");
+    assert!(matches!(
+        link(&[main, library, data]),
+        Err(LinkError::SeveralDataSections)
+    ));
+}
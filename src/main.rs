@@ -1,17 +1,46 @@
 use std::{env::args, ops::Deref};
 
-use compiler::compile;
-use error::UserError;
-use lexer::tokenize;
-use virtual_machine::VirtualMachine;
+use backend::Target;
+use compiler::{compile, compile_object};
+use diagnostics::check;
+use error::{LinkError, UserError};
+use lexer::tokenize_file;
+use linker::link;
+use object::ObjectFile;
+use optimizer::optimize;
+use pool::VmPool;
+use register_compiler::translate;
+use register_vm::RegisterVm;
+use source::Location;
+use virtual_machine::{RunState, VirtualMachine};
 
-use crate::parser::parse;
+use crate::parser::{parse, parse_module, Expression};
 
+mod backend;
+mod bytecode;
+mod cfg;
 mod compiler;
+mod diagnostics;
 mod error;
+mod expr;
+mod forth;
+mod instruction;
+#[cfg(feature = "jit")]
+mod jit;
+mod lang;
 mod lexer;
+mod linker;
+mod object;
 mod opcode;
+mod optimizer;
 mod parser;
+mod pool;
+#[cfg(test)]
+mod random_program;
+mod register_compiler;
+mod register_vm;
+mod snapshot;
+mod source;
 mod value;
 mod virtual_machine;
 
@@ -22,61 +51,178 @@ fn main() {
     let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
 
     match args.deref() {
-        ["run", file_path] => {
-            let file_content = match std::fs::read_to_string(file_path) {
-                Ok(content) => content,
-                Err(_) => return eprintln!("{}", UserError::FileNotFound(file_path)),
+        ["run", "--registers", file_path] => {
+            let expressions = match read_expressions(file_path, false) {
+                Ok(expressions) => expressions,
+                Err(error) => return eprintln!("{error}"),
             };
 
-            let bytecode = if file_path.ends_with(".bin") {
-                file_content.into_bytes()
-            } else {
-                let tokens = tokenize(&file_content);
-                let expressions = match parse(tokens) {
-                    Ok(expressions) => expressions,
-                    Err(error) => return eprintln!("{error}"),
-                };
-                compile(expressions)
+            let instructions = match translate(&expressions) {
+                Ok(instructions) => instructions,
+                Err(error) => return eprintln!("{error}"),
             };
 
-            let mut virtual_machine = VirtualMachine::new(bytecode);
+            let mut register_vm = RegisterVm::new(instructions);
 
-            match virtual_machine.run() {
+            match register_vm.run() {
                 Ok(result) => {
                     println!("PROGRAM RESULT: {:#?}", result)
                 }
-                Err(error) => return eprintln!("{error}"),
+                Err(error) => eprintln!("{error}"),
             };
         }
-        ["compile", file_path] => {
-            let file_content = match std::fs::read_to_string(file_path) {
-                Ok(content) => content,
-                Err(_) => return eprintln!("{}", UserError::FileNotFound(&file_path)),
-            };
+        ["run", options @ .., file_path] => run(options, file_path, false),
+        ["resume", options @ .., snapshot_path] => run(options, snapshot_path, true),
+        ["compile", options @ .., file_path] => {
+            let mut optimization = false;
+            let mut object = false;
+            let mut expand = false;
+            let mut target = Target::Bytecode;
+            let mut options = options.iter();
 
-            let bytecode = if file_path.ends_with(".bin") {
+            while let Some(option) = options.next() {
+                match *option {
+                    "-O" => optimization = true,
+                    "-c" => object = true,
+                    "--expand" => expand = true,
+                    "--target" => {
+                        let Some(name) = options.next() else {
+                            return eprintln!("{}", UserError::TargetRequired);
+                        };
+
+                        match Target::from_name(name) {
+                            Some(name) => target = name,
+                            None => return eprintln!("{}", UserError::UnknownTarget(name)),
+                        }
+                    }
+                    _ => return eprintln!("{}", UserError::UnknownOption(option)),
+                }
+            }
+
+            if file_path.ends_with(".bin") {
                 return eprintln!("this file is already compiled");
-            } else {
-                let tokens = tokenize(&file_content);
-                let expressions = match parse(tokens) {
-                    Ok(expressions) => expressions,
-                    Err(error) => return eprintln!("{error}"),
-                };
-                compile(expressions)
+            }
+
+            if object && target != Target::Bytecode {
+                return eprintln!("{}", UserError::ConflictingOptions("-c", "--target"));
+            }
+
+            let expressions = match read_expressions(file_path, object) {
+                Ok(expressions) => expressions,
+                Err(error) => return eprintln!("{error}"),
+            };
+
+            let expressions = match optimization {
+                true => optimize(expressions),
+                false => expressions,
             };
 
+            // The expanded assembly is printed instead of being compiled.
+            if expand {
+                for expression in expressions {
+                    println!("{expression}");
+                }
+
+                return;
+            }
+
             let file_name = std::path::Path::new(file_path)
                 .file_name()
                 .unwrap()
                 .to_str()
                 .unwrap();
 
-            std::fs::write(format!("{file_name}.bin"), bytecode).unwrap();
+            if object {
+                let object = compile_object(expressions);
+                std::fs::write(format!("{file_name}.o"), object.to_bytes()).unwrap();
+
+                return println!("program is compiled and `{file_name}.o` is created");
+            }
+
+            let bytecode = compile(expressions);
+
+            let output = match backend::generate(target, bytecode) {
+                Ok(output) => output,
+                Err(error) => return eprintln!("{error}"),
+            };
 
-            println!("program is compiled and `{file_name}.bin` is created")
+            let extension = target.extension();
+            std::fs::write(format!("{file_name}.{extension}"), output).unwrap();
+
+            println!("program is compiled and `{file_name}.{extension}` is created")
+        }
+
+        ["link", object_paths @ .., "-o", output_path] if !object_paths.is_empty() => {
+            let mut objects = vec![];
+
+            for object_path in object_paths {
+                let Ok(bytes) = std::fs::read(object_path) else {
+                    return eprintln!("{}", UserError::FileNotFound(object_path));
+                };
+
+                match ObjectFile::from_bytes(&bytes) {
+                    Some(object) => objects.push(object),
+                    None => {
+                        let error = LinkError::NotAnObjectFile(object_path.to_string());
+                        return eprintln!("{error}");
+                    }
+                }
+            }
+
+            match link(&objects) {
+                Ok(bytecode) => {
+                    std::fs::write(output_path, bytecode).unwrap();
+                    println!("program is linked and `{output_path}` is created")
+                }
+                Err(error) => eprintln!("{error}"),
+            }
         }
 
-        ["run"] | ["compile"] => {
+        ["batch", file_paths @ ..] if !file_paths.is_empty() => {
+            let mut programs = vec![];
+
+            for file_path in file_paths {
+                match read_bytecode(file_path) {
+                    Ok(bytecode) => programs.push(bytecode),
+                    Err(error) => return eprintln!("{error}"),
+                }
+            }
+
+            let workers = std::thread::available_parallelism().map_or(1, |workers| workers.get());
+
+            for (file_path, result) in file_paths.iter().zip(VmPool::new(workers).run(programs)) {
+                match result {
+                    Ok(result) => println!("{file_path} RESULT: {:#?}", result),
+                    Err(error) => eprintln!("{file_path}: {error}"),
+                }
+            }
+        }
+        ["bench", file_path] => {
+            let bytecode = match read_bytecode(file_path) {
+                Ok(bytecode) => bytecode,
+                Err(error) => return eprintln!("{error}"),
+            };
+
+            let byte_level = bench(|| {
+                VirtualMachine::new(bytecode.clone())
+                    .run_bytecode()
+                    .map(|_| ())
+            });
+            let pre_decoded = bench(|| VirtualMachine::new(bytecode.clone()).run().map(|_| ()));
+
+            match (byte_level, pre_decoded) {
+                (Ok(byte_level), Ok(pre_decoded)) => {
+                    println!("byte-level:  {byte_level:?} per run");
+                    println!("pre-decoded: {pre_decoded:?} per run");
+                    println!(
+                        "speedup:     {:.2}x",
+                        byte_level.as_secs_f64() / pre_decoded.as_secs_f64()
+                    );
+                }
+                (Err(error), _) | (_, Err(error)) => eprintln!("{error}"),
+            }
+        }
+        ["run"] | ["compile"] | ["link", ..] | ["batch"] | ["bench"] | ["resume", ..] => {
             let error = UserError::NoFilenameGiven;
             eprintln!("{error}");
         }
@@ -86,9 +232,171 @@ fn main() {
 
             eprintln!("COMMANDS:");
             eprintln!("run <file>      runs the program");
+            eprintln!("    --registers     runs the program on the register machine");
+            eprintln!(
+                "    --round-robin   switches fibers that yield without being resumed in turn"
+            );
+            eprintln!(
+                "    --snapshot-on-exit      saves the state of the program to `<file>.snapshot` when it stops"
+            );
+            eprintln!("    --steps <number>    pauses the program after a number of instructions");
+            eprintln!("    --timeout <ms>      pauses the program after a number of milliseconds");
+            eprintln!("resume <snapshot>      continues the program from a snapshot, with the options of `run`");
             eprintln!(
                 "compile <file>      compiles the program and creates a bytecode executable file"
             );
+            eprintln!("    -O              optimizes the program before compiling it");
+            eprintln!(
+                "    --expand        prints the assembly with macros and included files expanded"
+            );
+            eprintln!("    -c              creates an object file to be linked with `link`");
+            eprintln!("    --target <name>     creates `bytecode` (default), `x86_64-asm`, `c`, `wasm` or `wat`");
+            eprintln!(
+                "link <files> -o <output>      links object files into a bytecode executable file"
+            );
+            eprintln!("batch <files>      runs the programs in parallel as jobs that can send values to each other");
+            eprintln!("bench <file>      compares the byte-level and pre-decoded interpreters");
+        }
+    }
+}
+
+/// Runs a program from the start, or a snapshot if `resume` is true, with the options of `run`.
+fn run(options: &[&str], file_path: &str, resume: bool) {
+    let mut round_robin = false;
+    let mut snapshot_on_exit = false;
+    let mut steps = usize::MAX;
+    let mut timeout = None;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        match *option {
+            "--round-robin" => round_robin = true,
+            "--snapshot-on-exit" => snapshot_on_exit = true,
+            "--steps" | "--timeout" => {
+                let Some(number) = options.next().and_then(|number| number.parse().ok()) else {
+                    return eprintln!("{}", UserError::NumberRequired(option));
+                };
+
+                match *option {
+                    "--steps" => steps = number,
+                    _ => timeout = Some(std::time::Duration::from_millis(number as u64)),
+                }
+            }
+            _ => return eprintln!("{}", UserError::UnknownOption(option)),
+        }
+    }
+
+    let mut virtual_machine = if resume {
+        let Ok(snapshot) = std::fs::read(file_path) else {
+            return eprintln!("{}", UserError::FileNotFound(file_path));
+        };
+
+        match VirtualMachine::from_snapshot(&snapshot) {
+            Ok(virtual_machine) => virtual_machine,
+            Err(error) => return eprintln!("{error}"),
+        }
+    } else {
+        match read_bytecode(file_path) {
+            Ok(bytecode) => VirtualMachine::new(bytecode),
+            Err(error) => return eprintln!("{error}"),
         }
+    };
+
+    virtual_machine.set_round_robin(round_robin);
+
+    if let Some(timeout) = timeout {
+        let interrupt = virtual_machine.interrupt();
+        std::thread::spawn(move || {
+            std::thread::sleep(timeout);
+            interrupt.interrupt();
+        });
     }
+
+    match virtual_machine.run_for(steps) {
+        RunState::Completed(result) => {
+            println!("PROGRAM RESULT: {:#?}", result)
+        }
+        RunState::Paused => println!("program is paused"),
+        RunState::Error(error) => eprintln!("{error}"),
+    };
+
+    if snapshot_on_exit {
+        let file_name = std::path::Path::new(file_path)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap();
+
+        // A resumed program saves its state over the snapshot it continued from.
+        let snapshot_name = if resume {
+            file_name.to_string()
+        } else {
+            format!("{file_name}.snapshot")
+        };
+
+        std::fs::write(&snapshot_name, virtual_machine.snapshot()).unwrap();
+
+        println!("snapshot is saved to `{snapshot_name}`");
+    }
+}
+
+/// Reads a source file, either assembly, an `.expr`, `.fs` or `.lang` file, and parses it into expressions. Warnings are printed as they are found.
+/// Assembly of a module can refer to labels that are imported from other object files.
+fn read_expressions(file_path: &str, module: bool) -> Result<Vec<Expression>, String> {
+    let file_content = std::fs::read_to_string(file_path)
+        .map_err(|_| UserError::FileNotFound(file_path).to_string())?;
+
+    let expressions = if file_path.ends_with(".expr") {
+        expr::compile(&file_content).map_err(|error| error.to_string())?
+    } else if file_path.ends_with(".fs") {
+        forth::compile(&file_content).map_err(|error| error.to_string())?
+    } else if file_path.ends_with(".lang") {
+        lang::compile(&file_content).map_err(|error| error.to_string())?
+    } else {
+        // Included files are found relative to this file.
+        let start = Location {
+            file: file_path.into(),
+            line: 1,
+            origin: None,
+        };
+        let tokens = tokenize_file(&file_content, start);
+
+        match module {
+            true => parse_module(tokens),
+            false => parse(tokens),
+        }
+        .map_err(|error| error.to_string())?
+    };
+
+    for warning in check(&expressions) {
+        eprintln!("{warning}");
+    }
+
+    Ok(expressions)
+}
+
+/// Reads a compiled bytecode file, or reads a source file and compiles it.
+fn read_bytecode(file_path: &str) -> Result<Vec<u8>, String> {
+    if file_path.ends_with(".bin") {
+        return std::fs::read(file_path)
+            .map_err(|_| UserError::FileNotFound(file_path).to_string());
+    }
+
+    read_expressions(file_path, false).map(compile)
+}
+
+/// The number of times `bench` runs a program with each interpreter.
+const BENCH_RUNS: u32 = 10;
+
+/// Measures the average duration of running a program.
+fn bench(
+    mut run: impl FnMut() -> Result<(), error::VmError>,
+) -> Result<std::time::Duration, error::VmError> {
+    let start = std::time::Instant::now();
+
+    for _ in 0..BENCH_RUNS {
+        run()?;
+    }
+
+    Ok(start.elapsed() / BENCH_RUNS)
 }
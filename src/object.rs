@@ -0,0 +1,203 @@
+use crate::bytecode::CURRENT_VERSION;
+
+/// Marks the start of an object file, like `MAGIC` marks bytecode.
+pub const OBJECT_MAGIC: [u8; 4] = [0xFF, b'B', b'C', b'O'];
+
+/// What the address at a relocation refers to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationTarget {
+    /// A label of the same object file. The address is relative to the start of its code.
+    Local,
+    /// The symbol at this index of the imports.
+    Import(u32),
+}
+
+/// An address in the code that is only known after linking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relocation {
+    pub offset: u32,
+    pub target: RelocationTarget,
+}
+
+/// Code that is compiled separately and linked into a program later.
+/// The code has no header, and its addresses are filled in by the linker.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectFile {
+    pub code: Vec<u8>,
+    /// The data of the `.data` section, which initializes the memory.
+    pub data: Vec<u8>,
+    /// The exported labels and their offsets in the code.
+    pub exports: Vec<(String, u32)>,
+    /// The labels that are referred to but defined by other object files.
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    /// Writes the object file, starting with `OBJECT_MAGIC` and the bytecode format version of its code.
+    /// Every count, length and offset is a little-endian `u32`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.push(CURRENT_VERSION);
+
+        write_u32(&mut bytes, self.code.len() as u32);
+        bytes.extend_from_slice(&self.code);
+
+        write_u32(&mut bytes, self.data.len() as u32);
+        bytes.extend_from_slice(&self.data);
+
+        write_u32(&mut bytes, self.exports.len() as u32);
+        for (name, offset) in &self.exports {
+            write_name(&mut bytes, name);
+            write_u32(&mut bytes, *offset);
+        }
+
+        write_u32(&mut bytes, self.imports.len() as u32);
+        for name in &self.imports {
+            write_name(&mut bytes, name);
+        }
+
+        write_u32(&mut bytes, self.relocations.len() as u32);
+        for relocation in &self.relocations {
+            write_u32(&mut bytes, relocation.offset);
+
+            match relocation.target {
+                RelocationTarget::Local => bytes.push(0),
+                RelocationTarget::Import(index) => {
+                    bytes.push(1);
+                    write_u32(&mut bytes, index);
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// Reads an object file. It returns `None` if the bytes are not an object file of the current format.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(OBJECT_MAGIC.len())? != OBJECT_MAGIC || reader.take(1)? != [CURRENT_VERSION]
+        {
+            return None;
+        }
+
+        let code_length = reader.u32()?;
+        let code = reader.take(code_length as usize)?.to_vec();
+
+        let data_length = reader.u32()?;
+        let data = reader.take(data_length as usize)?.to_vec();
+
+        // Exported labels must be in the code, or right after it.
+        let exports = (0..reader.u32()?)
+            .map(|_| {
+                let (name, offset) = (reader.name()?, reader.u32()?);
+                (offset as usize <= code.len()).then_some((name, offset))
+            })
+            .collect::<Option<_>>()?;
+
+        let imports = (0..reader.u32()?)
+            .map(|_| reader.name())
+            .collect::<Option<Vec<_>>>()?;
+
+        let relocations = (0..reader.u32()?)
+            .map(|_| {
+                let offset = reader.u32()?;
+                let target = match reader.take(1)? {
+                    // The label that a local address refers to must be in the code, or right after it.
+                    [0] => {
+                        let address = code.get(offset as usize..offset as usize + 4)?;
+                        let address = u32::from_le_bytes(address.try_into().ok()?);
+                        (address as usize <= code.len()).then_some(RelocationTarget::Local)?
+                    }
+                    [1] => {
+                        let index = reader.u32()?;
+                        ((index as usize) < imports.len())
+                            .then_some(RelocationTarget::Import(index))?
+                    }
+                    _ => return None,
+                };
+
+                // The address must fit in the code.
+                (offset as usize + 4 <= code.len()).then_some(Relocation { offset, target })
+            })
+            .collect::<Option<_>>()?;
+
+        (reader.position == bytes.len()).then_some(Self {
+            code,
+            data,
+            exports,
+            imports,
+            relocations,
+        })
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_u32(bytes, name.len() as u32);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.checked_add(length)?)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let length = self.u32()?;
+        String::from_utf8(self.take(length as usize)?.to_vec()).ok()
+    }
+}
+
+#[test]
+fn test_object_file_bytes() {
+    let object = ObjectFile {
+        code: vec![1, 2, 0, 0, 0, 0, 3, 0, 0, 0, 0],
+        data: vec![4, 5],
+        exports: vec![("main".to_string(), 0)],
+        imports: vec!["double".to_string()],
+        relocations: vec![
+            Relocation {
+                offset: 2,
+                target: RelocationTarget::Local,
+            },
+            Relocation {
+                offset: 7,
+                target: RelocationTarget::Import(0),
+            },
+        ],
+    };
+
+    let bytes = object.to_bytes();
+    assert!(bytes.starts_with(&OBJECT_MAGIC));
+    assert_eq!(ObjectFile::from_bytes(&bytes), Some(object.clone()));
+
+    assert_eq!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(ObjectFile::from_bytes(&[0xFF, b'B', b'C', b'V', 2]), None);
+
+    // Labels out of the code are rejected.
+    let mut outside = object.clone();
+    outside.code[2..6].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(ObjectFile::from_bytes(&outside.to_bytes()), None);
+
+    let mut outside = object;
+    outside.exports[0].1 = 12;
+    assert_eq!(ObjectFile::from_bytes(&outside.to_bytes()), None);
+}
@@ -1,7 +1,8 @@
 use crate::error::VmError;
 
 /// An enum that represents opcode type for the virtual machine.
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Opcode {
     PUSH,
@@ -14,6 +15,54 @@ pub enum Opcode {
     DIV,
     MOD,
     RET,
+    DUP,
+    SWAP,
+    OVER,
+    ROT,
+    NIP,
+    TUCK,
+    PICK,
+    DEPTH,
+    BAND,
+    BOR,
+    BXOR,
+    BNOT,
+    SHL,
+    SHR,
+    USHR,
+    ROTL,
+    ROTR,
+    POPCNT,
+    JMP,
+    JZ,
+    JNZ,
+    CALL,
+    RETURN,
+    PUSH8,
+    PUSH16,
+    PUSH32,
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+    PUT,
+    LOAD8,
+    LOAD64,
+    STORE8,
+    STORE64,
+    MEMSIZE,
+    MEMGROW,
+    TRY,
+    THROW,
+    ENDTRY,
+    SPAWN,
+    YIELD,
+    RESUME,
+    FIBERSTATUS,
+    SEND,
+    RECV,
 }
 
 impl TryFrom<u8> for Opcode {
@@ -31,6 +80,54 @@ impl TryFrom<u8> for Opcode {
             7 => Ok(Self::DIV),
             8 => Ok(Self::MOD),
             9 => Ok(Self::RET),
+            10 => Ok(Self::DUP),
+            11 => Ok(Self::SWAP),
+            12 => Ok(Self::OVER),
+            13 => Ok(Self::ROT),
+            14 => Ok(Self::NIP),
+            15 => Ok(Self::TUCK),
+            16 => Ok(Self::PICK),
+            17 => Ok(Self::DEPTH),
+            18 => Ok(Self::BAND),
+            19 => Ok(Self::BOR),
+            20 => Ok(Self::BXOR),
+            21 => Ok(Self::BNOT),
+            22 => Ok(Self::SHL),
+            23 => Ok(Self::SHR),
+            24 => Ok(Self::USHR),
+            25 => Ok(Self::ROTL),
+            26 => Ok(Self::ROTR),
+            27 => Ok(Self::POPCNT),
+            28 => Ok(Self::JMP),
+            29 => Ok(Self::JZ),
+            30 => Ok(Self::JNZ),
+            31 => Ok(Self::CALL),
+            32 => Ok(Self::RETURN),
+            33 => Ok(Self::PUSH8),
+            34 => Ok(Self::PUSH16),
+            35 => Ok(Self::PUSH32),
+            36 => Ok(Self::EQ),
+            37 => Ok(Self::NE),
+            38 => Ok(Self::LT),
+            39 => Ok(Self::LE),
+            40 => Ok(Self::GT),
+            41 => Ok(Self::GE),
+            42 => Ok(Self::PUT),
+            43 => Ok(Self::LOAD8),
+            44 => Ok(Self::LOAD64),
+            45 => Ok(Self::STORE8),
+            46 => Ok(Self::STORE64),
+            47 => Ok(Self::MEMSIZE),
+            48 => Ok(Self::MEMGROW),
+            49 => Ok(Self::TRY),
+            50 => Ok(Self::THROW),
+            51 => Ok(Self::ENDTRY),
+            52 => Ok(Self::SPAWN),
+            53 => Ok(Self::YIELD),
+            54 => Ok(Self::RESUME),
+            55 => Ok(Self::FIBERSTATUS),
+            56 => Ok(Self::SEND),
+            57 => Ok(Self::RECV),
             _ => Err(VmError::InvalidOpcode),
         }
     }
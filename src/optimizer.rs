@@ -0,0 +1,362 @@
+use crate::{cfg::ControlFlowGraph, parser::Expression, value::Value};
+
+/// Optimizes expressions without changing the result of the program.
+/// Peephole rules are applied until none of them matches anymore.
+pub fn optimize(mut expressions: Vec<Expression>) -> Vec<Expression> {
+    loop {
+        let optimized = eliminate_dead_code(optimize_once(&expressions));
+
+        if optimized == expressions {
+            return optimized;
+        }
+
+        expressions = optimized;
+    }
+}
+
+/// Removes the basic blocks that can't be reached from the entries of the program.
+/// `.global` directives and data are kept wherever they are written.
+fn eliminate_dead_code(expressions: Vec<Expression>) -> Vec<Expression> {
+    let graph = ControlFlowGraph::new(&expressions);
+    let reachable = graph.reachable_blocks();
+
+    let mut expressions: Vec<Option<Expression>> = expressions.into_iter().map(Some).collect();
+
+    for (block, reachable) in graph.blocks.iter().zip(reachable) {
+        if !reachable {
+            for expression in &mut expressions[block.range.clone()] {
+                if !matches!(
+                    expression,
+                    Some(Expression::GLOBAL(_) | Expression::DATA(_))
+                ) {
+                    *expression = None;
+                }
+            }
+        }
+    }
+
+    expressions.into_iter().flatten().collect()
+}
+
+/// Applies the peephole rules to the expressions in one pass.
+fn optimize_once(expressions: &[Expression]) -> Vec<Expression> {
+    let mut optimized: Vec<Expression> = vec![];
+    let mut depth = Some(0);
+    let mut index = 0;
+    // A handler can load a register after any expression that fails, and another fiber after any switch,
+    // so registers are never dead in programs with `TRY` or `SPAWN`.
+    let shares_registers = expressions
+        .iter()
+        .any(|expression| matches!(expression, Expression::TRY(_) | Expression::SPAWN(_)));
+
+    while let Some(expression) = expressions.get(index) {
+        index += 1;
+
+        // `STORE n; LOAD n` leaves the stack as it was, so it can be dropped if `n` is never loaded again.
+        // The stack must not be empty, otherwise `STORE` would stop the program with an error.
+        if let (Expression::STORE(store_index), Some(Expression::LOAD(load_index))) =
+            (expression, expressions.get(index))
+        {
+            if store_index == load_index
+                && depth > Some(0)
+                && !shares_registers
+                && is_register_dead(*store_index, &expressions[index + 1..])
+            {
+                index += 1;
+                continue;
+            }
+        }
+
+        let (pops, pushes) = expression.stack_effect();
+        depth = match expression {
+            // The depth is unknown where the control flow joins or after a subroutine is called.
+            Expression::LABEL(_) | Expression::CALL(_) => None,
+            _ => depth
+                .and_then(|depth: usize| depth.checked_sub(pops))
+                .map(|depth| depth + pushes),
+        };
+
+        optimized.push(expression.clone());
+
+        while let Some((matched, rewritten)) = reduce_tail(&optimized) {
+            optimized.truncate(optimized.len() - matched);
+            optimized.extend(rewritten);
+        }
+    }
+
+    optimized
+}
+
+/// Returns true if the register at `index` is not loaded before it is overwritten.
+/// Registers are considered live when the control flow leaves the straight-line code.
+fn is_register_dead(index: u8, expressions: &[Expression]) -> bool {
+    for expression in expressions {
+        match expression {
+            Expression::LOAD(load_index) if *load_index == index => return false,
+            Expression::STORE(store_index) if *store_index == index => return true,
+            Expression::RET => return true,
+            Expression::LABEL(_)
+            | Expression::JMP(_)
+            | Expression::JZ(_)
+            | Expression::JNZ(_)
+            | Expression::CALL(_)
+            | Expression::RETURN
+            | Expression::TRY(_)
+            | Expression::THROW
+            | Expression::ENDTRY => return false,
+            _ => {}
+        }
+    }
+
+    true
+}
+
+/// Finds a peephole rule that matches the last expressions.
+/// It returns how many expressions are matched and what they are rewritten to.
+fn reduce_tail(expressions: &[Expression]) -> Option<(usize, Vec<Expression>)> {
+    if let [.., Expression::PUSH(_), Expression::POP] = expressions {
+        return Some((2, vec![]));
+    }
+
+    if let [.., Expression::PUSH(left), Expression::PUSH(right), binary] = expressions {
+        if let Some(value) = fold_binary(binary, *left, *right) {
+            return Some((3, vec![Expression::PUSH(value)]));
+        }
+    }
+
+    if let [.., Expression::PUSH(value), unary] = expressions {
+        if let Some(value) = fold_unary(unary, *value) {
+            return Some((2, vec![Expression::PUSH(value)]));
+        }
+    }
+
+    // Jumping to the label right after the jump does nothing.
+    if let [.., Expression::JMP(target), Expression::LABEL(label)] = expressions {
+        if target == label {
+            return Some((2, vec![Expression::LABEL(label.clone())]));
+        }
+    }
+
+    // A conditional jump on a constant either always jumps or never does.
+    if let [.., Expression::PUSH(value), Expression::JZ(label) | Expression::JNZ(label)] =
+        expressions
+    {
+        let jumps = match expressions[expressions.len() - 1] {
+            Expression::JZ(_) => *value == 0,
+            _ => *value != 0,
+        };
+
+        return match jumps {
+            true => Some((2, vec![Expression::JMP(label.clone())])),
+            false => Some((2, vec![])),
+        };
+    }
+
+    // Multiplying by a power of two is the same as shifting, even when it overflows.
+    if let [.., Expression::PUSH(value), Expression::MUL] = expressions {
+        if *value > 1 && value.count_ones() == 1 {
+            let amount = value.trailing_zeros() as Value;
+            return Some((2, vec![Expression::PUSH(amount), Expression::SHL]));
+        }
+    }
+
+    None
+}
+
+/// Computes a unary expression on a constant like the virtual machine does.
+fn fold_unary(expression: &Expression, value: Value) -> Option<Value> {
+    match expression {
+        Expression::BNOT => Some(!value),
+        Expression::POPCNT => Some(value.count_ones() as Value),
+        _ => None,
+    }
+}
+
+/// Computes a binary expression on constants like the virtual machine does.
+/// It returns `None` if the expression would stop the program with a runtime error.
+fn fold_binary(expression: &Expression, left: Value, right: Value) -> Option<Value> {
+    let shift_amount = || u32::try_from(right).ok().filter(|amount| *amount < 64);
+
+    match expression {
+        Expression::ADD => Some(left.wrapping_add(right)),
+        Expression::SUB => Some(left.wrapping_sub(right)),
+        Expression::MUL => Some(left.wrapping_mul(right)),
+        Expression::DIV if right != 0 => Some(left.wrapping_div(right)),
+        Expression::MOD if right != 0 => Some(left.wrapping_rem(right)),
+        Expression::BAND => Some(left & right),
+        Expression::BOR => Some(left | right),
+        Expression::BXOR => Some(left ^ right),
+        Expression::SHL => Some(left << shift_amount()?),
+        Expression::SHR => Some(left >> shift_amount()?),
+        Expression::USHR => Some(((left as u64) >> shift_amount()?) as Value),
+        Expression::ROTL => Some(left.rotate_left(shift_amount()?)),
+        Expression::ROTR => Some(left.rotate_right(shift_amount()?)),
+        Expression::EQ => Some((left == right) as Value),
+        Expression::NE => Some((left != right) as Value),
+        Expression::LT => Some((left < right) as Value),
+        Expression::LE => Some((left <= right) as Value),
+        Expression::GT => Some((left > right) as Value),
+        Expression::GE => Some((left >= right) as Value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn run(expressions: Vec<Expression>) -> Result<Vec<Value>, String> {
+    let bytecode = crate::compiler::compile(expressions);
+    let mut virtual_machine = crate::virtual_machine::VirtualMachine::new(bytecode);
+
+    virtual_machine
+        .run()
+        .map(|result| result.to_vec())
+        .map_err(|error| error.to_string())
+}
+
+#[test]
+fn test_optimizing() {
+    let expressions = vec![
+        Expression::PUSH(10),
+        Expression::PUSH(40),
+        Expression::ADD,
+        Expression::STORE(0),
+        Expression::LOAD(0),
+        Expression::PUSH(7),
+        Expression::POP,
+        Expression::PUSH(8),
+        Expression::MUL,
+        Expression::LOAD(1),
+        Expression::PUSH(3),
+        Expression::PUSH(0),
+        Expression::DIV,
+        Expression::RET,
+    ];
+
+    assert_eq!(
+        optimize(expressions),
+        &[
+            Expression::PUSH(400),
+            Expression::LOAD(1),
+            Expression::PUSH(3),
+            Expression::PUSH(0),
+            Expression::DIV,
+            Expression::RET,
+        ]
+    );
+
+    let expressions = vec![
+        Expression::LOAD(0),
+        Expression::PUSH(16),
+        Expression::MUL,
+        Expression::STORE(1),
+        Expression::LOAD(1),
+        Expression::LOAD(1),
+        Expression::RET,
+    ];
+
+    assert_eq!(
+        optimize(expressions),
+        &[
+            Expression::LOAD(0),
+            Expression::PUSH(4),
+            Expression::SHL,
+            Expression::STORE(1),
+            Expression::LOAD(1),
+            Expression::LOAD(1),
+            Expression::RET,
+        ]
+    );
+}
+
+#[test]
+fn test_eliminating_dead_code() {
+    let expressions = vec![
+        Expression::PUSH(0),
+        Expression::JNZ("skip".to_string()),
+        Expression::PUSH(1),
+        Expression::JMP("end".to_string()),
+        Expression::LABEL("skip".to_string()),
+        Expression::PUSH(2),
+        Expression::LABEL("end".to_string()),
+        Expression::RET,
+        Expression::PUSH(3),
+        Expression::DATA(vec![4]),
+    ];
+
+    assert_eq!(
+        optimize(expressions),
+        &[
+            Expression::PUSH(1),
+            Expression::LABEL("end".to_string()),
+            Expression::RET,
+            Expression::DATA(vec![4]),
+        ]
+    );
+}
+
+#[test]
+fn test_optimizing_handlers() {
+    // The handler loads the register that the division by zero leaves behind.
+    let expressions = vec![
+        Expression::TRY("handler".to_string()),
+        Expression::PUSH(2),
+        Expression::STORE(0),
+        Expression::LOAD(0),
+        Expression::PUSH(0),
+        Expression::DIV,
+        Expression::RET,
+        Expression::LABEL("handler".to_string()),
+        Expression::LOAD(0),
+        Expression::RET,
+    ];
+
+    assert_eq!(optimize(expressions.clone()), expressions);
+}
+
+#[test]
+fn test_optimizing_differentially() {
+    use crate::random_program::{generate, Random};
+
+    let mut random = Random::new(0x2545_F491_4F6C_DD1D);
+
+    let binaries = [
+        Expression::ADD,
+        Expression::SUB,
+        Expression::MUL,
+        Expression::DIV,
+        Expression::MOD,
+        Expression::BAND,
+        Expression::BOR,
+        Expression::BXOR,
+        Expression::SHL,
+        Expression::SHR,
+        Expression::USHR,
+        Expression::ROTL,
+        Expression::ROTR,
+        Expression::EQ,
+        Expression::LT,
+        Expression::GE,
+    ];
+
+    for _ in 0..500 {
+        // Most programs should run to the end, so underflows are rare.
+        let expressions = generate(&mut random, 24, true, |random| match random.below(8) {
+            0 | 1 => Expression::PUSH(random.below(70) as Value - 3),
+            2 => Expression::PUSH(1 << random.below(8)),
+            3 => Expression::STORE(random.below(3) as u8),
+            4 => Expression::LOAD(random.below(3) as u8),
+            5 => Expression::POP,
+            6 => match random.below(3) {
+                0 => Expression::BNOT,
+                1 => Expression::POPCNT,
+                _ => Expression::DUP,
+            },
+            _ => random.choose(&binaries).clone(),
+        });
+
+        assert_eq!(
+            run(optimize(expressions.clone())),
+            run(expressions.clone()),
+            "{expressions:?}"
+        );
+    }
+}
@@ -1,7 +1,26 @@
-use crate::{error::ParseError, lexer::Token, value::Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    iter::Peekable,
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    error::ParseError,
+    expr::{
+        self,
+        ast::{BinaryOperator, Expr, Statement, UnaryOperator},
+    },
+    lexer::{classify, tokenize_file, Token, TokenKind},
+    source::{Located, Location, Origin},
+    value::Value,
+    virtual_machine::{MEMORY_LIMIT, REGISTER_SIZE},
+};
 
 /// It represent expressions in virtual machine's assembly language.
-#[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     PUSH(Value),
     POP,
@@ -13,98 +32,838 @@ pub enum Expression {
     DIV,
     MOD,
     RET,
+    DUP,
+    SWAP,
+    OVER,
+    ROT,
+    NIP,
+    TUCK,
+    PICK(u8),
+    DEPTH,
+    BAND,
+    BOR,
+    BXOR,
+    BNOT,
+    SHL,
+    SHR,
+    USHR,
+    ROTL,
+    ROTR,
+    POPCNT,
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+    PUT(u8),
+    LOAD8,
+    LOAD64,
+    STORE8,
+    STORE64,
+    MEMSIZE,
+    MEMGROW,
+    /// Bytes of the `.data` section, which initializes the memory.
+    DATA(Vec<u8>),
+    LABEL(String),
+    /// Exports a label from an object file, so other object files can refer to it.
+    GLOBAL(String),
+    JMP(String),
+    JZ(String),
+    JNZ(String),
+    CALL(String),
+    RETURN,
+    TRY(String),
+    THROW,
+    ENDTRY,
+    SPAWN(String),
+    YIELD,
+    RESUME,
+    FIBERSTATUS,
+    SEND,
+    RECV,
 }
 
-/// Parses tokens into expressions.
-pub fn parse(tokens: Vec<Token>) -> Result<Vec<Expression>, ParseError> {
-    let mut expressions = vec![];
-
-    let mut tokens_iter = tokens.into_iter();
-
-    while let Some(token) = tokens_iter.next() {
-        match token {
-            Token::Number(number_string) => return Err(ParseError::OpcodeRequired(number_string)),
-            Token::Opcode(opcode_string) => match opcode_string {
-                "PUSH" => {
-                    let next_token = tokens_iter
-                        .next()
-                        .ok_or(ParseError::ValueRequired("PUSH"))?;
-                    let value: Value = match next_token {
-                        Token::Number(number_string) => number_string
-                            .parse()
-                            .map_err(|_| ParseError::MistakenValue(number_string))?,
-                        _ => return Err(ParseError::ValueRequired("PUSH")),
-                    };
-                    expressions.push(Expression::PUSH(value))
-                }
-                "POP" => expressions.push(Expression::POP),
-                "STORE" => {
-                    let next_token = tokens_iter
-                        .next()
-                        .ok_or(ParseError::IndexRequired("STORE"))?;
-                    let index: u8 = match next_token {
-                        Token::Number(number_string) => number_string
-                            .parse()
-                            .map_err(|_| ParseError::MistakenIndex(number_string))?,
-                        _ => return Err(ParseError::IndexRequired("STORE")),
+impl Expression {
+    /// Returns how many values the expression requires in the stack and how many it leaves in their place.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Self::PUSH(_)
+            | Self::LOAD(_)
+            | Self::DEPTH
+            | Self::MEMSIZE
+            | Self::SPAWN(_)
+            | Self::RECV => (0, 1),
+            Self::POP | Self::STORE(_) => (1, 0),
+            Self::STORE8 | Self::STORE64 | Self::SEND => (2, 0),
+            Self::RET
+            | Self::DATA(_)
+            | Self::LABEL(_)
+            | Self::GLOBAL(_)
+            | Self::JMP(_)
+            | Self::CALL(_)
+            | Self::RETURN
+            | Self::TRY(_)
+            | Self::ENDTRY => (0, 0),
+            Self::JZ(_) | Self::JNZ(_) | Self::THROW => (1, 0),
+            Self::DUP => (1, 2),
+            Self::SWAP => (2, 2),
+            Self::OVER | Self::TUCK => (2, 3),
+            Self::ROT => (3, 3),
+            Self::PICK(index) => (*index as usize + 1, *index as usize + 2),
+            Self::PUT(index) => (*index as usize + 2, *index as usize + 1),
+            Self::BNOT
+            | Self::POPCNT
+            | Self::LOAD8
+            | Self::LOAD64
+            | Self::MEMGROW
+            | Self::YIELD
+            | Self::FIBERSTATUS => (1, 1),
+            Self::RESUME => (2, 1),
+            Self::ADD
+            | Self::SUB
+            | Self::MUL
+            | Self::DIV
+            | Self::MOD
+            | Self::NIP
+            | Self::BAND
+            | Self::BOR
+            | Self::BXOR
+            | Self::SHL
+            | Self::SHR
+            | Self::USHR
+            | Self::ROTL
+            | Self::ROTR
+            | Self::EQ
+            | Self::NE
+            | Self::LT
+            | Self::LE
+            | Self::GT
+            | Self::GE => (2, 1),
+        }
+    }
+}
+
+impl Display for Expression {
+    /// Formats the expression as it is written in assembly language.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PUSH(value) => write!(f, "PUSH {value}"),
+            Self::STORE(index) => write!(f, "STORE {index}"),
+            Self::LOAD(index) => write!(f, "LOAD {index}"),
+            Self::PICK(index) => write!(f, "PICK {index}"),
+            Self::PUT(index) => write!(f, "PUT {index}"),
+            // `.byte` reads operands until the end of its line, so the directives are on separate lines.
+            Self::DATA(bytes) if bytes.is_empty() => write!(f, ".data\n.text"),
+            Self::DATA(bytes) => {
+                write!(f, ".data\n.byte")?;
+                for byte in bytes {
+                    write!(f, " {byte}")?;
+                }
+                write!(f, "\n.text")
+            }
+            Self::LABEL(label) => write!(f, "{label}:"),
+            Self::GLOBAL(label) => write!(f, ".global {label}"),
+            Self::JMP(label) => write!(f, "JMP {label}"),
+            Self::JZ(label) => write!(f, "JZ {label}"),
+            Self::JNZ(label) => write!(f, "JNZ {label}"),
+            Self::CALL(label) => write!(f, "CALL {label}"),
+            Self::TRY(label) => write!(f, "TRY {label}"),
+            Self::SPAWN(label) => write!(f, "SPAWN {label}"),
+            _ => write!(f, "{self:?}"),
+        }
+    }
+}
+
+/// Parses the label that is required after `opcode_string`.
+fn parse_label<'a>(
+    opcode_string: &str,
+    tokens_iter: &mut impl Iterator<Item = Token<'a>>,
+) -> Result<&'a str, ParseError> {
+    match tokens_iter.next().map(|token| token.value) {
+        Some(TokenKind::Opcode(label)) => Ok(label),
+        _ => Err(ParseError::LabelRequired(opcode_string.to_string())),
+    }
+}
+
+/// Parses the index that is required after `opcode_string`. It is a number or the name of a constant.
+/// Register aliases are accepted too if the index is the index of a register.
+fn parse_index<'a>(
+    opcode_string: &str,
+    tokens_iter: &mut impl Iterator<Item = Token<'a>>,
+    names: &Names,
+    register: bool,
+) -> Result<u8, ParseError> {
+    match tokens_iter.next().map(|token| token.value) {
+        Some(TokenKind::Number(number_string)) => number_string
+            .parse()
+            .map_err(|_| ParseError::MistakenIndex(number_string.to_string())),
+        Some(TokenKind::Opcode(name)) if register && names.registers.contains_key(name) => {
+            Ok(names.registers[name])
+        }
+        Some(TokenKind::Opcode(name)) => {
+            let value = names.constant(name, register)?;
+            u8::try_from(value).map_err(|_| ParseError::MistakenIndex(name.to_string()))
+        }
+        _ => Err(ParseError::IndexRequired(opcode_string.to_string())),
+    }
+}
+
+/// The constants of `.const` and the register aliases of `.reg` that are defined so far.
+#[derive(Default)]
+struct Names {
+    constants: HashMap<String, Value>,
+    registers: HashMap<String, u8>,
+}
+
+impl Names {
+    /// Returns the value of a constant, or an error that suggests a similar name.
+    /// Register aliases are suggested too if `register` is true.
+    fn constant(&self, name: &str, register: bool) -> Result<Value, ParseError> {
+        if let Some(&value) = self.constants.get(name) {
+            return Ok(value);
+        }
+
+        let registers = self.registers.keys().filter(|_| register);
+        let suggestion = suggest(name, self.constants.keys().chain(registers));
+
+        Err(ParseError::UnknownName {
+            name: name.to_string(),
+            suggestion,
+        })
+    }
+
+    /// Evaluates the expression of a constant or a register alias.
+    fn evaluate(&self, expr: &Expr, name: &str) -> Result<Value, ParseError> {
+        let invalid = || ParseError::InvalidConstant(name.to_string());
+
+        let value = match expr {
+            Expr::Number(value) => *value,
+            Expr::Variable(constant) => self.constant(constant, false)?,
+            Expr::Unary(operator, operand) => {
+                let operand = self.evaluate(operand, name)?;
+
+                match operator {
+                    UnaryOperator::Negate => operand.wrapping_neg(),
+                    UnaryOperator::Not => !operand,
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.evaluate(left, name)?;
+                let right = self.evaluate(right, name)?;
+                let shift_amount = || {
+                    u32::try_from(right)
+                        .ok()
+                        .filter(|amount| *amount < 64)
+                        .ok_or_else(invalid)
+                };
+
+                match operator {
+                    BinaryOperator::Add => left.wrapping_add(right),
+                    BinaryOperator::Sub => left.wrapping_sub(right),
+                    BinaryOperator::Mul => left.wrapping_mul(right),
+                    BinaryOperator::Div | BinaryOperator::Mod if right == 0 => {
+                        return Err(invalid())
+                    }
+                    BinaryOperator::Div => left.wrapping_div(right),
+                    BinaryOperator::Mod => left.wrapping_rem(right),
+                    BinaryOperator::And => left & right,
+                    BinaryOperator::Or => left | right,
+                    BinaryOperator::Xor => left ^ right,
+                    BinaryOperator::Shl => left << shift_amount()?,
+                    BinaryOperator::Shr => left >> shift_amount()?,
+                    BinaryOperator::Ushr => ((left as u64) >> shift_amount()?) as Value,
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// Finds the candidate that is closest to a mistaken name, if it is close enough to be a typo.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<String> {
+    let name = name.to_lowercase();
+
+    candidates
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= (name.len() / 3).max(1))
+        .min_by_key(|(distance, candidate)| (*distance, candidate.as_str()))
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// Counts the characters that must be inserted, removed or replaced to turn one text into another.
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+
+    for (index, from_char) in from.chars().enumerate() {
+        let mut current = vec![index + 1];
+
+        for (to_index, to_char) in to.iter().enumerate() {
+            let replaced = previous[to_index] + usize::from(from_char != *to_char);
+            current.push(
+                replaced
+                    .min(previous[to_index + 1] + 1)
+                    .min(current[to_index] + 1),
+            );
+        }
+
+        previous = current;
+    }
+
+    previous[to.len()]
+}
+
+/// Parses an opcode and the operand it requires into an expression.
+/// A value after `PUSH` is a number or the name of a constant.
+fn parse_opcode<'a>(
+    opcode_string: &str,
+    tokens_iter: &mut impl Iterator<Item = Token<'a>>,
+    names: &Names,
+) -> Result<Expression, ParseError> {
+    let expression = match opcode_string {
+        "PUSH" => {
+            let value = match tokens_iter.next().map(|token| token.value) {
+                Some(TokenKind::Number(number_string)) => number_string
+                    .parse()
+                    .map_err(|_| ParseError::MistakenValue(number_string.to_string()))?,
+                Some(TokenKind::Opcode(name)) => names.constant(name, false)?,
+                _ => return Err(ParseError::ValueRequired("PUSH".to_string())),
+            };
+            Expression::PUSH(value)
+        }
+        "POP" => Expression::POP,
+        "STORE" => Expression::STORE(parse_index(opcode_string, tokens_iter, names, true)?),
+        "LOAD" => Expression::LOAD(parse_index(opcode_string, tokens_iter, names, true)?),
+        "ADD" => Expression::ADD,
+        "SUB" => Expression::SUB,
+        "MUL" => Expression::MUL,
+        "DIV" => Expression::DIV,
+        "MOD" => Expression::MOD,
+        "RET" => Expression::RET,
+        "DUP" => Expression::DUP,
+        "SWAP" => Expression::SWAP,
+        "OVER" => Expression::OVER,
+        "ROT" => Expression::ROT,
+        "NIP" => Expression::NIP,
+        "TUCK" => Expression::TUCK,
+        "PICK" => Expression::PICK(parse_index(opcode_string, tokens_iter, names, false)?),
+        "DEPTH" => Expression::DEPTH,
+        "BAND" => Expression::BAND,
+        "BOR" => Expression::BOR,
+        "BXOR" => Expression::BXOR,
+        "BNOT" => Expression::BNOT,
+        "SHL" => Expression::SHL,
+        "SHR" => Expression::SHR,
+        "USHR" => Expression::USHR,
+        "ROTL" => Expression::ROTL,
+        "ROTR" => Expression::ROTR,
+        "POPCNT" => Expression::POPCNT,
+        "EQ" => Expression::EQ,
+        "NE" => Expression::NE,
+        "LT" => Expression::LT,
+        "LE" => Expression::LE,
+        "GT" => Expression::GT,
+        "GE" => Expression::GE,
+        "PUT" => Expression::PUT(parse_index(opcode_string, tokens_iter, names, false)?),
+        "LOAD8" => Expression::LOAD8,
+        "LOAD64" => Expression::LOAD64,
+        "STORE8" => Expression::STORE8,
+        "STORE64" => Expression::STORE64,
+        "MEMSIZE" => Expression::MEMSIZE,
+        "MEMGROW" => Expression::MEMGROW,
+        "JMP" | "JZ" | "JNZ" | "CALL" | "TRY" | "SPAWN" => {
+            let label = parse_label(opcode_string, tokens_iter)?.to_string();
+            match opcode_string {
+                "JMP" => Expression::JMP(label),
+                "JZ" => Expression::JZ(label),
+                "JNZ" => Expression::JNZ(label),
+                "CALL" => Expression::CALL(label),
+                "TRY" => Expression::TRY(label),
+                _ => Expression::SPAWN(label),
+            }
+        }
+        "RETURN" => Expression::RETURN,
+        "THROW" => Expression::THROW,
+        "ENDTRY" => Expression::ENDTRY,
+        "YIELD" => Expression::YIELD,
+        "RESUME" => Expression::RESUME,
+        "FIBERSTATUS" => Expression::FIBERSTATUS,
+        "SEND" => Expression::SEND,
+        "RECV" => Expression::RECV,
+        ".global" => Expression::GLOBAL(parse_label(opcode_string, tokens_iter)?.to_string()),
+        _ => return Err(ParseError::MistakenOpcode(opcode_string.to_string())),
+    };
+
+    Ok(expression)
+}
+
+fn at<T>(value: T, location: &Location) -> Located<T> {
+    Located {
+        value,
+        location: location.clone(),
+    }
+}
+
+/// How deeply macros can be used inside other macros, so a recursive macro stops with an error.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// A macro that is defined with `.macro name parameters ... .endm`.
+/// Its body is kept as words, since the file it is defined in may be gone when it is used.
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Located<String>>,
+}
+
+/// Collects the expressions of a file and of the files it includes, which are read where they are included.
+/// Macros are expanded where they are used.
+#[derive(Default)]
+struct Parser {
+    expressions: Vec<Located<Expression>>,
+    /// Whether the tokens are in a `.data` section, which lasts until `.text`.
+    in_data: bool,
+    data_size: usize,
+    defined_labels: HashSet<String>,
+    referenced_labels: Vec<Located<String>>,
+    exported_labels: Vec<Located<String>>,
+    macros: HashMap<String, Rc<Macro>>,
+    expansions: usize,
+    names: Names,
+}
+
+impl Parser {
+    fn parse_tokens(&mut self, tokens: Vec<Token>) -> Result<(), Located<ParseError>> {
+        let mut tokens_iter = tokens.into_iter().peekable();
+
+        while let Some(token) = tokens_iter.next() {
+            let location = token.location;
+
+            let expression = match token.value {
+                TokenKind::Number(number_string) | TokenKind::Text(number_string) => {
+                    return Err(at(
+                        ParseError::OpcodeRequired(number_string.to_string()),
+                        &location,
+                    ))
+                }
+                TokenKind::Label(label) if self.in_data => {
+                    // A label in the data section is a constant for the offset of the data after it.
+                    if self.names.constants.contains_key(label)
+                        || self.names.registers.contains_key(label)
+                    {
+                        return Err(at(ParseError::DuplicateName(label.to_string()), &location));
+                    }
+                    let offset = self.data_size as Value;
+                    self.names.constants.insert(label.to_string(), offset);
+                    continue;
+                }
+                TokenKind::Label(label) => {
+                    if !self.defined_labels.insert(label.to_string()) {
+                        return Err(at(ParseError::DuplicateLabel(label.to_string()), &location));
+                    }
+                    Expression::LABEL(label.to_string())
+                }
+                TokenKind::Opcode(".include") => {
+                    let Some(TokenKind::Text(path)) = tokens_iter.next().map(|token| token.value)
+                    else {
+                        return Err(at(ParseError::PathRequired, &location));
                     };
-                    expressions.push(Expression::STORE(index))
-                }
-                "LOAD" => {
-                    let next_token = tokens_iter
-                        .next()
-                        .ok_or(ParseError::IndexRequired("LOAD"))?;
-                    let index: u8 = match next_token {
-                        Token::Number(number_string) => number_string
-                            .parse()
-                            .map_err(|_| ParseError::MistakenIndex(number_string))?,
-                        _ => return Err(ParseError::IndexRequired("LOAD")),
+                    self.include(path, location)?;
+                    continue;
+                }
+                TokenKind::Opcode(".macro") => {
+                    self.define_macro(location, &mut tokens_iter)?;
+                    continue;
+                }
+                TokenKind::Opcode(name) if self.macros.contains_key(name) => {
+                    self.expand_macro(name, location, &mut tokens_iter)?;
+                    continue;
+                }
+                TokenKind::Opcode(directive @ (".const" | ".reg")) => {
+                    self.define_name(directive, location, &mut tokens_iter)?;
+                    continue;
+                }
+                TokenKind::Opcode(directive @ (".data" | ".text")) => {
+                    self.in_data = directive == ".data";
+                    continue;
+                }
+                TokenKind::Opcode(directive @ (".byte" | ".quad" | ".ascii" | ".zero")) => {
+                    if !self.in_data {
+                        let error = ParseError::DataOutsideSection(directive.to_string());
+                        return Err(at(error, &location));
+                    }
+                    let bytes = self
+                        .parse_data(directive, &location, &mut tokens_iter)
+                        .map_err(|error| at(error, &location))?;
+                    self.data_size += bytes.len();
+                    Expression::DATA(bytes)
+                }
+                TokenKind::Opcode(word) if self.in_data => {
+                    let error = ParseError::CodeInDataSection(word.to_string());
+                    return Err(at(error, &location));
+                }
+                TokenKind::Opcode(opcode_string) => {
+                    parse_opcode(opcode_string, &mut tokens_iter, &self.names)
+                        .map_err(|error| at(error, &location))?
+                }
+            };
+
+            match &expression {
+                Expression::JMP(label)
+                | Expression::JZ(label)
+                | Expression::JNZ(label)
+                | Expression::CALL(label)
+                | Expression::TRY(label)
+                | Expression::SPAWN(label) => {
+                    self.referenced_labels.push(at(label.clone(), &location))
+                }
+                Expression::GLOBAL(label) => {
+                    self.exported_labels.push(at(label.clone(), &location))
+                }
+                _ => {}
+            }
+
+            self.expressions.push(at(expression, &location));
+        }
+
+        Ok(())
+    }
+
+    /// Reads a file relative to the file that includes it and parses it in place of the `.include` at `location`.
+    fn include(&mut self, path: &str, location: Location) -> Result<(), Located<ParseError>> {
+        let directory = Path::new(&*location.file).parent().unwrap_or(Path::new(""));
+        let path = directory.join(path);
+        let file = path.display().to_string();
+
+        let canonical_path = std::fs::canonicalize(&path)
+            .map_err(|_| at(ParseError::IncludedFileNotFound(file.clone()), &location))?;
+        let including_files = location.origins().filter_map(|origin| match origin {
+            Origin::Include(location) => Some(location),
+            Origin::Macro(..) => None,
+        });
+        let is_cycle = std::iter::once(&location)
+            .chain(including_files)
+            .any(|including| {
+                std::fs::canonicalize(&*including.file)
+                    .is_ok_and(|including| including == canonical_path)
+            });
+
+        if is_cycle {
+            return Err(at(ParseError::IncludeCycle(file), &location));
+        }
+
+        let source_code = std::fs::read_to_string(&path)
+            .map_err(|_| at(ParseError::IncludedFileNotFound(file.clone()), &location))?;
+        let start = Location {
+            file: Rc::from(file),
+            line: 1,
+            origin: Some(Rc::new(Origin::Include(location))),
+        };
+
+        self.parse_tokens(tokenize_file(&source_code, start))
+    }
+
+    /// Reads `.const NAME = expression` or `.reg name = expression`, which end at the end of the line.
+    /// The expression is written like in `.expr` files and can use the constants that are already defined.
+    fn define_name<'a>(
+        &mut self,
+        directive: &str,
+        location: Location,
+        tokens_iter: &mut Peekable<impl Iterator<Item = Token<'a>>>,
+    ) -> Result<(), Located<ParseError>> {
+        let mut line = std::iter::from_fn(|| {
+            tokens_iter
+                .next_if(|token| token.location.line == location.line)
+                .map(|token| token.value)
+        });
+
+        let (Some(TokenKind::Opcode(name)), Some(TokenKind::Opcode("="))) =
+            (line.next(), line.next())
+        else {
+            return Err(at(
+                ParseError::MistakenDefinition(directive.to_string()),
+                &location,
+            ));
+        };
+
+        let text = line
+            .map(|token| token.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let statements = expr::lexer::tokenize(&text)
+            .and_then(|tokens| expr::parser::parse(&tokens))
+            .map_err(|_| at(ParseError::MistakenExpression(text.clone()), &location))?;
+
+        let [Statement::Expression(expr)] = statements.as_slice() else {
+            return Err(at(ParseError::MistakenExpression(text), &location));
+        };
+
+        let value = self
+            .names
+            .evaluate(expr, name)
+            .map_err(|error| at(error, &location))?;
+
+        if self.names.constants.contains_key(name) || self.names.registers.contains_key(name) {
+            return Err(at(ParseError::DuplicateName(name.to_string()), &location));
+        }
+
+        match directive {
+            ".const" => {
+                self.names.constants.insert(name.to_string(), value);
+            }
+            _ => {
+                let index = u8::try_from(value)
+                    .ok()
+                    .filter(|&index| (index as usize) < REGISTER_SIZE)
+                    .ok_or_else(|| at(ParseError::InvalidRegister(value), &location))?;
+                self.names.registers.insert(name.to_string(), index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the operands of a data directive, which are written on its line, into the bytes that it adds to the data.
+    /// `.byte` and `.quad` add values of 1 and 8 bytes, `.ascii` adds quoted texts and `.zero` adds a number of zeros.
+    fn parse_data<'a>(
+        &self,
+        directive: &str,
+        location: &Location,
+        tokens_iter: &mut Peekable<impl Iterator<Item = Token<'a>>>,
+    ) -> Result<Vec<u8>, ParseError> {
+        let mut operands = std::iter::from_fn(|| {
+            tokens_iter
+                .next_if(|token| token.location.line == location.line)
+                .map(|token| token.value)
+        })
+        .peekable();
+
+        if operands.peek().is_none() {
+            return Err(ParseError::ValueRequired(directive.to_string()));
+        }
+
+        let mut bytes = vec![];
+
+        for operand in operands {
+            let mistaken = || ParseError::MistakenValue(operand.to_string());
+
+            let value = match operand {
+                TokenKind::Text(text) if directive == ".ascii" => {
+                    bytes.extend_from_slice(text.as_bytes());
+                    continue;
+                }
+                _ if directive == ".ascii" => return Err(mistaken()),
+                TokenKind::Number(number_string) => {
+                    number_string.parse::<Value>().map_err(|_| mistaken())?
+                }
+                TokenKind::Opcode(name) => self.names.constant(name, false)?,
+                _ => return Err(mistaken()),
+            };
+
+            match directive {
+                ".byte" => match u8::try_from(value) {
+                    Ok(byte) => bytes.push(byte),
+                    Err(_) => bytes.push(i8::try_from(value).map_err(|_| mistaken())? as u8),
+                },
+                ".quad" => bytes.extend_from_slice(&value.to_le_bytes()),
+                _ => {
+                    let count = usize::try_from(value)
+                        .ok()
+                        .filter(|count| *count <= MEMORY_LIMIT)
+                        .ok_or_else(mistaken)?;
+                    bytes.resize(bytes.len() + count, 0);
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Reads the definition of a macro after `.macro` up to `.endm`. The parameters are written on the line of `.macro`.
+    fn define_macro<'a>(
+        &mut self,
+        location: Location,
+        tokens_iter: &mut Peekable<impl Iterator<Item = Token<'a>>>,
+    ) -> Result<(), Located<ParseError>> {
+        let Some(TokenKind::Opcode(name)) = tokens_iter.next().map(|token| token.value) else {
+            return Err(at(ParseError::MacroNameRequired, &location));
+        };
+
+        let mut parameters = vec![];
+
+        while let Some(token) = tokens_iter.next_if(|token| token.location.line == location.line) {
+            match token.value {
+                TokenKind::Opcode(parameter) => parameters.push(parameter.to_string()),
+                _ => {
+                    let error = ParseError::MistakenParameter(token.value.to_string());
+                    return Err(at(error, &token.location));
+                }
+            }
+        }
+
+        let mut body = vec![];
+
+        loop {
+            match tokens_iter.next() {
+                Some(token) if token.value == TokenKind::Opcode(".endm") => break,
+                Some(token) if token.value == TokenKind::Opcode(".macro") => {
+                    let error = ParseError::NestedMacro(name.to_string());
+                    return Err(at(error, &token.location));
+                }
+                Some(token) => body.push(at(token.value.to_string(), &token.location)),
+                None => return Err(at(ParseError::UnclosedMacro(name.to_string()), &location)),
+            }
+        }
+
+        let definition = Rc::new(Macro { parameters, body });
+
+        if self.macros.insert(name.to_string(), definition).is_some() {
+            return Err(at(ParseError::DuplicateMacro(name.to_string()), &location));
+        }
+
+        Ok(())
+    }
+
+    /// Parses the body of a macro in place of its use at `location`, which is followed by its arguments.
+    /// The parameters are replaced by the arguments, and the labels that are defined in the body are
+    /// renamed for each expansion, so they don't clash with the labels around it.
+    fn expand_macro<'a>(
+        &mut self,
+        name: &str,
+        location: Location,
+        tokens_iter: &mut impl Iterator<Item = Token<'a>>,
+    ) -> Result<(), Located<ParseError>> {
+        let definition = Rc::clone(&self.macros[name]);
+
+        let depth = location
+            .origins()
+            .filter(|origin| matches!(origin, Origin::Macro(..)))
+            .count();
+
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(at(ParseError::MacroTooDeep(name.to_string()), &location));
+        }
+
+        let mut arguments = HashMap::new();
+
+        for parameter in &definition.parameters {
+            match tokens_iter.next().map(|token| token.value) {
+                Some(TokenKind::Number(argument) | TokenKind::Opcode(argument)) => {
+                    arguments.insert(parameter.as_str(), argument.to_string());
+                }
+                _ => {
+                    let error = ParseError::ArgumentRequired {
+                        macro_name: name.to_string(),
+                        parameter: parameter.clone(),
                     };
-                    expressions.push(Expression::LOAD(index))
+                    return Err(at(error, &location));
                 }
-                "ADD" => expressions.push(Expression::ADD),
-                "SUB" => expressions.push(Expression::SUB),
-                "MUL" => expressions.push(Expression::MUL),
-                "DIV" => expressions.push(Expression::DIV),
-                "MOD" => expressions.push(Expression::MOD),
-                "RET" => expressions.push(Expression::RET),
-                _ => return Err(ParseError::MistakenOpcode(opcode_string)),
-            },
+            }
         }
+
+        self.expansions += 1;
+        let prefix = format!(".M{}.", self.expansions);
+        let local_labels: HashSet<&str> = definition
+            .body
+            .iter()
+            .filter_map(|word| match classify(&word.value) {
+                TokenKind::Label(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+
+        let origin = Rc::new(Origin::Macro(name.to_string(), location));
+        let words: Vec<Located<String>> = definition
+            .body
+            .iter()
+            .map(|word| {
+                let value = match classify(&word.value) {
+                    TokenKind::Number(text) | TokenKind::Opcode(text)
+                        if arguments.contains_key(text) =>
+                    {
+                        arguments[text].clone()
+                    }
+                    TokenKind::Opcode(label) if local_labels.contains(label) => {
+                        format!("{prefix}{label}")
+                    }
+                    TokenKind::Label(label) => format!("{prefix}{label}:"),
+                    _ => word.value.clone(),
+                };
+
+                let location = Location {
+                    origin: Some(Rc::clone(&origin)),
+                    ..word.location.clone()
+                };
+
+                Located { value, location }
+            })
+            .collect();
+
+        let tokens = words
+            .iter()
+            .map(|word| Located {
+                value: classify(&word.value),
+                location: word.location.clone(),
+            })
+            .collect();
+
+        self.parse_tokens(tokens)
+    }
+}
+
+/// Parses tokens into expressions that remember where they are written.
+/// `.include "path"` is replaced by the expressions of the file at the path, relative to the including file.
+pub fn parse_located(tokens: Vec<Token>) -> Result<Vec<Located<Expression>>, Located<ParseError>> {
+    parse_program(tokens, false)
+}
+
+/// Parses the tokens of a module that is compiled into an object file.
+/// Labels that aren't defined are left to be imported from other object files.
+pub fn parse_module(tokens: Vec<Token>) -> Result<Vec<Expression>, Located<ParseError>> {
+    let expressions = parse_program(tokens, true)?;
+    Ok(expressions
+        .into_iter()
+        .map(|expression| expression.value)
+        .collect())
+}
+
+fn parse_program(
+    tokens: Vec<Token>,
+    imports_allowed: bool,
+) -> Result<Vec<Located<Expression>>, Located<ParseError>> {
+    let mut parser = Parser::default();
+    parser.parse_tokens(tokens)?;
+
+    let mut required_labels = parser.exported_labels;
+
+    if !imports_allowed {
+        required_labels.extend(parser.referenced_labels);
+    }
+
+    if let Some(label) = required_labels
+        .into_iter()
+        .find(|label| !parser.defined_labels.contains(&label.value))
+    {
+        return Err(at(ParseError::UndefinedLabel(label.value), &label.location));
     }
 
-    Ok(expressions)
+    Ok(parser.expressions)
+}
+
+/// Parses tokens into expressions.
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Expression>, Located<ParseError>> {
+    let expressions = parse_located(tokens)?;
+    Ok(expressions
+        .into_iter()
+        .map(|expression| expression.value)
+        .collect())
 }
 
 #[test]
 fn test_parsing() {
-    let tokens = vec![
-        Token::Opcode("PUSH"),
-        Token::Number("10"),
-        Token::Opcode("PUSH"),
-        Token::Number("40"),
-        Token::Opcode("ADD"),
-        Token::Opcode("STORE"),
-        Token::Number("0"),
-        Token::Opcode("PUSH"),
-        Token::Number("6"),
-        Token::Opcode("PUSH"),
-        Token::Number("-2"),
-        Token::Opcode("SUB"),
-        Token::Opcode("STORE"),
-        Token::Number("1"),
-        Token::Opcode("PUSH"),
-        Token::Number("10"),
-        Token::Opcode("PUSH"),
-        Token::Number("20"),
-        Token::Opcode("DIV"),
-        Token::Opcode("LOAD"),
-        Token::Number("0"),
-        Token::Opcode("LOAD"),
-        Token::Number("1"),
-        Token::Opcode("MUL"),
-        Token::Opcode("RET"),
-    ];
+    use crate::lexer::tokenize;
+
+    let tokens = tokenize("PUSH 10 PUSH 40 ADD STORE 0 PUSH 6 PUSH -2 SUB STORE 1 PUSH 10 PUSH 20 DIV LOAD 0 LOAD 1 MUL RET");
 
     let expressions = parse(tokens).unwrap();
 
@@ -129,3 +888,340 @@ fn test_parsing() {
         ]
     )
 }
+
+#[test]
+fn test_parsing_stack_manipulation() {
+    use crate::lexer::tokenize;
+
+    let tokens = tokenize("DUP SWAP OVER ROT NIP TUCK PICK 2 DEPTH");
+
+    let expressions = parse(tokens).unwrap();
+
+    assert_eq!(
+        &expressions,
+        &[
+            Expression::DUP,
+            Expression::SWAP,
+            Expression::OVER,
+            Expression::ROT,
+            Expression::NIP,
+            Expression::TUCK,
+            Expression::PICK(2),
+            Expression::DEPTH,
+        ]
+    );
+
+    assert!(matches!(
+        parse(tokenize("PICK")),
+        Err(Located { value: ParseError::IndexRequired(name), .. }) if name == "PICK"
+    ));
+}
+
+#[test]
+fn test_parsing_labels() {
+    use crate::lexer::tokenize;
+
+    let tokens = tokenize("start: CALL double JNZ start RET double: DUP ADD RETURN");
+
+    let expressions = parse(tokens).unwrap();
+
+    assert_eq!(
+        &expressions,
+        &[
+            Expression::LABEL("start".to_string()),
+            Expression::CALL("double".to_string()),
+            Expression::JNZ("start".to_string()),
+            Expression::RET,
+            Expression::LABEL("double".to_string()),
+            Expression::DUP,
+            Expression::ADD,
+            Expression::RETURN,
+        ]
+    );
+
+    assert!(matches!(
+        parse(tokenize("JMP end")),
+        Err(Located { value: ParseError::UndefinedLabel(name), .. }) if name == "end"
+    ));
+    assert!(matches!(
+        parse(tokenize("end: end:")),
+        Err(Located { value: ParseError::DuplicateLabel(name), .. }) if name == "end"
+    ));
+    assert!(matches!(
+        parse(tokenize("JZ 3")),
+        Err(Located { value: ParseError::LabelRequired(name), .. }) if name == "JZ"
+    ));
+}
+
+#[test]
+fn test_parsing_includes() {
+    let directory = std::env::temp_dir().join(format!("include-test-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("lib")).unwrap();
+    std::fs::write(
+        directory.join("lib/double.code"),
+        "double:\nDUP ADD\nRETURN",
+    )
+    .unwrap();
+    std::fs::write(
+        directory.join("lib/broken.code"),
+        "PUSH 1\n.include \"../main.code\"",
+    )
+    .unwrap();
+
+    let parse_file = |name: &str, source_code: &str| {
+        let file = directory.join(name).display().to_string();
+        std::fs::write(&file, source_code).unwrap();
+        let start = Location {
+            file: Rc::from(file),
+            line: 1,
+            origin: None,
+        };
+        parse_located(tokenize_file(source_code, start))
+    };
+
+    let expressions = parse_file(
+        "main.code",
+        "PUSH 2 CALL double RET\n.include \"lib/double.code\"",
+    )
+    .unwrap();
+    assert_eq!(
+        expressions[3].value,
+        Expression::LABEL("double".to_string())
+    );
+    assert!(expressions[3].location.file.ends_with("double.code"));
+    assert_eq!(expressions[6].location.line, 3);
+    assert!(matches!(
+        expressions[6].location.origin.as_deref(),
+        Some(Origin::Include(location)) if location.line == 2
+    ));
+
+    let error = parse_file("main.code", "\n.include \"lib/broken.code\"").unwrap_err();
+    assert!(matches!(&error.value, ParseError::IncludeCycle(path) if path.ends_with("main.code")));
+    assert_eq!(error.location.line, 2);
+    assert_eq!(error.location.origins().count(), 1);
+    assert!(error.to_string().ends_with("main.code:2"));
+
+    assert!(matches!(
+        parse_file("missing.code", ".include \"lib/missing.code\"")
+            .unwrap_err()
+            .value,
+        ParseError::IncludedFileNotFound(_)
+    ));
+    assert!(matches!(
+        parse_file("missing.code", ".include lib/double.code")
+            .unwrap_err()
+            .value,
+        ParseError::PathRequired
+    ));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_parsing_macros() {
+    use crate::lexer::tokenize;
+
+    let expressions = parse(tokenize(
+        ".macro countdown n
+         PUSH n
+         loop:
+         PUSH 1 SUB DUP JNZ loop
+         .endm
+         countdown 3 countdown 5
+         loop:
+         JMP loop",
+    ))
+    .unwrap();
+
+    assert_eq!(
+        &expressions[..7],
+        &[
+            Expression::PUSH(3),
+            Expression::LABEL(".M1.loop".to_string()),
+            Expression::PUSH(1),
+            Expression::SUB,
+            Expression::DUP,
+            Expression::JNZ(".M1.loop".to_string()),
+            Expression::PUSH(5),
+        ]
+    );
+    assert_eq!(
+        &expressions[12..],
+        &[
+            Expression::LABEL("loop".to_string()),
+            Expression::JMP("loop".to_string())
+        ]
+    );
+
+    let error = parse(tokenize(".macro bad\nPUSH\n.endm\n\nbad")).unwrap_err();
+    assert!(matches!(&error.value, ParseError::ValueRequired(opcode) if opcode == "PUSH"));
+    assert!(error
+        .to_string()
+        .ends_with("at line 2\n    in macro `bad` used at line 5"));
+
+    assert!(matches!(
+        parse(tokenize(".macro forever\nforever\n.endm\nforever"))
+            .unwrap_err()
+            .value,
+        ParseError::MacroTooDeep(name) if name == "forever"
+    ));
+    assert!(matches!(
+        parse(tokenize(".macro twice a\n.endm\ntwice")).unwrap_err().value,
+        ParseError::ArgumentRequired { parameter, .. } if parameter == "a"
+    ));
+    assert!(matches!(
+        parse(tokenize(".macro open\nPUSH 1")).unwrap_err().value,
+        ParseError::UnclosedMacro(name) if name == "open"
+    ));
+    assert!(matches!(
+        parse(tokenize(".macro m\n.endm\n.macro m\n.endm"))
+            .unwrap_err()
+            .value,
+        ParseError::DuplicateMacro(name) if name == "m"
+    ));
+}
+
+#[test]
+fn test_parsing_names() {
+    use crate::lexer::tokenize;
+
+    let expressions = parse(tokenize(
+        ".const MAX_RETRIES = 3
+         .const LIMIT = (MAX_RETRIES + 1) * 10 >> 1
+         .reg counter = MAX_RETRIES - 1
+         PUSH MAX_RETRIES
+         STORE counter
+         PUSH LIMIT
+         PICK MAX_RETRIES",
+    ))
+    .unwrap();
+
+    assert_eq!(
+        &expressions,
+        &[
+            Expression::PUSH(3),
+            Expression::STORE(2),
+            Expression::PUSH(20),
+            Expression::PICK(3),
+        ]
+    );
+
+    assert!(matches!(
+        parse(tokenize(".const MAX_RETRIES = 3\nPUSH MAX_RETRY")).unwrap_err().value,
+        ParseError::UnknownName { name, suggestion: Some(suggestion) }
+            if name == "MAX_RETRY" && suggestion == "MAX_RETRIES"
+    ));
+    assert!(matches!(
+        parse(tokenize(".reg counter = 1\nLOAD countr")).unwrap_err().value,
+        ParseError::UnknownName { suggestion: Some(suggestion), .. } if suggestion == "counter"
+    ));
+    assert!(matches!(
+        parse(tokenize(".reg counter = 1\nPUSH counter"))
+            .unwrap_err()
+            .value,
+        ParseError::UnknownName {
+            suggestion: None,
+            ..
+        }
+    ));
+    assert!(matches!(
+        parse(tokenize(".const A = 1\n.reg A = 2")).unwrap_err().value,
+        ParseError::DuplicateName(name) if name == "A"
+    ));
+    assert!(matches!(
+        parse(tokenize(".const A = 1 / 0")).unwrap_err().value,
+        ParseError::InvalidConstant(name) if name == "A"
+    ));
+    assert!(matches!(
+        parse(tokenize(".const A = 1 +")).unwrap_err().value,
+        ParseError::MistakenExpression(text) if text == "1 +"
+    ));
+    for index in [255, 256] {
+        assert!(matches!(
+            parse(tokenize(&format!(".reg r = {index}"))).unwrap_err().value,
+            ParseError::InvalidRegister(value) if value == index
+        ));
+    }
+    assert!(matches!(
+        parse(tokenize(".const A 1")).unwrap_err().value,
+        ParseError::MistakenDefinition(directive) if directive == ".const"
+    ));
+}
+
+#[test]
+fn test_parsing_data() {
+    use crate::lexer::tokenize;
+
+    let source_code = "
+    .data
+    header: .byte 1 -1 255
+    .zero 2
+    message: .ascii \"hi there\"
+    counts: .quad 300 header
+    .text
+    PUSH message
+    LOAD8
+    PUSH counts
+    LOAD64
+    RET
+    ";
+
+    let expressions = parse(tokenize(source_code)).unwrap();
+
+    assert_eq!(
+        &expressions,
+        &[
+            Expression::DATA(vec![1, 255, 255]),
+            Expression::DATA(vec![0, 0]),
+            Expression::DATA(b"hi there".to_vec()),
+            Expression::DATA([300_i64.to_le_bytes(), 0_i64.to_le_bytes()].concat()),
+            Expression::PUSH(5),
+            Expression::LOAD8,
+            Expression::PUSH(13),
+            Expression::LOAD64,
+            Expression::RET,
+        ]
+    );
+    assert_eq!(expressions[0].to_string(), ".data\n.byte 1 255 255\n.text");
+    assert_eq!(Expression::DATA(vec![]).to_string(), ".data\n.text");
+
+    // The formatted data is parsed back into the same data.
+    for data in [vec![1, 255], vec![]] {
+        let source_code = format!("{}\nRET", Expression::DATA(data.clone()));
+        let expressions = parse(tokenize(&source_code)).unwrap();
+        let bytes: Vec<u8> = expressions
+            .iter()
+            .filter_map(|expression| match expression {
+                Expression::DATA(bytes) => Some(bytes.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(bytes, data);
+    }
+
+    assert!(matches!(
+        parse(tokenize(".byte 1")).unwrap_err().value,
+        ParseError::DataOutsideSection(directive) if directive == ".byte"
+    ));
+    assert!(matches!(
+        parse(tokenize(".data\nPUSH 1")).unwrap_err().value,
+        ParseError::CodeInDataSection(word) if word == "PUSH"
+    ));
+    assert!(matches!(
+        parse(tokenize(".data\n.byte 256")).unwrap_err().value,
+        ParseError::MistakenValue(value) if value == "256"
+    ));
+    assert!(matches!(
+        parse(tokenize(".data\n.ascii 1")).unwrap_err().value,
+        ParseError::MistakenValue(value) if value == "1"
+    ));
+    assert!(matches!(
+        parse(tokenize(".data\n.quad\n")).unwrap_err().value,
+        ParseError::ValueRequired(directive) if directive == ".quad"
+    ));
+    assert!(matches!(
+        parse(tokenize(".const x = 1\n.data\nx: .byte 1")).unwrap_err().value,
+        ParseError::DuplicateName(name) if name == "x"
+    ));
+}
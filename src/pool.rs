@@ -0,0 +1,258 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{error::VmError, value::Value, virtual_machine::VirtualMachine};
+
+#[cfg(test)]
+use crate::virtual_machine::compile_assembly;
+
+/// The result of a job, which is the stack that `RET` leaves or the error that stops the program.
+pub type JobResult = Result<Vec<Value>, VmError>;
+
+/// What a job finds in its inbox.
+enum Message {
+    Value(Value),
+    /// Every running job is waiting to receive, so none of them can be sent a value anymore.
+    Deadlock,
+}
+
+/// The state of the jobs, which is changed under one lock so that waiting jobs are counted exactly.
+struct State {
+    next_job: usize,
+    running: usize,
+    blocked: Vec<bool>,
+    blocked_count: usize,
+}
+
+impl State {
+    /// Wakes every blocked job with `Message::Deadlock` if none of them can be sent a value anymore.
+    /// That is when every running job is blocked and no other job can start, either because there is none left
+    /// or because every worker runs a blocked job.
+    fn detect_deadlock(&mut self, shared: &Shared) {
+        let jobs_left = self.next_job < shared.inboxes.len();
+
+        if self.blocked_count == 0
+            || self.blocked_count < self.running
+            || (jobs_left && self.running < shared.workers)
+        {
+            return;
+        }
+
+        for (job, blocked) in self.blocked.iter_mut().enumerate() {
+            if *blocked {
+                *blocked = false;
+                let _ = shared.inboxes[job].send(Message::Deadlock);
+            }
+        }
+
+        self.blocked_count = 0;
+    }
+}
+
+/// What the workers and the mailboxes of a pool share.
+struct Shared {
+    workers: usize,
+    programs: Vec<Vec<u8>>,
+    inboxes: Vec<Sender<Message>>,
+    receivers: Mutex<Vec<Option<Receiver<Message>>>>,
+    state: Mutex<State>,
+}
+
+/// The channels of a job in a pool, which `SEND` and `RECV` use.
+pub struct Mailbox {
+    job: usize,
+    inbox: Receiver<Message>,
+    shared: Arc<Shared>,
+}
+
+impl Mailbox {
+    /// Sends a value to the inbox of a job. A value sent to a finished job is dropped.
+    pub fn send(&self, job: Value, value: Value) -> Result<(), VmError> {
+        let index = usize::try_from(job)
+            .ok()
+            .filter(|&index| index < self.shared.inboxes.len())
+            .ok_or(VmError::InvalidJob(job))?;
+
+        let mut state = self.shared.state.lock().unwrap();
+        let _ = self.shared.inboxes[index].send(Message::Value(value));
+
+        // The job is woken by this value, so it is not counted as blocked from now on.
+        if state.blocked[index] {
+            state.blocked[index] = false;
+            state.blocked_count -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Receives the next value sent to this job, and waits for one if there is none.
+    pub fn receive(&self) -> Result<Value, VmError> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+
+            if let Ok(message) = self.inbox.try_recv() {
+                return Self::value(message);
+            }
+
+            state.blocked[self.job] = true;
+            state.blocked_count += 1;
+            state.detect_deadlock(&self.shared);
+        }
+
+        // Every sender is kept by the pool, so the channel isn't closed while the job runs.
+        let message = self.inbox.recv().map_err(|_| VmError::Deadlock)?;
+        Self::value(message)
+    }
+
+    fn value(message: Message) -> Result<Value, VmError> {
+        match message {
+            Message::Value(value) => Ok(value),
+            Message::Deadlock => Err(VmError::Deadlock),
+        }
+    }
+}
+
+/// Runs many programs as jobs on a fixed number of worker threads.
+/// Jobs are numbered by their order, and can send values to each other with `SEND` and `RECV`.
+pub struct VmPool {
+    workers: usize,
+}
+
+impl VmPool {
+    /// Creates a pool with `workers` threads, or one if it is zero.
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+        }
+    }
+
+    /// Runs every program and returns the results in the order of the programs.
+    pub fn run(&self, programs: Vec<Vec<u8>>) -> Vec<JobResult> {
+        let jobs = programs.len();
+        let (inboxes, receivers): (Vec<_>, Vec<_>) = (0..jobs)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel();
+                (sender, Some(receiver))
+            })
+            .unzip();
+
+        let shared = Arc::new(Shared {
+            workers: self.workers.min(jobs),
+            programs,
+            inboxes,
+            receivers: Mutex::new(receivers),
+            state: Mutex::new(State {
+                next_job: 0,
+                running: 0,
+                blocked: vec![false; jobs],
+                blocked_count: 0,
+            }),
+        });
+
+        let (result_sender, result_receiver) = mpsc::channel();
+
+        let workers: Vec<_> = (0..shared.workers)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                let result_sender = result_sender.clone();
+                thread::spawn(move || work(shared, result_sender))
+            })
+            .collect();
+
+        drop(result_sender);
+
+        let mut results: Vec<Option<JobResult>> = (0..jobs).map(|_| None).collect();
+
+        for (job, result) in result_receiver {
+            results[job] = Some(result);
+        }
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // Every job has a result, because no worker panicked.
+        results.into_iter().flatten().collect()
+    }
+}
+
+/// Runs jobs until there is none left, and sends their results with their numbers.
+fn work(shared: Arc<Shared>, results: Sender<(usize, JobResult)>) {
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap();
+
+            if state.next_job == shared.programs.len() {
+                return;
+            }
+
+            state.next_job += 1;
+            state.running += 1;
+            state.next_job - 1
+        };
+
+        let inbox = shared.receivers.lock().unwrap()[job].take().unwrap();
+        let mailbox = Mailbox {
+            job,
+            inbox,
+            shared: Arc::clone(&shared),
+        };
+
+        let mut virtual_machine = VirtualMachine::new(shared.programs[job].clone());
+        virtual_machine.set_mailbox(mailbox);
+        let result = virtual_machine.run().map(|result| result.to_vec());
+
+        {
+            let mut state = shared.state.lock().unwrap();
+            state.running -= 1;
+            state.detect_deadlock(&shared);
+        }
+
+        let _ = results.send((job, result));
+    }
+}
+
+#[test]
+fn test_pool() {
+    fn is_send<T: Send>() {}
+    is_send::<VirtualMachine>();
+
+    // Job 0 sends a number to job 1, which sends it back doubled.
+    let ping = compile_assembly("PUSH 21 PUSH 1 SEND RECV PUSH 1 ADD RET");
+    let pong = compile_assembly("RECV DUP ADD PUSH 0 SEND PUSH 7 RET");
+    let results = VmPool::new(2).run(vec![ping.clone(), pong.clone()]);
+    assert!(matches!(results.as_slice(), [Ok(ping), Ok(pong)] if ping == &[43] && pong == &[7]));
+
+    // With one worker, job 0 waits for a job that can't start, which still gets the value it was sent.
+    let results = VmPool::new(1).run(vec![ping, pong]);
+    assert!(matches!(results.as_slice(), [Err(VmError::Deadlock), Ok(pong)] if pong == &[7]));
+
+    // Both jobs wait for each other.
+    let receive = compile_assembly("RECV RET");
+    let results = VmPool::new(4).run(vec![receive.clone(), receive.clone()]);
+    assert!(matches!(
+        results.as_slice(),
+        [Err(VmError::Deadlock), Err(VmError::Deadlock)]
+    ));
+
+    // A value sent to a finished job is dropped, and errors are kept per job.
+    let results = VmPool::new(2).run(vec![
+        compile_assembly("PUSH 1 PUSH 1 SEND PUSH 1 RET"),
+        compile_assembly("PUSH 1 PUSH 0 DIV RET"),
+        compile_assembly("PUSH 1 PUSH 9 SEND RET"),
+    ]);
+    assert!(matches!(
+        results.as_slice(),
+        [Ok(result), Err(VmError::DivisionByZero), Err(VmError::InvalidJob(9))] if result == &[1]
+    ));
+
+    assert!(matches!(
+        VirtualMachine::new(receive).run(),
+        Err(VmError::NotInPool)
+    ));
+}
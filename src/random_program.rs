@@ -0,0 +1,49 @@
+use crate::parser::Expression;
+
+/// A xorshift generator, so that the differential tests are reproducible without dependencies.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Returns a number below `bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// Generates a straight-line program of fewer than `length` expressions made by `expression`, which ends with `RET`.
+/// Expressions that pop more values than the stack holds are left out, but one in 8 is kept if `underflows` is true.
+pub fn generate(
+    random: &mut Random,
+    length: u64,
+    underflows: bool,
+    mut expression: impl FnMut(&mut Random) -> Expression,
+) -> Vec<Expression> {
+    let mut expressions = vec![];
+    let mut depth = 0;
+
+    for _ in 0..random.below(length) {
+        let expression = expression(random);
+        let (pops, pushes) = expression.stack_effect();
+
+        if pops > depth && !(underflows && random.below(8) == 0) {
+            continue;
+        }
+
+        depth = depth.saturating_sub(pops) + pushes;
+        expressions.push(expression);
+    }
+
+    expressions.push(Expression::RET);
+    expressions
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::TranslationError,
+    parser::Expression,
+    register_vm::{Register, RegisterInstruction, SCRATCH_REGISTER, TEMPORARY_SIZE},
+    value::Value,
+    virtual_machine::REGISTER_SIZE,
+};
+
+/// Where a value in the stack of the stack machine is kept during translation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    /// The value is a constant that is not loaded into a register yet.
+    Constant(Value),
+    /// The value is in a register that `STORE` and `LOAD` use.
+    Variable(Register),
+    /// The value is in the temporary register of its stack slot.
+    Temporary,
+}
+
+/// Where a value is moved from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Constant(Value),
+    Register(Register),
+}
+
+/// Returns the temporary register of the stack slot at `depth`.
+fn temporary(depth: usize) -> Register {
+    (REGISTER_SIZE + depth) as Register
+}
+
+/// Translates stack machine expressions into register machine instructions.
+/// Every stack slot is given a temporary register, and constants and loaded registers are used without copying them when possible.
+pub fn translate(expressions: &[Expression]) -> Result<Vec<RegisterInstruction>, TranslationError> {
+    let mut translator = Translator {
+        stack: Some(vec![]),
+        ..Default::default()
+    };
+
+    for expression in expressions {
+        translator.translate(expression)?;
+    }
+
+    translator.finish()
+}
+
+#[derive(Default)]
+struct Translator {
+    instructions: Vec<RegisterInstruction>,
+    /// The stack slots, or `None` if the current expression can't be reached.
+    stack: Option<Vec<Slot>>,
+    /// The index of the first instruction that can't be jumped over.
+    block_start: usize,
+    labels: HashMap<String, (usize, usize)>,
+    label_depths: HashMap<String, usize>,
+    label_references: Vec<(usize, String)>,
+}
+
+impl Translator {
+    fn stack(&mut self) -> &mut Vec<Slot> {
+        self.stack.get_or_insert_with(Vec::new)
+    }
+
+    /// Returns where the value in the stack slot at `depth` is.
+    fn source(&mut self, depth: usize) -> Source {
+        match self.stack()[depth] {
+            Slot::Constant(value) => Source::Constant(value),
+            Slot::Variable(register) => Source::Register(register),
+            Slot::Temporary => Source::Register(temporary(depth)),
+        }
+    }
+
+    /// Returns a register that holds the value in the stack slot at `depth`.
+    fn operand(&mut self, depth: usize) -> Register {
+        match self.source(depth) {
+            Source::Register(register) => register,
+            Source::Constant(_) => {
+                self.materialize(depth);
+                temporary(depth)
+            }
+        }
+    }
+
+    /// Moves the value in the stack slot at `depth` into its temporary register.
+    fn materialize(&mut self, depth: usize) {
+        match self.source(depth) {
+            Source::Constant(value) => self
+                .instructions
+                .push(RegisterInstruction::LOADI(temporary(depth), value)),
+            Source::Register(register) if register != temporary(depth) => self
+                .instructions
+                .push(RegisterInstruction::MOV(temporary(depth), register)),
+            Source::Register(_) => {}
+        }
+
+        self.stack()[depth] = Slot::Temporary;
+    }
+
+    /// Moves every value in the stack into its temporary register, as it is expected where the control flow joins.
+    fn normalize(&mut self) {
+        for depth in 0..self.stack().len() {
+            self.materialize(depth);
+        }
+    }
+
+    /// Returns an error if the stack holds fewer than `count` values.
+    fn require(
+        &mut self,
+        expression: &Expression,
+        count: usize,
+    ) -> Result<usize, TranslationError> {
+        let len = self.stack().len();
+
+        len.checked_sub(count)
+            .ok_or_else(|| TranslationError::StackUnderflow(expression.clone()))
+    }
+
+    fn push(&mut self, slot: Slot) -> Result<(), TranslationError> {
+        if self.stack().len() == TEMPORARY_SIZE {
+            return Err(TranslationError::TooManyTemporaries);
+        }
+
+        self.stack().push(slot);
+        Ok(())
+    }
+
+    /// Replaces the last `count` stack slots with the slots picked by `order`, where `0` is the deepest of them.
+    fn shuffle(
+        &mut self,
+        expression: &Expression,
+        count: usize,
+        order: &[usize],
+    ) -> Result<(), TranslationError> {
+        let base = self.require(expression, count)?;
+        let sources: Vec<Source> = (base..base + count)
+            .map(|depth| self.source(depth))
+            .collect();
+
+        if base + order.len() > TEMPORARY_SIZE {
+            return Err(TranslationError::TooManyTemporaries);
+        }
+
+        self.stack().truncate(base);
+        let mut moves = vec![];
+
+        for (offset, &picked) in order.iter().enumerate() {
+            let destination = temporary(base + offset);
+
+            let slot = match sources[picked] {
+                Source::Constant(value) => Slot::Constant(value),
+                Source::Register(register) if register < REGISTER_SIZE as Register => {
+                    Slot::Variable(register)
+                }
+                Source::Register(register) => {
+                    if register != destination {
+                        moves.push((destination, sources[picked]));
+                    }
+                    Slot::Temporary
+                }
+            };
+
+            self.stack().push(slot);
+        }
+
+        self.parallel_move(moves);
+        Ok(())
+    }
+
+    /// Emits moves that all happen at once. Cycles are broken with the scratch register.
+    fn parallel_move(&mut self, mut moves: Vec<(Register, Source)>) {
+        while !moves.is_empty() {
+            let free = moves.iter().position(|(destination, _)| {
+                !moves
+                    .iter()
+                    .any(|(_, source)| *source == Source::Register(*destination))
+            });
+
+            match free {
+                Some(index) => {
+                    let (destination, source) = moves.remove(index);
+                    self.instructions.push(match source {
+                        Source::Constant(value) => RegisterInstruction::LOADI(destination, value),
+                        Source::Register(register) => {
+                            RegisterInstruction::MOV(destination, register)
+                        }
+                    });
+                }
+                None => {
+                    let saved = moves[0].0;
+                    self.instructions
+                        .push(RegisterInstruction::MOV(SCRATCH_REGISTER, saved));
+
+                    for (_, source) in &mut moves {
+                        if *source == Source::Register(saved) {
+                            *source = Source::Register(SCRATCH_REGISTER);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records the stack depth a label is jumped to with.
+    fn jump_to(&mut self, label: &str) -> Result<(), TranslationError> {
+        let depth = self.stack().len();
+
+        let expected = match self.labels.get(label) {
+            Some(&(_, expected)) => expected,
+            None => *self.label_depths.entry(label.to_string()).or_insert(depth),
+        };
+
+        if expected != depth {
+            return Err(TranslationError::StackDepthMismatch(label.to_string()));
+        }
+
+        self.label_references
+            .push((self.instructions.len(), label.to_string()));
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        expression: &Expression,
+        instruction: fn(Register, Register, Register) -> RegisterInstruction,
+    ) -> Result<(), TranslationError> {
+        let depth = self.require(expression, 2)?;
+        let right = self.operand(depth + 1);
+        let left = self.operand(depth);
+
+        self.instructions
+            .push(instruction(temporary(depth), left, right));
+        self.stack().truncate(depth);
+        self.stack().push(Slot::Temporary);
+        Ok(())
+    }
+
+    fn unary(
+        &mut self,
+        expression: &Expression,
+        instruction: fn(Register, Register) -> RegisterInstruction,
+    ) -> Result<(), TranslationError> {
+        let depth = self.require(expression, 1)?;
+        let source = self.operand(depth);
+
+        self.instructions
+            .push(instruction(temporary(depth), source));
+        self.stack()[depth] = Slot::Temporary;
+        Ok(())
+    }
+
+    fn translate(&mut self, expression: &Expression) -> Result<(), TranslationError> {
+        if let Expression::LABEL(label) = expression {
+            let depth = match self.stack.is_some() {
+                true => {
+                    self.normalize();
+                    Some(self.stack().len())
+                }
+                false => None,
+            };
+
+            let depth = match (depth, self.label_depths.get(label)) {
+                (Some(depth), Some(&expected)) if depth != expected => {
+                    return Err(TranslationError::StackDepthMismatch(label.clone()))
+                }
+                (Some(depth), _) | (None, Some(&depth)) => depth,
+                (None, None) => return Err(TranslationError::UnknownStackDepth(label.clone())),
+            };
+
+            self.labels
+                .insert(label.clone(), (self.instructions.len(), depth));
+            self.block_start = self.instructions.len();
+            self.stack = Some(vec![Slot::Temporary; depth]);
+            return Ok(());
+        }
+
+        // Expressions after an unconditional jump are skipped until the next label.
+        if self.stack.is_none() {
+            return Ok(());
+        }
+
+        match expression {
+            Expression::PUSH(value) => self.push(Slot::Constant(*value))?,
+            Expression::LOAD(index) if (*index as usize) < REGISTER_SIZE => {
+                self.push(Slot::Variable(*index as Register))?
+            }
+            Expression::STORE(index) if (*index as usize) < REGISTER_SIZE => {
+                let depth = self.require(expression, 1)?;
+                let register = *index as Register;
+
+                // Values that are loaded from the register must be kept before it is overwritten.
+                for below in 0..depth {
+                    if self.stack()[below] == Slot::Variable(register) {
+                        self.materialize(below);
+                    }
+                }
+
+                let last = self.instructions.len().checked_sub(1);
+                let retargetable = last
+                    .filter(|&last| last >= self.block_start)
+                    .filter(|&last| {
+                        self.instructions[last].destination() == Some(temporary(depth))
+                    });
+
+                match (self.source(depth), retargetable) {
+                    (Source::Register(source), Some(last)) if source == temporary(depth) => {
+                        self.instructions[last] = self.instructions[last].with_destination(register)
+                    }
+                    (Source::Register(source), _) if source == register => {}
+                    (Source::Register(source), _) => self
+                        .instructions
+                        .push(RegisterInstruction::MOV(register, source)),
+                    (Source::Constant(value), _) => self
+                        .instructions
+                        .push(RegisterInstruction::LOADI(register, value)),
+                }
+
+                self.stack().truncate(depth);
+            }
+            Expression::POP => {
+                let depth = self.require(expression, 1)?;
+                self.stack().truncate(depth);
+            }
+            Expression::ADD => self.binary(expression, RegisterInstruction::ADD)?,
+            Expression::SUB => self.binary(expression, RegisterInstruction::SUB)?,
+            Expression::MUL => self.binary(expression, RegisterInstruction::MUL)?,
+            Expression::DIV => self.binary(expression, RegisterInstruction::DIV)?,
+            Expression::MOD => self.binary(expression, RegisterInstruction::MOD)?,
+            Expression::BAND => self.binary(expression, RegisterInstruction::BAND)?,
+            Expression::BOR => self.binary(expression, RegisterInstruction::BOR)?,
+            Expression::BXOR => self.binary(expression, RegisterInstruction::BXOR)?,
+            Expression::SHL => self.binary(expression, RegisterInstruction::SHL)?,
+            Expression::SHR => self.binary(expression, RegisterInstruction::SHR)?,
+            Expression::USHR => self.binary(expression, RegisterInstruction::USHR)?,
+            Expression::ROTL => self.binary(expression, RegisterInstruction::ROTL)?,
+            Expression::ROTR => self.binary(expression, RegisterInstruction::ROTR)?,
+            Expression::BNOT => self.unary(expression, RegisterInstruction::BNOT)?,
+            Expression::POPCNT => self.unary(expression, RegisterInstruction::POPCNT)?,
+            Expression::DUP => self.shuffle(expression, 1, &[0, 0])?,
+            Expression::SWAP => self.shuffle(expression, 2, &[1, 0])?,
+            Expression::OVER => self.shuffle(expression, 2, &[0, 1, 0])?,
+            Expression::ROT => self.shuffle(expression, 3, &[1, 2, 0])?,
+            Expression::NIP => self.shuffle(expression, 2, &[1])?,
+            Expression::TUCK => self.shuffle(expression, 2, &[1, 0, 1])?,
+            Expression::PICK(index) => {
+                let count = *index as usize + 1;
+                let order: Vec<usize> = (0..count).chain([0]).collect();
+                self.shuffle(expression, count, &order)?
+            }
+            Expression::DEPTH => {
+                let depth = self.stack().len() as Value;
+                self.push(Slot::Constant(depth))?
+            }
+            Expression::RET => {
+                self.normalize();
+                let count = self.stack().len() as u16;
+                self.instructions
+                    .push(RegisterInstruction::RET(temporary(0), count));
+                self.stack = None;
+            }
+            Expression::JMP(label) => {
+                self.normalize();
+                self.jump_to(label)?;
+                self.instructions.push(RegisterInstruction::JMP(0));
+                self.stack = None;
+            }
+            Expression::JZ(label) | Expression::JNZ(label) => {
+                let depth = self.require(expression, 1)?;
+                let condition = self.operand(depth);
+                self.stack().truncate(depth);
+                self.normalize();
+                self.jump_to(label)?;
+                self.instructions.push(match expression {
+                    Expression::JZ(_) => RegisterInstruction::JZ(condition, 0),
+                    _ => RegisterInstruction::JNZ(condition, 0),
+                });
+                self.block_start = self.instructions.len();
+            }
+            _ => return Err(TranslationError::UnsupportedExpression(expression.clone())),
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the jump targets.
+    fn finish(mut self) -> Result<Vec<RegisterInstruction>, TranslationError> {
+        for (index, label) in self.label_references {
+            let &(target, _) = self
+                .labels
+                .get(&label)
+                .ok_or(TranslationError::UnknownStackDepth(label))?;
+
+            self.instructions[index] = match self.instructions[index] {
+                RegisterInstruction::JMP(_) => RegisterInstruction::JMP(target),
+                RegisterInstruction::JZ(condition, _) => RegisterInstruction::JZ(condition, target),
+                RegisterInstruction::JNZ(condition, _) => {
+                    RegisterInstruction::JNZ(condition, target)
+                }
+                instruction => instruction,
+            };
+        }
+
+        Ok(self.instructions)
+    }
+}
+
+#[cfg(test)]
+fn run_both(expressions: &[Expression]) -> (Vec<Value>, usize, Vec<Value>, usize) {
+    use crate::{register_vm::RegisterVm, virtual_machine::VirtualMachine};
+
+    let bytecode = crate::compiler::compile(expressions.to_vec());
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    let stack_count = virtual_machine.decode().unwrap().len();
+    let stack_result = virtual_machine.run().unwrap().to_vec();
+
+    let instructions = translate(expressions).unwrap();
+    let register_count = instructions.len();
+    let mut register_vm = RegisterVm::new(instructions);
+    let register_result = register_vm.run().unwrap().to_vec();
+
+    (stack_result, stack_count, register_result, register_count)
+}
+
+#[test]
+fn test_translating() {
+    let expressions = vec![
+        Expression::LOAD(0),
+        Expression::LOAD(1),
+        Expression::ADD,
+        Expression::STORE(2),
+        Expression::PUSH(7),
+        Expression::LOAD(2),
+        Expression::MUL,
+        Expression::RET,
+    ];
+
+    assert_eq!(
+        translate(&expressions).unwrap(),
+        &[
+            RegisterInstruction::ADD(2, 0, 1),
+            RegisterInstruction::LOADI(255, 7),
+            RegisterInstruction::MUL(255, 255, 2),
+            RegisterInstruction::RET(255, 1),
+        ]
+    );
+
+    let source_code = include_str!("../examples/complex.code");
+    let expressions = crate::parser::parse(crate::lexer::tokenize(source_code)).unwrap();
+    let (stack_result, stack_count, register_result, register_count) = run_both(&expressions);
+
+    assert_eq!(stack_result, register_result);
+    assert_eq!((stack_count, register_count), (10, 8));
+
+    let source_code = "
+    PUSH 10
+    STORE 0
+    PUSH 0
+    loop:
+    LOAD 0
+    ADD
+    LOAD 0
+    PUSH 1
+    SUB
+    DUP
+    STORE 0
+    JNZ loop
+    PUSH 1
+    PUSH 2
+    ROT
+    RET
+    ";
+    let expressions = crate::parser::parse(crate::lexer::tokenize(source_code)).unwrap();
+    let (stack_result, stack_count, register_result, register_count) = run_both(&expressions);
+
+    assert_eq!(stack_result, &[1, 2, 55]);
+    assert_eq!(stack_result, register_result);
+    assert!(register_count < stack_count);
+
+    assert!(matches!(
+        translate(&[Expression::ADD]),
+        Err(TranslationError::StackUnderflow(Expression::ADD))
+    ));
+}
+
+#[test]
+fn test_translating_differentially() {
+    use crate::random_program::{generate, Random};
+
+    let mut random = Random::new(0x9E37_79B9_7F4A_7C15);
+
+    let expressions = [
+        Expression::ADD,
+        Expression::SUB,
+        Expression::MUL,
+        Expression::BXOR,
+        Expression::BNOT,
+        Expression::DUP,
+        Expression::SWAP,
+        Expression::OVER,
+        Expression::ROT,
+        Expression::NIP,
+        Expression::TUCK,
+        Expression::PICK(2),
+        Expression::DEPTH,
+        Expression::POP,
+    ];
+
+    for _ in 0..500 {
+        let program = generate(&mut random, 30, false, |random| match random.below(4) {
+            0 => Expression::PUSH(random.below(100) as Value),
+            1 => Expression::LOAD(random.below(3) as u8),
+            2 => Expression::STORE(random.below(3) as u8),
+            _ => random.choose(&expressions).clone(),
+        });
+
+        let (stack_result, _, register_result, _) = run_both(&program);
+        assert_eq!(stack_result, register_result, "{program:?}");
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt::Display;
+
+use crate::{
+    error::VmError,
+    value::Value,
+    virtual_machine::{VirtualMachine, REGISTER_SIZE},
+};
+
+/// An index in the register file of a register machine.
+/// Indices below `REGISTER_SIZE` are the registers that `STORE` and `LOAD` use.
+pub type Register = u16;
+
+/// The number of registers that hold values which are in the stack of a stack machine.
+pub const TEMPORARY_SIZE: usize = 256;
+
+/// A register that is never a stack slot. It is used to swap values.
+pub const SCRATCH_REGISTER: Register = (REGISTER_SIZE + TEMPORARY_SIZE) as Register;
+
+/// It represents instructions of the register machine.
+/// Jump targets are instruction indices.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterInstruction {
+    LOADI(Register, Value),
+    MOV(Register, Register),
+    ADD(Register, Register, Register),
+    SUB(Register, Register, Register),
+    MUL(Register, Register, Register),
+    DIV(Register, Register, Register),
+    MOD(Register, Register, Register),
+    BAND(Register, Register, Register),
+    BOR(Register, Register, Register),
+    BXOR(Register, Register, Register),
+    SHL(Register, Register, Register),
+    SHR(Register, Register, Register),
+    USHR(Register, Register, Register),
+    ROTL(Register, Register, Register),
+    ROTR(Register, Register, Register),
+    BNOT(Register, Register),
+    POPCNT(Register, Register),
+    JMP(usize),
+    JZ(Register, usize),
+    JNZ(Register, usize),
+    RET(Register, u16),
+}
+
+impl RegisterInstruction {
+    /// Returns the register the instruction writes to.
+    pub fn destination(&self) -> Option<Register> {
+        match *self {
+            Self::LOADI(destination, _)
+            | Self::MOV(destination, _)
+            | Self::ADD(destination, _, _)
+            | Self::SUB(destination, _, _)
+            | Self::MUL(destination, _, _)
+            | Self::DIV(destination, _, _)
+            | Self::MOD(destination, _, _)
+            | Self::BAND(destination, _, _)
+            | Self::BOR(destination, _, _)
+            | Self::BXOR(destination, _, _)
+            | Self::SHL(destination, _, _)
+            | Self::SHR(destination, _, _)
+            | Self::USHR(destination, _, _)
+            | Self::ROTL(destination, _, _)
+            | Self::ROTR(destination, _, _)
+            | Self::BNOT(destination, _)
+            | Self::POPCNT(destination, _) => Some(destination),
+            Self::JMP(_) | Self::JZ(_, _) | Self::JNZ(_, _) | Self::RET(_, _) => None,
+        }
+    }
+
+    /// Returns the same instruction writing to another register.
+    pub fn with_destination(self, destination: Register) -> Self {
+        match self {
+            Self::LOADI(_, value) => Self::LOADI(destination, value),
+            Self::MOV(_, source) => Self::MOV(destination, source),
+            Self::ADD(_, left, right) => Self::ADD(destination, left, right),
+            Self::SUB(_, left, right) => Self::SUB(destination, left, right),
+            Self::MUL(_, left, right) => Self::MUL(destination, left, right),
+            Self::DIV(_, left, right) => Self::DIV(destination, left, right),
+            Self::MOD(_, left, right) => Self::MOD(destination, left, right),
+            Self::BAND(_, left, right) => Self::BAND(destination, left, right),
+            Self::BOR(_, left, right) => Self::BOR(destination, left, right),
+            Self::BXOR(_, left, right) => Self::BXOR(destination, left, right),
+            Self::SHL(_, left, right) => Self::SHL(destination, left, right),
+            Self::SHR(_, left, right) => Self::SHR(destination, left, right),
+            Self::USHR(_, left, right) => Self::USHR(destination, left, right),
+            Self::ROTL(_, left, right) => Self::ROTL(destination, left, right),
+            Self::ROTR(_, left, right) => Self::ROTR(destination, left, right),
+            Self::BNOT(_, source) => Self::BNOT(destination, source),
+            Self::POPCNT(_, source) => Self::POPCNT(destination, source),
+            Self::JMP(_) | Self::JZ(_, _) | Self::JNZ(_, _) | Self::RET(_, _) => self,
+        }
+    }
+}
+
+impl Display for RegisterInstruction {
+    /// Formats the instruction like `ADD r1, r2, r3`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = format!("{self:?}");
+        let name = &name[..name.find('(').unwrap_or(name.len())];
+
+        match *self {
+            Self::LOADI(destination, value) => write!(f, "{name} r{destination}, {value}"),
+            Self::MOV(destination, source)
+            | Self::BNOT(destination, source)
+            | Self::POPCNT(destination, source) => write!(f, "{name} r{destination}, r{source}"),
+            Self::JMP(target) => write!(f, "{name} {target}"),
+            Self::JZ(condition, target) | Self::JNZ(condition, target) => {
+                write!(f, "{name} r{condition}, {target}")
+            }
+            Self::RET(first, count) => write!(f, "{name} r{first}, {count}"),
+            Self::ADD(destination, left, right)
+            | Self::SUB(destination, left, right)
+            | Self::MUL(destination, left, right)
+            | Self::DIV(destination, left, right)
+            | Self::MOD(destination, left, right)
+            | Self::BAND(destination, left, right)
+            | Self::BOR(destination, left, right)
+            | Self::BXOR(destination, left, right)
+            | Self::SHL(destination, left, right)
+            | Self::SHR(destination, left, right)
+            | Self::USHR(destination, left, right)
+            | Self::ROTL(destination, left, right)
+            | Self::ROTR(destination, left, right) => {
+                write!(f, "{name} r{destination}, r{left}, r{right}")
+            }
+        }
+    }
+}
+
+/// A struct that represents a register machine instance.
+/// Its register file starts with the same registers as the stack machine, followed by temporaries.
+pub struct RegisterVm {
+    register: Vec<Value>,
+    instructions: Vec<RegisterInstruction>,
+    program_counter: usize,
+}
+
+impl RegisterVm {
+    /// Creates a new instance of register machine.
+    pub fn new(instructions: Vec<RegisterInstruction>) -> Self {
+        Self {
+            register: vec![0; SCRATCH_REGISTER as usize + 1],
+            instructions,
+            program_counter: 0,
+        }
+    }
+
+    fn invalid_register(&self, index: usize) -> VmError {
+        VmError::InvalidRegister {
+            index,
+            size: self.register.len(),
+        }
+    }
+
+    /// Returns the value of a register, or an error if the index is past the register file.
+    fn get(&self, index: Register) -> Result<Value, VmError> {
+        self.register
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| self.invalid_register(index as usize))
+    }
+
+    /// Writes a value to a register, or returns an error if the index is past the register file.
+    fn set(&mut self, index: Register, value: Value) -> Result<(), VmError> {
+        match self.register.get_mut(index as usize) {
+            Some(register) => *register = value,
+            None => return Err(self.invalid_register(index as usize)),
+        }
+
+        Ok(())
+    }
+
+    /// Computes a binary instruction like the stack machine does.
+    fn binary(
+        &mut self,
+        destination: Register,
+        left: Register,
+        right: Register,
+        operation: impl FnOnce(Value, Value) -> Result<Value, VmError>,
+    ) -> Result<(), VmError> {
+        let value = operation(self.get(left)?, self.get(right)?)?;
+        self.set(destination, value)
+    }
+
+    pub fn run(&mut self) -> Result<&[Value], VmError> {
+        while let Some(&instruction) = self.instructions.get(self.program_counter) {
+            self.program_counter += 1;
+
+            match instruction {
+                RegisterInstruction::LOADI(destination, value) => {
+                    self.set(destination, value)?;
+                }
+                RegisterInstruction::MOV(destination, source) => {
+                    self.set(destination, self.get(source)?)?;
+                }
+                RegisterInstruction::ADD(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left.wrapping_add(right))
+                    })?
+                }
+                RegisterInstruction::SUB(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left.wrapping_sub(right))
+                    })?
+                }
+                RegisterInstruction::MUL(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left.wrapping_mul(right))
+                    })?
+                }
+                RegisterInstruction::DIV(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| match right {
+                        0 => Err(VmError::DivisionByZero),
+                        _ => Ok(left.wrapping_div(right)),
+                    })?
+                }
+                RegisterInstruction::MOD(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| match right {
+                        0 => Err(VmError::DivisionByZero),
+                        _ => Ok(left.wrapping_rem(right)),
+                    })?
+                }
+                RegisterInstruction::BAND(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| Ok(left & right))?
+                }
+                RegisterInstruction::BOR(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| Ok(left | right))?
+                }
+                RegisterInstruction::BXOR(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| Ok(left ^ right))?
+                }
+                RegisterInstruction::SHL(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left << VirtualMachine::shift_amount(right)?)
+                    })?
+                }
+                RegisterInstruction::SHR(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left >> VirtualMachine::shift_amount(right)?)
+                    })?
+                }
+                RegisterInstruction::USHR(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(((left as u64) >> VirtualMachine::shift_amount(right)?) as Value)
+                    })?
+                }
+                RegisterInstruction::ROTL(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left.rotate_left(VirtualMachine::shift_amount(right)?))
+                    })?
+                }
+                RegisterInstruction::ROTR(destination, left, right) => {
+                    self.binary(destination, left, right, |left, right| {
+                        Ok(left.rotate_right(VirtualMachine::shift_amount(right)?))
+                    })?
+                }
+                RegisterInstruction::BNOT(destination, source) => {
+                    self.set(destination, !self.get(source)?)?;
+                }
+                RegisterInstruction::POPCNT(destination, source) => {
+                    self.set(destination, self.get(source)?.count_ones() as Value)?;
+                }
+                RegisterInstruction::JMP(target) => {
+                    self.program_counter = target;
+                }
+                RegisterInstruction::JZ(condition, target) => {
+                    if self.get(condition)? == 0 {
+                        self.program_counter = target;
+                    }
+                }
+                RegisterInstruction::JNZ(condition, target) => {
+                    if self.get(condition)? != 0 {
+                        self.program_counter = target;
+                    }
+                }
+                RegisterInstruction::RET(first, count) => {
+                    let first = first as usize;
+                    let end = first + count as usize;
+
+                    // The first missing register is reported, which is the first one if none of them exist.
+                    if end > self.register.len() {
+                        return Err(self.invalid_register(first.max(self.register.len())));
+                    }
+
+                    return Ok(&self.register[first..end]);
+                }
+            }
+        }
+
+        Err(VmError::RetOpcodeNotFound)
+    }
+}
+
+#[test]
+fn test_register_vm() {
+    let instructions = vec![
+        RegisterInstruction::LOADI(0, 5),
+        RegisterInstruction::LOADI(1, 0),
+        RegisterInstruction::LOADI(2, 1),
+        RegisterInstruction::ADD(1, 1, 0),
+        RegisterInstruction::SUB(0, 0, 2),
+        RegisterInstruction::JNZ(0, 3),
+        RegisterInstruction::RET(1, 1),
+    ];
+
+    assert_eq!(instructions[3].to_string(), "ADD r1, r1, r0");
+    assert_eq!(instructions[0].to_string(), "LOADI r0, 5");
+
+    let mut register_vm = RegisterVm::new(instructions);
+    assert_eq!(register_vm.run().unwrap(), &[15]);
+
+    let instructions = vec![
+        RegisterInstruction::LOADI(0, 1),
+        RegisterInstruction::DIV(0, 0, 1),
+        RegisterInstruction::RET(0, 1),
+    ];
+
+    let mut register_vm = RegisterVm::new(instructions);
+    assert!(matches!(register_vm.run(), Err(VmError::DivisionByZero)));
+
+    let size = SCRATCH_REGISTER as usize + 1;
+
+    for (instructions, index) in [
+        (
+            vec![RegisterInstruction::MOV(0, Register::MAX)],
+            Register::MAX as usize,
+        ),
+        (vec![RegisterInstruction::LOADI(size as Register, 1)], size),
+        (vec![RegisterInstruction::RET(SCRATCH_REGISTER, 2)], size),
+        (
+            vec![RegisterInstruction::RET(Register::MAX, 1)],
+            Register::MAX as usize,
+        ),
+    ] {
+        let mut register_vm = RegisterVm::new(instructions);
+        assert!(matches!(
+            register_vm.run(),
+            Err(VmError::InvalidRegister { index: i, size: s }) if i == index && s == size
+        ));
+    }
+}
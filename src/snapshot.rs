@@ -0,0 +1,86 @@
+use crate::value::Value;
+
+/// Marks the start of a snapshot of a virtual machine, like `MAGIC` marks bytecode.
+pub const SNAPSHOT_MAGIC: [u8; 4] = [0xFF, b'B', b'C', b'S'];
+
+/// The format of snapshots, which is increased whenever the state of the virtual machine changes.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+pub fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+pub fn write_bytes(bytes: &mut Vec<u8>, values: &[u8]) {
+    write_u32(bytes, values.len());
+    bytes.extend_from_slice(values);
+}
+
+pub fn write_values(bytes: &mut Vec<u8>, values: &[Value]) {
+    write_u32(bytes, values.len());
+
+    for value in values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.checked_add(length)?)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize)
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn bytes(&mut self) -> Option<&'a [u8]> {
+        let length = self.u32()?;
+        self.take(length)
+    }
+
+    pub fn values(&mut self) -> Option<Vec<Value>> {
+        (0..self.u32()?)
+            .map(|_| Some(self.u64()? as Value))
+            .collect()
+    }
+
+    /// Returns true if every byte is read.
+    pub fn is_done(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+#[test]
+fn test_snapshot_bytes() {
+    let mut bytes = vec![];
+    write_values(&mut bytes, &[-1, 7]);
+    write_bytes(&mut bytes, &[1, 2]);
+    write_u32(&mut bytes, 9);
+
+    let mut reader = Reader::new(&bytes);
+    assert_eq!(reader.values().unwrap(), vec![-1, 7]);
+    assert_eq!(reader.bytes(), Some(&[1, 2][..]));
+    assert_eq!(reader.u32(), Some(9));
+    assert!(reader.is_done());
+    assert_eq!(reader.u8(), None);
+}
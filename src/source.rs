@@ -0,0 +1,70 @@
+use std::{fmt::Display, rc::Rc};
+
+/// Where something is written. Code from an included file or a macro remembers how it got there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub origin: Option<Rc<Origin>>,
+}
+
+/// How code from another place comes to be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    /// The file of the code is included at the location.
+    Include(Location),
+    /// The code is in the body of the named macro, which is used at the location.
+    Macro(String, Location),
+}
+
+impl Origin {
+    pub fn location(&self) -> &Location {
+        match self {
+            Origin::Include(location) | Origin::Macro(_, location) => location,
+        }
+    }
+}
+
+impl Location {
+    /// Returns the `.include` directives and macro uses that lead to this location, innermost first.
+    pub fn origins(&self) -> impl Iterator<Item = &Origin> {
+        std::iter::successors(self.origin.as_deref(), |origin| {
+            origin.location().origin.as_deref()
+        })
+    }
+}
+
+impl Display for Location {
+    /// Formats the location like `lib.code:3`, or `line 3` if the source isn't from a file.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.file.is_empty() {
+            true => write!(f, "line {}", self.line),
+            false => write!(f, "{}:{}", self.file, self.line),
+        }
+    }
+}
+
+/// A value together with where it is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located<T> {
+    pub value: T,
+    pub location: Location,
+}
+
+impl<T: Display> Display for Located<T> {
+    /// Formats the value followed by its location and the origins of the location, one per line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n    at {}", self.value, self.location)?;
+
+        for origin in self.location.origins() {
+            match origin {
+                Origin::Include(location) => write!(f, "\n    included from {location}")?,
+                Origin::Macro(name, location) => {
+                    write!(f, "\n    in macro `{name}` used at {location}")?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,149 @@
-use crate::{error::VmError, opcode::Opcode, value::Value};
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-const REGISTER_SIZE: usize = u8::MAX as usize;
+use crate::{
+    bytecode::{self, LEGACY_VERSION},
+    error::VmError,
+    instruction::Instruction,
+    opcode::Opcode,
+    pool::Mailbox,
+    snapshot::{self, Reader, SNAPSHOT_MAGIC, SNAPSHOT_VERSION},
+    value::Value,
+};
+
+pub const REGISTER_SIZE: usize = u8::MAX as usize;
+
+/// The number of calls that can be nested in each fiber.
+pub const MAX_CALL_DEPTH: usize = 1 << 16;
+
+/// The size in bytes that `MEMGROW` can grow the memory to.
+pub const MEMORY_LIMIT: usize = 1 << 20;
+
+/// A handler pushed by `TRY`, with the depths that unwinding restores.
+struct Handler {
+    address: usize,
+    stack_depth: usize,
+    call_depth: usize,
+}
+
+/// The state of a fiber, which `FIBERSTATUS` pushes as a number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FiberState {
+    Suspended,
+    /// The fiber is running, or waiting for a fiber that it resumed.
+    Active,
+    Finished,
+}
+
+/// A fiber that is not running, with its own stacks and program counter.
+/// The slot of the running fiber is empty, because its state is in the virtual machine.
+struct Fiber {
+    stack: Vec<Value>,
+    call_stack: Vec<usize>,
+    handlers: Vec<Handler>,
+    program_counter: usize,
+    state: FiberState,
+    /// The fiber that resumed this one, which receives the value that it yields.
+    resumer: Option<usize>,
+}
+
+impl Fiber {
+    fn new(program_counter: usize, state: FiberState) -> Self {
+        Self {
+            stack: vec![],
+            call_stack: vec![],
+            handlers: vec![],
+            program_counter,
+            state,
+            resumer: None,
+        }
+    }
+
+    /// Writes the stacks and the program counter of a fiber, or of the virtual machine, to a snapshot.
+    fn write(
+        bytes: &mut Vec<u8>,
+        stack: &[Value],
+        call_stack: &[usize],
+        handlers: &[Handler],
+        program_counter: usize,
+    ) {
+        snapshot::write_values(bytes, stack);
+
+        snapshot::write_u32(bytes, call_stack.len());
+        for &address in call_stack {
+            snapshot::write_u32(bytes, address);
+        }
+
+        snapshot::write_u32(bytes, handlers.len());
+        for handler in handlers {
+            snapshot::write_u32(bytes, handler.address);
+            snapshot::write_u32(bytes, handler.stack_depth);
+            snapshot::write_u32(bytes, handler.call_depth);
+        }
+
+        snapshot::write_u32(bytes, program_counter);
+    }
+
+    /// Reads what `write` wrote into a suspended fiber.
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let stack = reader.values()?;
+        let call_stack = (0..reader.u32()?)
+            .map(|_| reader.u32())
+            .collect::<Option<_>>()?;
+        let handlers = (0..reader.u32()?)
+            .map(|_| {
+                Some(Handler {
+                    address: reader.u32()?,
+                    stack_depth: reader.u32()?,
+                    call_depth: reader.u32()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let program_counter = reader.u32()?;
+
+        Some(Self {
+            stack,
+            call_stack,
+            handlers,
+            ..Self::new(program_counter, FiberState::Suspended)
+        })
+    }
+}
+
+/// The fiber that the program starts in. The program finishes when it reaches `RET`.
+const MAIN_FIBER: usize = 0;
+
+/// How `run_for` stops.
+#[derive(Debug)]
+pub enum RunState<'a> {
+    /// The program reached `RET`, and this is its stack.
+    Completed(&'a [Value]),
+    /// The program ran out of steps or was interrupted, and continues with the next run.
+    Paused,
+    Error(VmError),
+}
+
+/// A handle that stops a running program from another thread at the next instruction.
+/// `run_bytecode` doesn't check it, so that it stays a baseline for `bench`.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
+
+impl Interrupt {
+    /// Stops the program at the next instruction. `run_for` pauses it, and `run` returns an error.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the program is interrupted, and clears it so that the program can continue.
+    fn take(&self) -> bool {
+        self.0.load(Ordering::Relaxed) && self.0.swap(false, Ordering::Relaxed)
+    }
+}
 
 /// A struct that represents a virtual machine instance.
 pub struct VirtualMachine {
@@ -8,6 +151,23 @@ pub struct VirtualMachine {
     register: [Value; REGISTER_SIZE],
     bytecode: Vec<u8>,
     program_counter: usize,
+    /// If true, the program counters are byte addresses of `run_bytecode` instead of instruction indices.
+    byte_addresses: bool,
+    call_stack: Vec<usize>,
+    handlers: Vec<Handler>,
+    fibers: Vec<Fiber>,
+    current_fiber: usize,
+    /// If true, a fiber that yields without being resumed switches to the next suspended fiber.
+    round_robin: bool,
+    /// The channels of the job when the program runs in a pool.
+    mailbox: Option<Mailbox>,
+    interrupt: Interrupt,
+    /// The byte-addressable memory of `LOAD8`, `STORE8` and the others, which starts with the data of the bytecode.
+    memory: Vec<u8>,
+    version: u8,
+    instructions: Option<Vec<Instruction>>,
+    #[cfg(feature = "jit")]
+    regions: Option<std::collections::HashMap<usize, crate::jit::Region>>,
 }
 
 impl VirtualMachine {
@@ -18,6 +178,19 @@ impl VirtualMachine {
             register: [0i64; REGISTER_SIZE],
             bytecode,
             program_counter: 0,
+            byte_addresses: false,
+            call_stack: vec![],
+            handlers: vec![],
+            fibers: vec![Fiber::new(0, FiberState::Active)],
+            current_fiber: MAIN_FIBER,
+            round_robin: false,
+            mailbox: None,
+            interrupt: Interrupt::default(),
+            memory: vec![],
+            version: LEGACY_VERSION,
+            instructions: None,
+            #[cfg(feature = "jit")]
+            regions: None,
         }
     }
 
@@ -25,103 +198,879 @@ impl VirtualMachine {
         let opcode = self
             .bytecode
             .get(self.program_counter)
-            .map(|byte| byte.clone().try_into());
+            .map(|&byte| byte.try_into());
 
         self.program_counter += 1;
 
         opcode
     }
 
-    pub fn get_value_from_bytecode(&mut self) -> Result<Value, VmError> {
-        let value = self
+    /// Reads a little-endian value that is `width` bytes long and sign-extends it.
+    pub fn get_value_from_bytecode(&mut self, width: usize) -> Result<Value, VmError> {
+        let bytes = self
             .bytecode
-            .get(self.program_counter..self.program_counter + 8)
-            .map(|bytes| {
-                bytes
-                    .try_into()
-                    .ok()
-                    .map(|bytes| Value::from_le_bytes(bytes))
-            })
-            .flatten();
+            .get(self.program_counter..self.program_counter + width);
+
+        self.program_counter += width;
 
-        self.program_counter += 8;
+        let bytes = bytes.ok_or(VmError::NoValueInBytecode)?;
+        let sign = match bytes.last() {
+            Some(byte) if byte & 0x80 != 0 => 0xFF,
+            _ => 0x00,
+        };
 
-        value.ok_or(VmError::NoValueInBytecode)
+        let mut value = [sign; 8];
+        value[..width].copy_from_slice(bytes);
+
+        Ok(Value::from_le_bytes(value))
     }
 
     pub fn get_index_from_bytecode(&mut self) -> Result<u8, VmError> {
-        let index = self
-            .bytecode
-            .get(self.program_counter)
-            .map(|byte| byte.clone());
+        let index = self.bytecode.get(self.program_counter).copied();
 
         self.program_counter += 1;
 
         index.ok_or(VmError::NoIndexInBytecode)
     }
 
+    pub fn get_address_from_bytecode(&mut self) -> Result<usize, VmError> {
+        let address = self
+            .bytecode
+            .get(self.program_counter..self.program_counter + 4)
+            .and_then(|bytes| bytes.try_into().ok().map(u32::from_le_bytes));
+
+        self.program_counter += 4;
+
+        address
+            .map(|address| address as usize)
+            .ok_or(VmError::NoAddressInBytecode)
+    }
+
+    /// Returns an error if the stack holds fewer than `required` values for `opcode`.
+    fn require_values_in_stack(&self, opcode: Opcode, required: usize) -> Result<(), VmError> {
+        let found = self.stack.len();
+
+        if found < required {
+            return Err(VmError::StackUnderflow {
+                opcode,
+                required,
+                found,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register that `STORE` and `LOAD` use at an index, which must be below `REGISTER_SIZE`.
+    fn register(&mut self, index: u8) -> Result<&mut Value, VmError> {
+        self.register
+            .get_mut(index as usize)
+            .ok_or(VmError::InvalidRegister {
+                index: index as usize,
+                size: REGISTER_SIZE,
+            })
+    }
+
+    /// Converts a value popped from the stack into a shift amount in `0..64`.
+    pub fn shift_amount(amount: Value) -> Result<u32, VmError> {
+        match amount {
+            0..=63 => Ok(amount as u32),
+            _ => Err(VmError::InvalidShiftAmount(amount)),
+        }
+    }
+
+    /// Returns the range of `len` bytes at `addr` in the memory, or an error if they don't fit in it.
+    fn memory_range(&self, addr: Value, len: usize) -> Result<Range<usize>, VmError> {
+        usize::try_from(addr)
+            .ok()
+            .filter(|start| *start <= self.memory.len() && len <= self.memory.len() - start)
+            .map(|start| start..start + len)
+            .ok_or(VmError::MemoryOutOfBounds { addr, len })
+    }
+
+    /// Returns the memory of the program.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Sets whether fibers that yield without being resumed are scheduled round-robin.
+    /// Otherwise such a `YIELD` is an error.
+    pub fn set_round_robin(&mut self, round_robin: bool) {
+        self.round_robin = round_robin;
+    }
+
+    /// Lets the program send and receive values as a job of a pool.
+    pub fn set_mailbox(&mut self, mailbox: Mailbox) {
+        self.mailbox = Some(mailbox);
+    }
+
+    /// Returns a handle that interrupts the program of this virtual machine.
+    pub fn interrupt(&self) -> Interrupt {
+        self.interrupt.clone()
+    }
+
+    /// Returns the index of the fiber that a value on the stack refers to.
+    fn fiber_index(&self, fiber: Value) -> Result<usize, VmError> {
+        usize::try_from(fiber)
+            .ok()
+            .filter(|&index| index < self.fibers.len())
+            .ok_or(VmError::InvalidFiber(fiber))
+    }
+
+    /// Exchanges the state of the virtual machine with the slot of a fiber.
+    fn swap_fiber(&mut self, index: usize) {
+        let fiber = &mut self.fibers[index];
+        std::mem::swap(&mut self.stack, &mut fiber.stack);
+        std::mem::swap(&mut self.call_stack, &mut fiber.call_stack);
+        std::mem::swap(&mut self.handlers, &mut fiber.handlers);
+        std::mem::swap(&mut self.program_counter, &mut fiber.program_counter);
+    }
+
+    /// Saves the running fiber, runs the fiber at `index` and pushes `value` on its stack.
+    fn switch_fiber(&mut self, index: usize, value: Value) {
+        self.swap_fiber(self.current_fiber);
+        self.swap_fiber(index);
+        self.current_fiber = index;
+        self.fibers[index].state = FiberState::Active;
+        self.stack.push(value);
+    }
+
+    /// Passes a value from the running fiber, which is already suspended or finished, to the fiber that resumed it.
+    /// In round-robin mode, a fiber that wasn't resumed passes it to the next suspended fiber, which may be itself.
+    fn yield_fiber(&mut self, value: Value) -> Result<(), VmError> {
+        let index = match self.fibers[self.current_fiber].resumer.take() {
+            Some(resumer) => resumer,
+            None if self.round_robin => {
+                let count = self.fibers.len();
+                (1..=count)
+                    .map(|offset| (self.current_fiber + offset) % count)
+                    .find(|&index| self.fibers[index].state == FiberState::Suspended)
+                    .ok_or(VmError::NoFiberToYieldTo)?
+            }
+            None => return Err(VmError::NoFiberToYieldTo),
+        };
+
+        self.switch_fiber(index, value);
+        Ok(())
+    }
+
+    /// Reads the header of the bytecode and fills the memory with its data.
+    /// It returns the index the code starts at.
+    fn load(&mut self) -> Result<usize, VmError> {
+        let (version, code_start) = bytecode::read_header(&self.bytecode)?;
+        let data = bytecode::read_data(&self.bytecode)?;
+
+        if data.len() > MEMORY_LIMIT {
+            return Err(VmError::DataTooLarge(data.len()));
+        }
+
+        self.version = version;
+        self.memory = data.to_vec();
+        Ok(code_start)
+    }
+
+    /// Pops the operands of `SUB`, `DIV` and `MOD` as `(left, right)`.
+    /// The legacy format takes the left operand from the top of the stack.
+    fn pop_arithmetic_operands(&mut self) -> Result<(Value, Value), VmError> {
+        let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+        let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+
+        if self.version == LEGACY_VERSION {
+            Ok((value_1, value_2))
+        } else {
+            Ok((value_2, value_1))
+        }
+    }
+
+    /// Pops the right operand and then the left one, and pushes 1 if `compare` holds for them or 0 otherwise.
+    fn compare(&mut self, compare: fn(&Value, &Value) -> bool) -> Result<(), VmError> {
+        let right = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+        let left = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+        self.stack.push(compare(&left, &right) as Value);
+        Ok(())
+    }
+
+    /// Reads the next opcode and its operands from the bytecode.
+    /// Jump targets are kept as bytecode addresses.
+    pub fn get_instruction_from_bytecode(&mut self) -> Option<Result<Instruction, VmError>> {
+        let opcode = match self.get_opcode_from_bytecode()? {
+            Ok(opcode) => opcode,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let instruction = match opcode {
+            Opcode::PUSH => self.get_value_from_bytecode(8).map(Instruction::PUSH),
+            Opcode::PUSH8 => self.get_value_from_bytecode(1).map(Instruction::PUSH),
+            Opcode::PUSH16 => self.get_value_from_bytecode(2).map(Instruction::PUSH),
+            Opcode::PUSH32 => self.get_value_from_bytecode(4).map(Instruction::PUSH),
+            Opcode::POP => Ok(Instruction::POP),
+            Opcode::STORE => self.get_index_from_bytecode().map(Instruction::STORE),
+            Opcode::LOAD => self.get_index_from_bytecode().map(Instruction::LOAD),
+            Opcode::ADD => Ok(Instruction::ADD),
+            Opcode::SUB => Ok(Instruction::SUB),
+            Opcode::MUL => Ok(Instruction::MUL),
+            Opcode::DIV => Ok(Instruction::DIV),
+            Opcode::MOD => Ok(Instruction::MOD),
+            Opcode::RET => Ok(Instruction::RET),
+            Opcode::DUP => Ok(Instruction::DUP),
+            Opcode::SWAP => Ok(Instruction::SWAP),
+            Opcode::OVER => Ok(Instruction::OVER),
+            Opcode::ROT => Ok(Instruction::ROT),
+            Opcode::NIP => Ok(Instruction::NIP),
+            Opcode::TUCK => Ok(Instruction::TUCK),
+            Opcode::PICK => self.get_index_from_bytecode().map(Instruction::PICK),
+            Opcode::DEPTH => Ok(Instruction::DEPTH),
+            Opcode::BAND => Ok(Instruction::BAND),
+            Opcode::BOR => Ok(Instruction::BOR),
+            Opcode::BXOR => Ok(Instruction::BXOR),
+            Opcode::BNOT => Ok(Instruction::BNOT),
+            Opcode::SHL => Ok(Instruction::SHL),
+            Opcode::SHR => Ok(Instruction::SHR),
+            Opcode::USHR => Ok(Instruction::USHR),
+            Opcode::ROTL => Ok(Instruction::ROTL),
+            Opcode::ROTR => Ok(Instruction::ROTR),
+            Opcode::POPCNT => Ok(Instruction::POPCNT),
+            Opcode::EQ => Ok(Instruction::EQ),
+            Opcode::NE => Ok(Instruction::NE),
+            Opcode::LT => Ok(Instruction::LT),
+            Opcode::LE => Ok(Instruction::LE),
+            Opcode::GT => Ok(Instruction::GT),
+            Opcode::GE => Ok(Instruction::GE),
+            Opcode::PUT => self.get_index_from_bytecode().map(Instruction::PUT),
+            Opcode::LOAD8 => Ok(Instruction::LOAD8),
+            Opcode::LOAD64 => Ok(Instruction::LOAD64),
+            Opcode::STORE8 => Ok(Instruction::STORE8),
+            Opcode::STORE64 => Ok(Instruction::STORE64),
+            Opcode::MEMSIZE => Ok(Instruction::MEMSIZE),
+            Opcode::MEMGROW => Ok(Instruction::MEMGROW),
+            Opcode::JMP => self.get_address_from_bytecode().map(Instruction::JMP),
+            Opcode::JZ => self.get_address_from_bytecode().map(Instruction::JZ),
+            Opcode::JNZ => self.get_address_from_bytecode().map(Instruction::JNZ),
+            Opcode::CALL => self.get_address_from_bytecode().map(Instruction::CALL),
+            Opcode::RETURN => Ok(Instruction::RETURN),
+            Opcode::TRY => self.get_address_from_bytecode().map(Instruction::TRY),
+            Opcode::THROW => Ok(Instruction::THROW(self.program_counter - 1)),
+            Opcode::ENDTRY => Ok(Instruction::ENDTRY),
+            Opcode::SPAWN => self.get_address_from_bytecode().map(Instruction::SPAWN),
+            Opcode::YIELD => Ok(Instruction::YIELD),
+            Opcode::RESUME => Ok(Instruction::RESUME),
+            Opcode::FIBERSTATUS => Ok(Instruction::FIBERSTATUS),
+            Opcode::SEND => Ok(Instruction::SEND),
+            Opcode::RECV => Ok(Instruction::RECV),
+        };
+
+        Some(instruction)
+    }
+
+    /// Decodes the whole bytecode into instructions whose jump targets are instruction indices.
+    /// It also verifies that the bytecode is complete and every jump targets the start of an instruction.
+    pub fn decode(&mut self) -> Result<&[Instruction], VmError> {
+        if self.instructions.is_none() {
+            self.program_counter = self.load()?;
+
+            let mut instructions = vec![];
+            let mut instruction_indices = vec![None; self.bytecode.len() + 1];
+
+            loop {
+                instruction_indices[self.program_counter] = Some(instructions.len());
+
+                match self.get_instruction_from_bytecode() {
+                    Some(instruction) => instructions.push(instruction?),
+                    None => break,
+                }
+            }
+
+            for instruction in &mut instructions {
+                if let Some(address) = instruction.target() {
+                    let index = instruction_indices
+                        .get(address)
+                        .copied()
+                        .flatten()
+                        .ok_or(VmError::InvalidAddress(address))?;
+                    *instruction = instruction.with_target(index);
+                }
+            }
+
+            self.instructions = Some(instructions);
+            self.program_counter = 0;
+            self.byte_addresses = false;
+        }
+
+        Ok(self.instructions.as_deref().unwrap_or_default())
+    }
+
+    /// Runs the program over the decoded instructions.
+    /// With the `jit` feature, straight-line regions run as machine code and the rest is interpreted.
     pub fn run(&mut self) -> Result<&[Value], VmError> {
-        while let Some(opcode) = self.get_opcode_from_bytecode() {
-            match opcode? {
-                Opcode::PUSH => {
-                    let value = self.get_value_from_bytecode()?;
-                    self.stack.push(value);
+        // The steps don't run out, so the program is only paused by an interrupt.
+        match self.run_for(usize::MAX) {
+            RunState::Completed(result) => Ok(result),
+            RunState::Paused => Err(VmError::Interrupted),
+            RunState::Error(error) => Err(error),
+        }
+    }
+
+    /// Runs at most `steps` instructions of the program, and pauses it there or when it is interrupted.
+    /// `run` and `run_for` continue a paused program.
+    pub fn run_for(&mut self, steps: usize) -> RunState<'_> {
+        if let Err(error) = self.decode() {
+            return RunState::Error(error);
+        }
+
+        #[cfg(feature = "jit")]
+        if self.regions.is_none() && self.version != LEGACY_VERSION {
+            let instructions = self.instructions.as_deref().unwrap_or_default();
+            self.regions = Some(crate::jit::compile(instructions));
+        }
+
+        let mut steps = steps;
+
+        loop {
+            if self.interrupt.take() {
+                return RunState::Paused;
+            }
+
+            #[cfg(feature = "jit")]
+            self.run_regions(&mut steps);
+
+            if steps == 0 {
+                return RunState::Paused;
+            }
+
+            let Some(&instruction) = self
+                .instructions
+                .as_ref()
+                .and_then(|instructions| instructions.get(self.program_counter))
+            else {
+                return RunState::Error(VmError::RetOpcodeNotFound);
+            };
+
+            self.program_counter += 1;
+            steps -= 1;
+
+            match self.execute(instruction) {
+                Ok(true) => return RunState::Completed(&self.stack),
+                Ok(false) => {}
+                Err(error) => {
+                    if let Err(error) = self.unwind(error) {
+                        return RunState::Error(error);
+                    }
                 }
-                Opcode::POP => {
-                    self.stack.pop().ok_or(VmError::NoValueInStack)?;
+            }
+        }
+    }
+
+    /// Runs compiled regions that start at the program counter.
+    /// A region is left to the interpreter if the stack is too shallow for it, so that the interpreter reports the error,
+    /// or if it has more instructions than the steps left.
+    #[cfg(feature = "jit")]
+    fn run_regions(&mut self, steps: &mut usize) {
+        while let Some(region) = self
+            .regions
+            .as_ref()
+            .and_then(|regions| regions.get(&self.program_counter))
+        {
+            let length = self.stack.len();
+
+            let instructions = region.end - self.program_counter;
+
+            if length < region.required || instructions > *steps {
+                return;
+            }
+
+            self.stack.reserve(region.growth);
+
+            // SAFETY: the stack holds `required` values and has room for `growth` more, which bounds
+            // every access of the region, and values are initialized before they are counted in the length.
+            unsafe {
+                let base = self.stack.as_mut_ptr();
+                let top = region.call(base.add(length), self.register.as_mut_ptr());
+                self.stack.set_len(top.offset_from(base) as usize);
+            }
+
+            self.program_counter = region.end;
+            *steps -= instructions;
+        }
+    }
+
+    /// Runs the program by decoding every opcode from the bytecode when it is reached.
+    pub fn run_bytecode(&mut self) -> Result<&[Value], VmError> {
+        self.program_counter = self.load()?;
+        self.byte_addresses = true;
+
+        while let Some(instruction) = self.get_instruction_from_bytecode() {
+            match self.execute(instruction?) {
+                Ok(true) => return Ok(&self.stack),
+                Ok(false) => {}
+                Err(error) => self.unwind(error)?,
+            }
+        }
+
+        Err(VmError::RetOpcodeNotFound)
+    }
+
+    /// Passes a thrown value or a catchable error to the innermost handler, or returns the error if there is none.
+    /// The stack and the call stack are cut back to their depths at `TRY` before the value is pushed.
+    fn unwind(&mut self, error: VmError) -> Result<(), VmError> {
+        let Some(value) = error.code() else {
+            return Err(error);
+        };
+        let Some(handler) = self.handlers.pop() else {
+            return Err(error);
+        };
+
+        self.stack.truncate(handler.stack_depth);
+        self.call_stack.truncate(handler.call_depth);
+        self.stack.push(value);
+        self.program_counter = handler.address;
+        Ok(())
+    }
+
+    /// Writes the bytecode and the state of the program to a snapshot, which `from_snapshot` continues.
+    /// It records whether the program counters are byte addresses, since `restore` only continues with instruction indices.
+    /// The round-robin mode and the mailbox of a pool are not part of it.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.push(SNAPSHOT_VERSION);
+        snapshot::write_bytes(&mut bytes, &self.bytecode);
+        bytes.push(self.byte_addresses as u8);
+        snapshot::write_values(&mut bytes, &self.register);
+
+        Fiber::write(
+            &mut bytes,
+            &self.stack,
+            &self.call_stack,
+            &self.handlers,
+            self.program_counter,
+        );
+
+        snapshot::write_u32(&mut bytes, self.current_fiber);
+        snapshot::write_u32(&mut bytes, self.fibers.len());
+
+        for fiber in &self.fibers {
+            bytes.push(fiber.state as u8);
+
+            match fiber.resumer {
+                None => bytes.push(0),
+                Some(resumer) => {
+                    bytes.push(1);
+                    snapshot::write_u32(&mut bytes, resumer);
                 }
-                Opcode::STORE => {
-                    let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let index = self.get_index_from_bytecode()?;
-                    self.register[index as usize] = value;
+            }
+
+            Fiber::write(
+                &mut bytes,
+                &fiber.stack,
+                &fiber.call_stack,
+                &fiber.handlers,
+                fiber.program_counter,
+            );
+        }
+
+        snapshot::write_u32(&mut bytes, self.memory.len());
+        bytes.extend_from_slice(&self.memory);
+
+        bytes
+    }
+
+    /// Creates a virtual machine with the bytecode of a snapshot and restores its state, so that `run` continues from there.
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self, VmError> {
+        let (bytecode, _) = Self::read_snapshot_header(snapshot)?;
+        let mut virtual_machine = Self::new(bytecode.to_vec());
+        virtual_machine.restore(snapshot)?;
+        Ok(virtual_machine)
+    }
+
+    /// Replaces the state of the program with a snapshot taken with the same bytecode, and `run` continues from there.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), VmError> {
+        self.decode()?;
+
+        let (bytecode, mut reader) = Self::read_snapshot_header(snapshot)?;
+
+        if bytecode != self.bytecode {
+            return Err(VmError::SnapshotMismatch);
+        }
+
+        match reader.u8() {
+            Some(0) => {}
+            Some(1) => return Err(VmError::ByteAddressSnapshot),
+            _ => return Err(VmError::InvalidSnapshot),
+        }
+
+        self.read_snapshot(&mut reader)
+            .ok_or(VmError::InvalidSnapshot)
+    }
+
+    /// Reads the magic number, the version and the bytecode of a snapshot, and returns the reader of the rest.
+    fn read_snapshot_header(snapshot: &[u8]) -> Result<(&[u8], Reader<'_>), VmError> {
+        let mut reader = Reader::new(snapshot);
+
+        if reader.take(SNAPSHOT_MAGIC.len()) != Some(&SNAPSHOT_MAGIC)
+            || reader.u8() != Some(SNAPSHOT_VERSION)
+        {
+            return Err(VmError::InvalidSnapshot);
+        }
+
+        let bytecode = reader.bytes().ok_or(VmError::InvalidSnapshot)?;
+        Ok((bytecode, reader))
+    }
+
+    /// Reads the state after the header of a snapshot. The state is only replaced if all of it is valid.
+    fn read_snapshot(&mut self, reader: &mut Reader) -> Option<()> {
+        let register = reader.values()?.try_into().ok()?;
+        let running = Fiber::read(reader)?;
+        let current_fiber = reader.u32()?;
+
+        let fibers = (0..reader.u32()?)
+            .map(|_| {
+                let state = match reader.u8()? {
+                    0 => FiberState::Suspended,
+                    1 => FiberState::Active,
+                    2 => FiberState::Finished,
+                    _ => return None,
+                };
+                let resumer = match reader.u8()? {
+                    0 => None,
+                    1 => Some(reader.u32()?),
+                    _ => return None,
+                };
+
+                Some(Fiber {
+                    state,
+                    resumer,
+                    ..Fiber::read(reader)?
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let length = reader.u32()?;
+        let memory = reader.take(length)?.to_vec();
+
+        // Fibers are indexed by these numbers, so they must exist.
+        let is_fiber = |index: usize| index < fibers.len();
+
+        if !is_fiber(current_fiber)
+            || !fibers
+                .iter()
+                .all(|fiber| fiber.resumer.is_none_or(is_fiber))
+            || memory.len() > MEMORY_LIMIT
+            || !reader.is_done()
+        {
+            return None;
+        }
+
+        self.register = register;
+        self.stack = running.stack;
+        self.call_stack = running.call_stack;
+        self.handlers = running.handlers;
+        self.program_counter = running.program_counter;
+        self.current_fiber = current_fiber;
+        self.fibers = fibers;
+        self.memory = memory;
+        Some(())
+    }
+
+    /// Executes an instruction whose operands are already read. The program counter must point to the next instruction.
+    /// It returns true if the program is finished.
+    fn execute(&mut self, instruction: Instruction) -> Result<bool, VmError> {
+        match instruction {
+            Instruction::PUSH(value) => {
+                self.stack.push(value);
+            }
+            Instruction::POP => {
+                self.stack.pop().ok_or(VmError::NoValueInStack)?;
+            }
+            Instruction::STORE(index) => {
+                self.register(index)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                *self.register(index)? = value;
+            }
+            Instruction::LOAD(index) => {
+                let value = *self.register(index)?;
+                self.stack.push(value);
+            }
+            Instruction::ADD => {
+                let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_3 = value_1.wrapping_add(value_2);
+                self.stack.push(value_3);
+            }
+
+            Instruction::SUB => {
+                let (left, right) = self.pop_arithmetic_operands()?;
+                self.stack.push(left.wrapping_sub(right))
+            }
+
+            Instruction::MUL => {
+                let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_3 = value_1.wrapping_mul(value_2);
+                self.stack.push(value_3);
+            }
+            Instruction::DIV => {
+                let (left, right) = self.pop_arithmetic_operands()?;
+                if right == 0 {
+                    return Err(VmError::DivisionByZero);
                 }
-                Opcode::LOAD => {
-                    let index = self.get_index_from_bytecode()?;
-                    let value = self.register[index as usize];
-                    self.stack.push(value);
+                self.stack.push(left.wrapping_div(right));
+            }
+            Instruction::MOD => {
+                let (left, right) = self.pop_arithmetic_operands()?;
+                if right == 0 {
+                    return Err(VmError::DivisionByZero);
                 }
-                Opcode::ADD => {
-                    let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_3 = value_1 + value_2;
-                    self.stack.push(value_3);
+                self.stack.push(left.wrapping_rem(right));
+            }
+            Instruction::RET => {
+                if self.current_fiber == MAIN_FIBER {
+                    return Ok(true);
                 }
 
-                Opcode::SUB => {
-                    let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_3 = value_1 - value_2;
-                    self.stack.push(value_3)
-                }
+                // Another fiber finishes by yielding the value on top of its stack.
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                self.fibers[self.current_fiber].state = FiberState::Finished;
+                self.yield_fiber(value)?;
+            }
+            Instruction::DUP => {
+                self.require_values_in_stack(Opcode::DUP, 1)?;
+                let value = self.stack[self.stack.len() - 1];
+                self.stack.push(value);
+            }
+            Instruction::SWAP => {
+                self.require_values_in_stack(Opcode::SWAP, 2)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 1, len - 2);
+            }
+            Instruction::OVER => {
+                self.require_values_in_stack(Opcode::OVER, 2)?;
+                let value = self.stack[self.stack.len() - 2];
+                self.stack.push(value);
+            }
+            Instruction::ROT => {
+                self.require_values_in_stack(Opcode::ROT, 3)?;
+                let len = self.stack.len();
+                self.stack[len - 3..].rotate_left(1);
+            }
+            Instruction::NIP => {
+                self.require_values_in_stack(Opcode::NIP, 2)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            }
+            Instruction::TUCK => {
+                self.require_values_in_stack(Opcode::TUCK, 2)?;
+                let len = self.stack.len();
+                self.stack.insert(len - 2, self.stack[len - 1]);
+            }
+            Instruction::PICK(index) => {
+                self.require_values_in_stack(Opcode::PICK, index as usize + 1)?;
+                let value = self.stack[self.stack.len() - 1 - index as usize];
+                self.stack.push(value);
+            }
+            Instruction::DEPTH => {
+                let depth = self.stack.len() as Value;
+                self.stack.push(depth);
+            }
+            Instruction::BAND => {
+                let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_3 = value_1 & value_2;
+                self.stack.push(value_3);
+            }
+            Instruction::BOR => {
+                let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_3 = value_1 | value_2;
+                self.stack.push(value_3);
+            }
+            Instruction::BXOR => {
+                let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value_3 = value_1 ^ value_2;
+                self.stack.push(value_3);
+            }
+            Instruction::BNOT => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                self.stack.push(!value);
+            }
+            Instruction::SHL => {
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let amount = Self::shift_amount(amount)?;
+                self.stack.push(value << amount);
+            }
+            Instruction::SHR => {
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let amount = Self::shift_amount(amount)?;
+                self.stack.push(value >> amount);
+            }
+            Instruction::USHR => {
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let amount = Self::shift_amount(amount)?;
+                self.stack.push(((value as u64) >> amount) as Value);
+            }
+            Instruction::ROTL => {
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let amount = Self::shift_amount(amount)?;
+                self.stack.push(value.rotate_left(amount));
+            }
+            Instruction::ROTR => {
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let amount = Self::shift_amount(amount)?;
+                self.stack.push(value.rotate_right(amount));
+            }
+            Instruction::POPCNT => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                self.stack.push(value.count_ones() as Value);
+            }
+            Instruction::EQ => self.compare(Value::eq)?,
+            Instruction::NE => self.compare(Value::ne)?,
+            Instruction::LT => self.compare(Value::lt)?,
+            Instruction::LE => self.compare(Value::le)?,
+            Instruction::GT => self.compare(Value::gt)?,
+            Instruction::GE => self.compare(Value::ge)?,
+            Instruction::PUT(index) => {
+                self.require_values_in_stack(Opcode::PUT, index as usize + 2)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let len = self.stack.len();
+                self.stack[len - 1 - index as usize] = value;
+            }
+            Instruction::LOAD8 => {
+                let addr = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let range = self.memory_range(addr, 1)?;
+                self.stack.push(self.memory[range.start] as Value);
+            }
+            Instruction::LOAD64 => {
+                let addr = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let range = self.memory_range(addr, 8)?;
+                let bytes = self.memory[range].try_into().unwrap();
+                self.stack.push(Value::from_le_bytes(bytes));
+            }
+            Instruction::STORE8 => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let addr = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let range = self.memory_range(addr, 1)?;
+                self.memory[range.start] = value as u8;
+            }
+            Instruction::STORE64 => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let addr = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let range = self.memory_range(addr, 8)?;
+                self.memory[range].copy_from_slice(&value.to_le_bytes());
+            }
+            Instruction::MEMSIZE => {
+                self.stack.push(self.memory.len() as Value);
+            }
+            Instruction::MEMGROW => {
+                // The old size is pushed, or -1 if the memory can't grow by the amount.
+                let amount = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let size = self.memory.len();
 
-                Opcode::MUL => {
-                    let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_3 = value_1 * value_2;
-                    self.stack.push(value_3);
+                match usize::try_from(amount) {
+                    Ok(amount) if amount <= MEMORY_LIMIT - size => {
+                        self.memory.resize(size + amount, 0);
+                        self.stack.push(size as Value);
+                    }
+                    _ => self.stack.push(-1),
+                }
+            }
+            Instruction::JMP(address) => {
+                self.program_counter = address;
+            }
+            Instruction::JZ(address) => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                if value == 0 {
+                    self.program_counter = address;
                 }
-                Opcode::DIV => {
-                    let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_3 = value_1 / value_2;
-                    self.stack.push(value_3);
+            }
+            Instruction::JNZ(address) => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                if value != 0 {
+                    self.program_counter = address;
                 }
-                Opcode::MOD => {
-                    let value_1 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_2 = self.stack.pop().ok_or(VmError::NoValueInStack)?;
-                    let value_3 = value_1 % value_2;
-                    self.stack.push(value_3);
+            }
+            Instruction::CALL(address) => {
+                // Fibers swap their call stacks in, so this limits every fiber.
+                if self.call_stack.len() >= MAX_CALL_DEPTH {
+                    return Err(VmError::CallStackOverflow);
                 }
-                Opcode::RET => {
-                    return Ok(&self.stack);
+
+                self.call_stack.push(self.program_counter);
+                self.program_counter = address;
+            }
+            Instruction::RETURN => {
+                let address = self.call_stack.pop().ok_or(VmError::NoAddressInCallStack)?;
+                self.program_counter = address;
+            }
+            Instruction::TRY(address) => {
+                self.handlers.push(Handler {
+                    address,
+                    stack_depth: self.stack.len(),
+                    call_depth: self.call_stack.len(),
+                });
+            }
+            Instruction::THROW(address) => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                return Err(VmError::UncaughtThrow { value, address });
+            }
+            Instruction::ENDTRY => {
+                self.handlers
+                    .pop()
+                    .ok_or(VmError::NoHandlerInHandlerStack)?;
+            }
+            Instruction::SPAWN(address) => {
+                self.fibers.push(Fiber::new(address, FiberState::Suspended));
+                self.stack.push(self.fibers.len() as Value - 1);
+            }
+            Instruction::YIELD => {
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                self.fibers[self.current_fiber].state = FiberState::Suspended;
+                self.yield_fiber(value)?;
+            }
+            Instruction::RESUME => {
+                let fiber = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let index = self.fiber_index(fiber)?;
+
+                if self.fibers[index].state != FiberState::Suspended {
+                    return Err(VmError::FiberNotSuspended(fiber));
                 }
+
+                self.fibers[index].resumer = Some(self.current_fiber);
+                self.switch_fiber(index, value);
+            }
+            Instruction::FIBERSTATUS => {
+                let fiber = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let index = self.fiber_index(fiber)?;
+                self.stack.push(self.fibers[index].state as Value);
+            }
+            Instruction::SEND => {
+                let job = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let value = self.stack.pop().ok_or(VmError::NoValueInStack)?;
+                let mailbox = self.mailbox.as_ref().ok_or(VmError::NotInPool)?;
+                mailbox.send(job, value)?;
+            }
+            Instruction::RECV => {
+                let mailbox = self.mailbox.as_ref().ok_or(VmError::NotInPool)?;
+                let value = mailbox.receive()?;
+                self.stack.push(value);
             }
         }
 
-        Err(VmError::RetOpcodeNotFound)
+        Ok(false)
     }
 }
 
+/// Compiles assembly for the tests of the virtual machine and the modules around it.
+#[cfg(test)]
+pub fn compile_assembly(source_code: &str) -> Vec<u8> {
+    let tokens = crate::lexer::tokenize(source_code);
+    crate::compiler::compile(crate::parser::parse(tokens).unwrap())
+}
+
 #[test]
 fn test_bytecode() {
     let mut bytecode: Vec<u8> = vec![];
@@ -156,3 +1105,713 @@ fn test_bytecode() {
 
     assert_eq!(result, &[360_i64])
 }
+
+#[test]
+fn test_stack_manipulation() {
+    let run = |opcodes: &[Opcode]| {
+        let mut bytecode: Vec<u8> = vec![];
+
+        for value in [1_i64, 2, 3] {
+            bytecode.push(Opcode::PUSH.into());
+            bytecode.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for &opcode in opcodes {
+            bytecode.push(opcode.into());
+        }
+
+        bytecode.push(Opcode::RET.into());
+
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    assert_eq!(run(&[Opcode::DUP]).unwrap(), &[1, 2, 3, 3]);
+    assert_eq!(run(&[Opcode::SWAP]).unwrap(), &[1, 3, 2]);
+    assert_eq!(run(&[Opcode::OVER]).unwrap(), &[1, 2, 3, 2]);
+    assert_eq!(run(&[Opcode::ROT]).unwrap(), &[2, 3, 1]);
+    assert_eq!(run(&[Opcode::NIP]).unwrap(), &[1, 3]);
+    assert_eq!(run(&[Opcode::TUCK]).unwrap(), &[1, 3, 2, 3]);
+    assert_eq!(run(&[Opcode::DEPTH]).unwrap(), &[1, 2, 3, 3]);
+
+    let mut bytecode = vec![Opcode::PUSH.into()];
+    bytecode.extend_from_slice(&7_i64.to_le_bytes());
+    bytecode.extend_from_slice(&[Opcode::PUSH.into()]);
+    bytecode.extend_from_slice(&8_i64.to_le_bytes());
+    bytecode.extend_from_slice(&[Opcode::PICK.into(), 1, Opcode::PICK.into(), 0]);
+    bytecode.push(Opcode::RET.into());
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run().unwrap(), &[7, 8, 7, 7]);
+}
+
+#[test]
+fn test_stack_underflow() {
+    let run = |bytecode: Vec<u8>| VirtualMachine::new(bytecode).run().map(|_| ()).unwrap_err();
+
+    for (opcode, required) in [
+        (Opcode::DUP, 1),
+        (Opcode::SWAP, 2),
+        (Opcode::OVER, 2),
+        (Opcode::NIP, 2),
+        (Opcode::TUCK, 2),
+    ] {
+        assert!(matches!(
+            run(vec![opcode.into(), Opcode::RET.into()]),
+            VmError::StackUnderflow { opcode: o, required: r, found: 0 } if o == opcode && r == required
+        ));
+    }
+
+    let mut bytecode = vec![Opcode::PUSH.into()];
+    bytecode.extend_from_slice(&1_i64.to_le_bytes());
+    bytecode.extend_from_slice(&[Opcode::DUP.into(), Opcode::ROT.into(), Opcode::RET.into()]);
+    assert!(matches!(
+        run(bytecode),
+        VmError::StackUnderflow {
+            opcode: Opcode::ROT,
+            required: 3,
+            found: 2
+        }
+    ));
+
+    let bytecode = vec![
+        Opcode::DEPTH.into(),
+        Opcode::PICK.into(),
+        1,
+        Opcode::RET.into(),
+    ];
+    assert!(matches!(
+        run(bytecode),
+        VmError::StackUnderflow {
+            opcode: Opcode::PICK,
+            required: 2,
+            found: 1
+        }
+    ));
+}
+
+#[test]
+fn test_bitwise() {
+    let run = |values: &[Value], opcode: Opcode| {
+        let mut bytecode: Vec<u8> = vec![];
+
+        for value in values {
+            bytecode.push(Opcode::PUSH.into());
+            bytecode.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytecode.push(opcode.into());
+        bytecode.push(Opcode::RET.into());
+
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    assert_eq!(run(&[0b1100, 0b1010], Opcode::BAND).unwrap(), &[0b1000]);
+    assert_eq!(run(&[0b1100, 0b1010], Opcode::BOR).unwrap(), &[0b1110]);
+    assert_eq!(run(&[0b1100, 0b1010], Opcode::BXOR).unwrap(), &[0b0110]);
+    assert_eq!(run(&[0], Opcode::BNOT).unwrap(), &[-1]);
+    assert_eq!(run(&[3, 4], Opcode::SHL).unwrap(), &[48]);
+    assert_eq!(run(&[-16, 2], Opcode::SHR).unwrap(), &[-4]);
+    assert_eq!(run(&[-16, 60], Opcode::USHR).unwrap(), &[15]);
+    assert_eq!(run(&[Value::MIN, 1], Opcode::ROTL).unwrap(), &[1]);
+    assert_eq!(run(&[1, 1], Opcode::ROTR).unwrap(), &[Value::MIN]);
+    assert_eq!(run(&[-1], Opcode::POPCNT).unwrap(), &[64]);
+
+    for opcode in [
+        Opcode::SHL,
+        Opcode::SHR,
+        Opcode::USHR,
+        Opcode::ROTL,
+        Opcode::ROTR,
+    ] {
+        assert!(matches!(
+            run(&[1, 64], opcode),
+            Err(VmError::InvalidShiftAmount(64))
+        ));
+        assert!(matches!(
+            run(&[1, -1], opcode),
+            Err(VmError::InvalidShiftAmount(-1))
+        ));
+    }
+}
+
+#[test]
+fn test_comparison() {
+    let run = |values: &[Value], opcodes: &[u8]| {
+        let mut bytecode: Vec<u8> = vec![];
+
+        for value in values {
+            bytecode.push(Opcode::PUSH.into());
+            bytecode.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytecode.extend_from_slice(opcodes);
+        bytecode.push(Opcode::RET.into());
+
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    for (opcode, expected) in [
+        (Opcode::EQ, [0, 1, 0]),
+        (Opcode::NE, [1, 0, 1]),
+        (Opcode::LT, [1, 0, 0]),
+        (Opcode::LE, [1, 1, 0]),
+        (Opcode::GT, [0, 0, 1]),
+        (Opcode::GE, [0, 1, 1]),
+    ] {
+        for (values, expected) in [[-1, 2], [2, 2], [3, 2]].iter().zip(expected) {
+            assert_eq!(run(values, &[opcode.into()]).unwrap(), &[expected]);
+        }
+    }
+
+    assert_eq!(
+        run(&[1, 2, 3, 4], &[Opcode::PUT.into(), 2]).unwrap(),
+        &[4, 2, 3]
+    );
+    assert!(matches!(
+        run(&[1, 2], &[Opcode::PUT.into(), 1]),
+        Err(VmError::StackUnderflow {
+            opcode: Opcode::PUT,
+            required: 3,
+            found: 2
+        })
+    ));
+}
+
+#[test]
+fn test_operand_order() {
+    let run = |header: bool, opcode: Opcode| {
+        let mut bytecode: Vec<u8> = vec![];
+
+        if header {
+            bytecode::write_header(&mut bytecode, &[]);
+        }
+
+        for value in [10_i64, 3] {
+            bytecode.push(Opcode::PUSH.into());
+            bytecode.extend_from_slice(&value.to_le_bytes());
+        }
+
+        bytecode.push(opcode.into());
+        bytecode.push(Opcode::RET.into());
+
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        virtual_machine.run().unwrap().to_vec()
+    };
+
+    assert_eq!(run(true, Opcode::SUB), &[7]);
+    assert_eq!(run(true, Opcode::DIV), &[3]);
+    assert_eq!(run(true, Opcode::MOD), &[1]);
+
+    assert_eq!(run(false, Opcode::SUB), &[-7]);
+    assert_eq!(run(false, Opcode::DIV), &[0]);
+    assert_eq!(run(false, Opcode::MOD), &[3]);
+}
+
+#[test]
+fn test_arithmetic_errors() {
+    let mut bytecode: Vec<u8> = vec![];
+    bytecode::write_header(&mut bytecode, &[]);
+
+    for value in [1_i64, 0] {
+        bytecode.push(Opcode::PUSH.into());
+        bytecode.extend_from_slice(&value.to_le_bytes());
+    }
+
+    bytecode.push(Opcode::DIV.into());
+    bytecode.push(Opcode::RET.into());
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::DivisionByZero)
+    ));
+
+    let mut bytecode: Vec<u8> = vec![0xFF, b'B', b'C', b'V', 9];
+    bytecode.push(Opcode::RET.into());
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::UnsupportedVersion(9))
+    ));
+
+    // Indices fit in a byte, but the last one is not a register.
+    for opcodes in [
+        vec![Opcode::PUSH8.into(), 1, Opcode::STORE.into(), 255],
+        vec![Opcode::LOAD.into(), 255],
+    ] {
+        let mut bytecode: Vec<u8> = vec![];
+        bytecode::write_header(&mut bytecode, &[]);
+        bytecode.extend_from_slice(&opcodes);
+        bytecode.push(Opcode::RET.into());
+
+        let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+        assert!(matches!(
+            virtual_machine.run(),
+            Err(VmError::InvalidRegister { index: 255, .. })
+        ));
+
+        let mut virtual_machine = VirtualMachine::new(bytecode);
+        assert!(matches!(
+            virtual_machine.run_bytecode(),
+            Err(VmError::InvalidRegister { index: 255, .. })
+        ));
+    }
+}
+
+#[test]
+fn test_control_flow() {
+    let source_code = "
+    PUSH 5
+    STORE 0
+    PUSH 0
+    loop:
+    LOAD 0
+    CALL double
+    ADD
+    LOAD 0
+    PUSH 1
+    SUB
+    DUP
+    STORE 0
+    JNZ loop
+    RET
+    double:
+    DUP
+    ADD
+    RETURN
+    ";
+
+    let tokens = crate::lexer::tokenize(source_code);
+    let expressions = crate::parser::parse(tokens).unwrap();
+    let bytecode = crate::compiler::compile(expressions);
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run().unwrap(), &[30]);
+
+    let mut virtual_machine = VirtualMachine::new(vec![Opcode::RETURN.into()]);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::NoAddressInCallStack)
+    ));
+
+    // Endless recursion is stopped, in the main fiber and in others.
+    let expressions = crate::forth::compile(": f recurse ; f").unwrap();
+    let mut virtual_machine = VirtualMachine::new(crate::compiler::compile(expressions));
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::CallStackOverflow)
+    ));
+
+    let bytecode = compile_assembly("SPAWN f PUSH 0 SWAP RESUME RET f: CALL f");
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::CallStackOverflow)
+    ));
+}
+
+#[test]
+fn test_compact_push() {
+    let mut bytecode: Vec<u8> = vec![];
+
+    bytecode.push(Opcode::PUSH8.into());
+    bytecode.push(0xFE);
+    bytecode.push(Opcode::PUSH16.into());
+    bytecode.extend_from_slice(&300_i16.to_le_bytes());
+    bytecode.push(Opcode::PUSH32.into());
+    bytecode.extend_from_slice(&(-70_000_i32).to_le_bytes());
+    bytecode.push(Opcode::PUSH.into());
+    bytecode.extend_from_slice(&Value::MAX.to_le_bytes());
+    bytecode.push(Opcode::RET.into());
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(
+        virtual_machine.run().unwrap(),
+        &[-2, 300, -70_000, Value::MAX]
+    );
+
+    let mut virtual_machine = VirtualMachine::new(vec![Opcode::PUSH16.into(), 1]);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::NoValueInBytecode)
+    ));
+}
+
+#[test]
+fn test_decoding() {
+    let source_code = include_str!("../examples/loop.code");
+    let tokens = crate::lexer::tokenize(source_code);
+    let expressions = crate::parser::parse(tokens).unwrap();
+    let bytecode = crate::compiler::compile(expressions);
+
+    let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+    assert_eq!(
+        virtual_machine.decode().unwrap()[..4],
+        [
+            Instruction::PUSH(1_000_000),
+            Instruction::STORE(0),
+            Instruction::PUSH(0),
+            Instruction::LOAD(0),
+        ]
+    );
+    assert_eq!(virtual_machine.decode().unwrap()[10], Instruction::JNZ(3));
+    assert_eq!(virtual_machine.run().unwrap(), &[500_000_500_000]);
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert_eq!(virtual_machine.run_bytecode().unwrap(), &[500_000_500_000]);
+
+    let mut virtual_machine =
+        VirtualMachine::new(vec![Opcode::JMP.into(), 2, 0, 0, 0, Opcode::RET.into()]);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::InvalidAddress(2))
+    ));
+}
+
+#[test]
+fn test_memory() {
+    let run = |source_code: &str| {
+        let tokens = crate::lexer::tokenize(source_code);
+        let expressions = crate::parser::parse(tokens).unwrap();
+        let mut virtual_machine = VirtualMachine::new(crate::compiler::compile(expressions));
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    let source_code = "
+    .data
+    values: .quad 5 -7
+    bytes: .byte 255 2
+    .text
+    PUSH values LOAD64
+    PUSH values PUSH 8 ADD LOAD64
+    PUSH bytes LOAD8
+    PUSH bytes PUSH 300 STORE8
+    PUSH bytes LOAD8
+    PUSH 0 PUSH -2 STORE64
+    PUSH 0 LOAD64
+    MEMSIZE
+    RET
+    ";
+    assert_eq!(run(source_code).unwrap(), &[5, -7, 255, 44, -2, 18]);
+
+    assert_eq!(
+        run("PUSH 4 MEMGROW PUSH -1 MEMGROW PUSH 1048576 MEMGROW MEMSIZE PUSH 3 LOAD8 RET")
+            .unwrap(),
+        &[0, -1, -1, 4, 0]
+    );
+    assert!(matches!(
+        run("PUSH 8 MEMGROW PUSH 1 PUSH 1 STORE64 RET"),
+        Err(VmError::MemoryOutOfBounds { addr: 1, len: 8 })
+    ));
+    assert!(matches!(
+        run("PUSH -1 LOAD8 RET"),
+        Err(VmError::MemoryOutOfBounds { addr: -1, len: 1 })
+    ));
+
+    let mut bytecode = vec![];
+    bytecode::write_header(&mut bytecode, &[9]);
+    bytecode.extend_from_slice(&[
+        Opcode::PUSH8.into(),
+        0,
+        Opcode::LOAD8.into(),
+        Opcode::RET.into(),
+    ]);
+    let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+    assert_eq!(virtual_machine.run_bytecode().unwrap(), &[9]);
+    assert_eq!(virtual_machine.memory(), &[9]);
+
+    bytecode.truncate(bytecode::HEADER_SIZE + 2);
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    assert!(matches!(
+        virtual_machine.run(),
+        Err(VmError::NoDataInBytecode)
+    ));
+}
+
+#[test]
+fn test_exceptions() {
+    let run = |source_code: &str| {
+        let mut virtual_machine = VirtualMachine::new(compile_assembly(source_code));
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    assert_eq!(
+        run(include_str!("../examples/exceptions.code")).unwrap(),
+        &[51]
+    );
+    assert_eq!(
+        run("PUSH 1 TRY h PUSH 2 PUSH 3 THROW h: RET").unwrap(),
+        &[1, 3]
+    );
+    assert_eq!(
+        run("TRY outer TRY inner PUSH 1 THROW inner: PUSH 2 ADD THROW outer: RET").unwrap(),
+        &[3]
+    );
+    assert_eq!(
+        run("PUSH 1 TRY h PUSH 2 CALL f h: RETURN f: PUSH 3 PUSH 0 MOD RETURN")
+            .unwrap_err()
+            .to_string(),
+        VmError::NoAddressInCallStack.to_string()
+    );
+    assert_eq!(run("TRY h POP RET h: RET").unwrap(), &[-1]);
+    assert_eq!(
+        run("TRY h ENDTRY PUSH 1 TRY h PUSH 2 ENDTRY RET h: RET").unwrap(),
+        &[1, 2]
+    );
+    assert!(matches!(
+        run("TRY h ENDTRY PUSH 1 PUSH 0 DIV RET h: RET"),
+        Err(VmError::DivisionByZero)
+    ));
+    assert!(matches!(
+        run("ENDTRY RET"),
+        Err(VmError::NoHandlerInHandlerStack)
+    ));
+    assert!(matches!(
+        run("TRY h PUSH 1 PUSH 64 SHL RET h: RET"),
+        Err(VmError::InvalidShiftAmount(64))
+    ));
+
+    // The address of the `THROW` is reported, after the header, the length of the data and two `PUSH8`s.
+    let bytecode = compile_assembly("PUSH 1 PUSH 5 THROW RET");
+    let address = bytecode::HEADER_SIZE + 4 + 4;
+    assert!(matches!(
+        VirtualMachine::new(bytecode.clone()).run(),
+        Err(VmError::UncaughtThrow { value: 5, address: a }) if a == address
+    ));
+    assert!(matches!(
+        VirtualMachine::new(bytecode).run_bytecode(),
+        Err(VmError::UncaughtThrow { value: 5, address: a }) if a == address
+    ));
+
+    let mut virtual_machine = VirtualMachine::new(compile_assembly("TRY h CALL f h: RET f: THROW"));
+    assert_eq!(virtual_machine.run_bytecode().unwrap(), &[-1]);
+}
+
+#[test]
+fn test_fibers() {
+    let run = |source_code: &str, round_robin: bool| {
+        let mut virtual_machine = VirtualMachine::new(compile_assembly(source_code));
+        virtual_machine.set_round_robin(round_robin);
+        virtual_machine.run().map(|result| result.to_vec())
+    };
+
+    // The consumer resumes a generator until it finishes with 0, and then sums what it produced.
+    let generator = "
+    SPAWN numbers
+    STORE 0
+    PUSH 0
+    next:
+    PUSH 0
+    LOAD 0
+    RESUME
+    DUP
+    JZ done
+    ADD
+    JMP next
+    done:
+    POP
+    LOAD 0
+    FIBERSTATUS
+    RET
+    numbers:
+    PUSH 1 YIELD POP
+    PUSH 2 YIELD POP
+    PUSH 3 YIELD POP
+    PUSH 0
+    RET
+    ";
+    assert_eq!(run(generator, false).unwrap(), &[6, 2]);
+
+    let mut virtual_machine = VirtualMachine::new(compile_assembly(generator));
+    assert_eq!(virtual_machine.run_bytecode().unwrap(), &[6, 2]);
+
+    // The values that fibers pass in turn are ignored, and items go through register 1, which the consumer empties.
+    let producer_consumer = "
+    SPAWN producer POP
+    SPAWN consumer POP
+    wait:
+    PUSH 0 YIELD POP
+    PUSH 1 FIBERSTATUS PUSH 2 NE JNZ wait
+    LOAD 2
+    RET
+    producer:
+    POP
+    PUSH 1 STORE 0
+    produce:
+    LOAD 0 STORE 1
+    PUSH 0 YIELD POP
+    LOAD 0 PUSH 1 ADD DUP STORE 0
+    PUSH 4 LT JNZ produce
+    PUSH 0
+    RET
+    consumer:
+    POP
+    consume:
+    LOAD 2 LOAD 1 ADD STORE 2
+    PUSH 0 STORE 1
+    PUSH 0 YIELD POP
+    JMP consume
+    ";
+    assert_eq!(run(producer_consumer, true).unwrap(), &[6]);
+    assert!(matches!(
+        run(producer_consumer, false),
+        Err(VmError::NoFiberToYieldTo)
+    ));
+
+    assert_eq!(
+        run(
+            "SPAWN f PUSH 5 SWAP RESUME PUSH 0 FIBERSTATUS RET f: PUSH 2 MUL RET",
+            false
+        )
+        .unwrap(),
+        &[10, 1]
+    );
+    assert_eq!(run("PUSH 7 YIELD RET", true).unwrap(), &[7]);
+    assert!(matches!(
+        run("PUSH 0 PUSH 1 RESUME RET", false),
+        Err(VmError::InvalidFiber(1))
+    ));
+    assert!(matches!(
+        run("PUSH 0 PUSH 0 RESUME RET", false),
+        Err(VmError::FiberNotSuspended(0))
+    ));
+    assert!(matches!(
+        run(
+            "SPAWN f DUP PUSH 0 SWAP RESUME POP PUSH 0 SWAP RESUME RET f: RET",
+            false
+        ),
+        Err(VmError::FiberNotSuspended(1))
+    ));
+}
+
+#[test]
+fn test_snapshots() {
+    // The program stops at the first `RET` and continues after it, with its registers, fibers, handlers and memory.
+    let bytecode = compile_assembly(
+        "
+    .data
+    number: .byte 3
+    .text
+    PUSH 5 STORE 0
+    SPAWN double POP
+    TRY caught
+    PUSH 1
+    RET
+    LOAD 0 ADD
+    PUSH 0 PUSH 1 RESUME
+    ADD
+    PUSH number LOAD8 ADD
+    THROW
+    caught:
+    RET
+    double:
+    POP PUSH 10 YIELD
+    RET
+    ",
+    );
+
+    let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+    assert_eq!(virtual_machine.run().unwrap(), &[1]);
+    let snapshot = virtual_machine.snapshot();
+
+    let mut virtual_machine = VirtualMachine::from_snapshot(&snapshot).unwrap();
+    assert_eq!(virtual_machine.run().unwrap(), &[19]);
+
+    // A snapshot of a new virtual machine starts the program from the start.
+    let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+    virtual_machine.decode().unwrap();
+    let mut restored = VirtualMachine::new(bytecode.clone());
+    restored.restore(&virtual_machine.snapshot()).unwrap();
+    assert_eq!(restored.run().unwrap(), &[1]);
+
+    let mut virtual_machine = VirtualMachine::new(compile_assembly("PUSH 1 RET"));
+    assert!(matches!(
+        virtual_machine.restore(&snapshot),
+        Err(VmError::SnapshotMismatch)
+    ));
+
+    // The program counters of the byte-level interpreter are byte addresses, which `run` can't continue with.
+    let mut virtual_machine = VirtualMachine::new(bytecode.clone());
+    assert_eq!(virtual_machine.run_bytecode().unwrap(), &[1]);
+    let mut restored = VirtualMachine::new(bytecode.clone());
+    assert!(matches!(
+        restored.restore(&virtual_machine.snapshot()),
+        Err(VmError::ByteAddressSnapshot)
+    ));
+
+    let mut virtual_machine = VirtualMachine::new(bytecode);
+    for invalid in [
+        &snapshot[..snapshot.len() - 1],
+        &[0xFF, b'B', b'C', b'S', 9],
+    ] {
+        assert!(matches!(
+            virtual_machine.restore(invalid),
+            Err(VmError::InvalidSnapshot)
+        ));
+    }
+}
+
+#[test]
+fn test_run_for() {
+    let mut virtual_machine = VirtualMachine::new(compile_assembly("PUSH 1 PUSH 2 ADD RET"));
+    assert!(matches!(virtual_machine.run_for(0), RunState::Paused));
+    assert!(matches!(virtual_machine.run_for(2), RunState::Paused));
+    assert!(matches!(virtual_machine.run_for(1), RunState::Paused));
+    assert!(matches!(
+        virtual_machine.run_for(1),
+        RunState::Completed(&[3])
+    ));
+
+    // A loop that runs in slices gives the same result as in one run.
+    let source_code = "
+    PUSH 0 STORE 0
+    PUSH 100
+    loop:
+    DUP LOAD 0 ADD STORE 0
+    PUSH 1 SUB
+    DUP JNZ loop
+    LOAD 0
+    RET
+    ";
+    let mut virtual_machine = VirtualMachine::new(compile_assembly(source_code));
+    let mut slices = 1;
+
+    let result = loop {
+        match virtual_machine.run_for(7) {
+            RunState::Completed(result) => break result.to_vec(),
+            RunState::Paused => slices += 1,
+            RunState::Error(error) => panic!("{error}"),
+        }
+    };
+
+    assert!(slices > 100);
+    assert_eq!(result, &[0, 5050]);
+
+    let mut virtual_machine = VirtualMachine::new(compile_assembly("PUSH 1 PUSH 0 DIV RET"));
+    assert!(matches!(
+        virtual_machine.run_for(10),
+        RunState::Error(VmError::DivisionByZero)
+    ));
+
+    // An endless loop is stopped by another thread, and can be interrupted again when it continues.
+    let mut virtual_machine = VirtualMachine::new(compile_assembly("loop: JMP loop RET"));
+    let interrupt = virtual_machine.interrupt();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        interrupt.interrupt();
+    });
+
+    assert!(matches!(virtual_machine.run(), Err(VmError::Interrupted)));
+    thread.join().unwrap();
+
+    virtual_machine.interrupt().interrupt();
+    assert!(matches!(
+        virtual_machine.run_for(usize::MAX),
+        RunState::Paused
+    ));
+    assert!(matches!(virtual_machine.run_for(5), RunState::Paused));
+}
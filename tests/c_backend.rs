@@ -0,0 +1,104 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const COMPILER: &str = env!("CARGO_BIN_EXE_bytecode-compiler");
+
+/// Creates a directory for the generated files, and fails if there is no C compiler to build them.
+fn create_directory(name: &str) -> PathBuf {
+    Command::new("cc")
+        .arg("--version")
+        .output()
+        .expect("the C backend tests need `cc`");
+
+    let directory = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Compiles a program to C, builds it with `cc` and compares its output with the interpreter.
+fn compare_with_interpreter(path: &Path, directory: &Path) {
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+
+    // The compiler reports errors without failing, so its output is checked instead of its status.
+    let output = Command::new(COMPILER)
+        .args(["compile", "--target", "c"])
+        .arg(path)
+        .current_dir(directory)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{file_name}");
+
+    let source = directory.join(format!("{file_name}.c"));
+    assert!(source.exists(), "{file_name}");
+
+    let executable = directory.join(file_name.replace('.', "_"));
+    let status = Command::new("cc")
+        .arg(&source)
+        .arg("-o")
+        .arg(&executable)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{file_name}");
+
+    let expected = Command::new(COMPILER)
+        .arg("run")
+        .arg(path)
+        .output()
+        .unwrap();
+    let output = Command::new(&executable).output().unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&expected.stdout),
+        "{file_name}"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        String::from_utf8_lossy(&expected.stderr),
+        "{file_name}"
+    );
+}
+
+/// Compiles every example to C and compares it with the interpreter.
+#[test]
+#[ignore = "needs `cc`, run it with `cargo test -- --include-ignored`"]
+fn test_c_backend_matches_interpreter() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let directory = create_directory("c-backend-test");
+
+    for entry in std::fs::read_dir(examples).unwrap() {
+        let path = entry.unwrap().path();
+
+        let extension = path.extension().and_then(|extension| extension.to_str());
+
+        if matches!(extension, Some("code" | "expr" | "fs" | "lang")) {
+            compare_with_interpreter(&path, &directory);
+        }
+    }
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+/// Compiles programs that fail to C, and checks that they report the errors of the interpreter.
+#[test]
+#[ignore = "needs `cc`, run it with `cargo test -- --include-ignored`"]
+fn test_c_backend_runtime_errors() {
+    let directory = create_directory("c-backend-errors-test");
+
+    let programs = [
+        ("call_stack_overflow", "f: CALL f RET"),
+        ("division_by_zero", "PUSH 1 PUSH 0 DIV RET"),
+        ("no_value_in_stack", "ADD RET"),
+        ("uncaught_throw", "PUSH 3 CALL f RET f: THROW"),
+    ];
+
+    for (name, source_code) in programs {
+        let path = directory.join(format!("{name}.code"));
+        std::fs::write(&path, source_code).unwrap();
+        compare_with_interpreter(&path, &directory);
+    }
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}